@@ -1,11 +1,13 @@
 use crate::linker::{Instruction, LinkedTokenData};
 use crate::{lexer, linker, tokenizer};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt::{Display, Formatter};
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Clone, Copy, PartialEq, Debug)]
 pub enum DataType {
     INT,
+    SINT,
     PTR,
     BOOL,
 }
@@ -13,6 +15,7 @@ pub enum DataType {
 pub fn get_data_type_by_text(txt: &str) -> Option<DataType> {
     match txt {
         "int" => Some(DataType::INT),
+        "sint" => Some(DataType::SINT),
         "ptr" => Some(DataType::PTR),
         "bool" => Some(DataType::BOOL),
         _ => None,
@@ -36,6 +39,7 @@ struct Context {
     vars: Vec<TypedPos>,
     ptr: usize,
     outs: Vec<TypedPos>,
+    function: Option<lexer::Word>,
 }
 
 #[derive(Clone)]
@@ -44,8 +48,9 @@ struct Signature {
     outs: Vec<TypedPos>,
 }
 
-pub fn check_types(linker_context: &linker::LinkerContext, allowed_overflow: usize) {
+pub fn check_types(linker_context: &mut linker::LinkerContext, allowed_overflow: usize) {
     let mut visited_loops: HashMap<usize, Vec<TypedPos>> = HashMap::new();
+    let mut signed_ops: HashSet<usize> = HashSet::new();
     let mut function_signatures: HashMap<String, Signature> = HashMap::new();
     for (func_name, func_ref) in &linker_context.functions {
         let sig = Signature {
@@ -54,13 +59,33 @@ pub fn check_types(linker_context: &linker::LinkerContext, allowed_overflow: usi
         };
         function_signatures.insert(func_name.clone(), sig);
     }
+    for extern_ref in &linker_context.externs {
+        let sig = Signature {
+            ins: extern_ref.ins.clone(),
+            outs: extern_ref.outs.clone(),
+        };
+        function_signatures.insert(extern_ref.name.clone(), sig);
+    }
     let ops = &linker_context.result;
     let mut contexts: Vec<Context> = vec![Context {
         stack: vec![],
         vars: vec![],
         ptr: 0,
         outs: vec![],
+        function: None,
     }];
+    let mut function_refs: Vec<&linker::FunctionRef> = linker_context.functions.values().collect();
+    function_refs.sort_by_key(|x| std::cmp::Reverse(x.ptr));
+    for func_ref in function_refs {
+        //Every function body is checked on its own, starting with its declared inputs on the stack
+        contexts.push(Context {
+            stack: func_ref.ins.clone(),
+            vars: vec![],
+            ptr: func_ref.ptr + 1,
+            outs: func_ref.outs.clone(),
+            function: Some(ops[func_ref.ptr].word.clone()),
+        });
+    }
     while !&contexts.is_empty() {
         let ctx = contexts.last_mut().unwrap();
         if ctx.ptr >= ops.len() {
@@ -77,6 +102,13 @@ pub fn check_types(linker_context: &linker::LinkerContext, allowed_overflow: usi
                 });
                 ctx.ptr += 1;
             }
+            Instruction::PushSInt(_) => {
+                ctx.stack.push(TypedPos {
+                    word: op.word.clone(),
+                    typ: DataType::SINT,
+                });
+                ctx.ptr += 1;
+            }
             Instruction::PushPtr(_) | Instruction::PushMem(_) => {
                 ctx.stack.push(TypedPos {
                     word: op.word.clone(),
@@ -100,16 +132,26 @@ pub fn check_types(linker_context: &linker::LinkerContext, allowed_overflow: usi
                 match intrinsic {
                     tokenizer::Intrinsic::Dump => {
                         let a = check_arity(1, ctx, op);
-                        check_signature(&op, ctx, vec![Signature { ins: a, outs: vec![] }]);
+                        check_signature(op, ctx, vec![Signature { ins: a, outs: vec![] }]);
+                    }
+                    tokenizer::Intrinsic::Puts | tokenizer::Intrinsic::EPuts => {
+                        check_signature(
+                            op,
+                            ctx,
+                            vec![Signature {
+                                ins: vec![tp(&op.word, DataType::INT), tp(&op.word, DataType::PTR)],
+                                outs: vec![],
+                            }],
+                        );
                     }
                     tokenizer::Intrinsic::Drop => {
                         let a = check_arity(1, ctx, op);
-                        check_signature(&op, ctx, vec![Signature { ins: a, outs: vec![] }]);
+                        check_signature(op, ctx, vec![Signature { ins: a, outs: vec![] }]);
                     }
                     tokenizer::Intrinsic::Dup => {
                         let a = check_arity(1, ctx, op);
                         check_signature(
-                            &op,
+                            op,
                             ctx,
                             vec![Signature {
                                 ins: a.clone(),
@@ -120,7 +162,7 @@ pub fn check_types(linker_context: &linker::LinkerContext, allowed_overflow: usi
                     tokenizer::Intrinsic::Over => {
                         let ab = check_arity(2, ctx, op);
                         check_signature(
-                            &op,
+                            op,
                             ctx,
                             vec![Signature {
                                 ins: ab.clone(),
@@ -131,7 +173,7 @@ pub fn check_types(linker_context: &linker::LinkerContext, allowed_overflow: usi
                     tokenizer::Intrinsic::Swap => {
                         let ab = check_arity(2, ctx, op);
                         check_signature(
-                            &op,
+                            op,
                             ctx,
                             vec![Signature {
                                 ins: ab.clone(),
@@ -142,7 +184,7 @@ pub fn check_types(linker_context: &linker::LinkerContext, allowed_overflow: usi
                     tokenizer::Intrinsic::Rot => {
                         let abc = check_arity(3, ctx, op);
                         check_signature(
-                            &op,
+                            op,
                             ctx,
                             vec![Signature {
                                 ins: abc.clone(),
@@ -150,43 +192,46 @@ pub fn check_types(linker_context: &linker::LinkerContext, allowed_overflow: usi
                             }],
                         );
                     }
-                    tokenizer::Intrinsic::Add => {
-                        let a = check_arity(2, ctx, op);
-                        check_signature(
-                            &op,
-                            ctx,
-                            vec![Signature {
-                                ins: a,
-                                outs: vec![tp(&op.word, DataType::INT)],
-                            }],
-                        );
-                    }
-                    tokenizer::Intrinsic::Subtract => {
+                    tokenizer::Intrinsic::Add
+                    | tokenizer::Intrinsic::Subtract
+                    | tokenizer::Intrinsic::Multiply
+                    | tokenizer::Intrinsic::BitAnd
+                    | tokenizer::Intrinsic::BitOr
+                    | tokenizer::Intrinsic::BitXor => {
                         let a = check_arity(2, ctx, op);
+                        let typ = get_integer_result_type(op, &a);
+                        if typ == DataType::SINT {
+                            signed_ops.insert(op.self_ptr);
+                        }
                         check_signature(
-                            &op,
+                            op,
                             ctx,
                             vec![Signature {
                                 ins: a,
-                                outs: vec![tp(&op.word, DataType::INT)],
+                                outs: vec![tp(&op.word, typ)],
                             }],
                         );
                     }
-                    tokenizer::Intrinsic::Multiply => {
+                    tokenizer::Intrinsic::ShiftLeft => {
                         let a = check_arity(2, ctx, op);
+                        let typ = if a[0].typ == DataType::SINT { DataType::SINT } else { DataType::INT };
+                        if typ == DataType::SINT {
+                            signed_ops.insert(op.self_ptr);
+                        }
                         check_signature(
-                            &op,
+                            op,
                             ctx,
                             vec![Signature {
                                 ins: a,
-                                outs: vec![tp(&op.word, DataType::INT)],
+                                outs: vec![tp(&op.word, typ)],
                             }],
                         );
                     }
-                    tokenizer::Intrinsic::Divide => {
+                    tokenizer::Intrinsic::Divide | tokenizer::Intrinsic::Modulo | tokenizer::Intrinsic::ShiftRight => {
                         let a = check_arity(2, ctx, op);
+                        check_unsigned_operands(op, &a);
                         check_signature(
-                            &op,
+                            op,
                             ctx,
                             vec![Signature {
                                 ins: a,
@@ -194,65 +239,66 @@ pub fn check_types(linker_context: &linker::LinkerContext, allowed_overflow: usi
                             }],
                         );
                     }
-                    tokenizer::Intrinsic::Modulo => {
+                    tokenizer::Intrinsic::Less | tokenizer::Intrinsic::Greater | tokenizer::Intrinsic::LessOrEqual | tokenizer::Intrinsic::GreaterOrEqual => {
                         let a = check_arity(2, ctx, op);
+                        check_unsigned_operands(op, &a);
                         check_signature(
-                            &op,
+                            op,
                             ctx,
                             vec![Signature {
                                 ins: a,
-                                outs: vec![tp(&op.word, DataType::INT)],
+                                outs: vec![tp(&op.word, DataType::BOOL)],
                             }],
                         );
                     }
-                    tokenizer::Intrinsic::ShiftLeft => {
+                    tokenizer::Intrinsic::Equals | tokenizer::Intrinsic::NotEquals => {
                         let a = check_arity(2, ctx, op);
                         check_signature(
-                            &op,
+                            op,
                             ctx,
                             vec![Signature {
                                 ins: a,
-                                outs: vec![tp(&op.word, DataType::INT)],
+                                outs: vec![tp(&op.word, DataType::BOOL)],
                             }],
                         );
                     }
-                    tokenizer::Intrinsic::ShiftRight => {
-                        let a = check_arity(2, ctx, op);
+                    tokenizer::Intrinsic::DivideSigned | tokenizer::Intrinsic::ModuloSigned => {
                         check_signature(
-                            &op,
+                            op,
                             ctx,
                             vec![Signature {
-                                ins: a,
-                                outs: vec![tp(&op.word, DataType::INT)],
+                                ins: vec![tp(&op.word, DataType::SINT), tp(&op.word, DataType::SINT)],
+                                outs: vec![tp(&op.word, DataType::SINT)],
                             }],
                         );
                     }
-                    tokenizer::Intrinsic::BitAnd => {
-                        let a = check_arity(2, ctx, op);
+                    tokenizer::Intrinsic::ShiftRightSigned => {
                         check_signature(
-                            &op,
+                            op,
                             ctx,
                             vec![Signature {
-                                ins: a,
-                                outs: vec![tp(&op.word, DataType::INT)],
+                                ins: vec![tp(&op.word, DataType::SINT), tp(&op.word, DataType::INT)],
+                                outs: vec![tp(&op.word, DataType::SINT)],
                             }],
                         );
                     }
-                    tokenizer::Intrinsic::BitOr => {
-                        let a = check_arity(2, ctx, op);
+                    tokenizer::Intrinsic::LessSigned
+                    | tokenizer::Intrinsic::GreaterSigned
+                    | tokenizer::Intrinsic::LessOrEqualSigned
+                    | tokenizer::Intrinsic::GreaterOrEqualSigned => {
                         check_signature(
-                            &op,
+                            op,
                             ctx,
                             vec![Signature {
-                                ins: a,
-                                outs: vec![tp(&op.word, DataType::INT)],
+                                ins: vec![tp(&op.word, DataType::SINT), tp(&op.word, DataType::SINT)],
+                                outs: vec![tp(&op.word, DataType::BOOL)],
                             }],
                         );
                     }
-                    tokenizer::Intrinsic::BitXor => {
-                        let a = check_arity(2, ctx, op);
+                    tokenizer::Intrinsic::CastInt => {
+                        let a = check_arity(1, ctx, op);
                         check_signature(
-                            &op,
+                            op,
                             ctx,
                             vec![Signature {
                                 ins: a,
@@ -260,88 +306,94 @@ pub fn check_types(linker_context: &linker::LinkerContext, allowed_overflow: usi
                             }],
                         );
                     }
-                    tokenizer::Intrinsic::Equals => {
-                        let a = check_arity(2, ctx, op);
+                    tokenizer::Intrinsic::CastSInt => {
+                        let a = check_arity(1, ctx, op);
                         check_signature(
-                            &op,
+                            op,
                             ctx,
                             vec![Signature {
                                 ins: a,
-                                outs: vec![tp(&op.word, DataType::BOOL)],
+                                outs: vec![tp(&op.word, DataType::SINT)],
                             }],
                         );
                     }
-                    tokenizer::Intrinsic::NotEquals => {
-                        let a = check_arity(2, ctx, op);
+                    tokenizer::Intrinsic::CastPtr => {
+                        let a = check_arity(1, ctx, op);
                         check_signature(
-                            &op,
+                            op,
                             ctx,
                             vec![Signature {
                                 ins: a,
-                                outs: vec![tp(&op.word, DataType::BOOL)],
+                                outs: vec![tp(&op.word, DataType::PTR)],
                             }],
                         );
                     }
-                    tokenizer::Intrinsic::Less => {
-                        let a = check_arity(2, ctx, op);
+                    tokenizer::Intrinsic::Store8 | tokenizer::Intrinsic::Store16 | tokenizer::Intrinsic::Store32 | tokenizer::Intrinsic::Store64 => {
                         check_signature(
-                            &op,
+                            op,
                             ctx,
                             vec![Signature {
-                                ins: a,
-                                outs: vec![tp(&op.word, DataType::BOOL)],
+                                ins: vec![tp(&op.word, DataType::INT), tp(&op.word, DataType::PTR)],
+                                outs: vec![],
                             }],
                         );
                     }
-                    tokenizer::Intrinsic::Greater => {
-                        let a = check_arity(2, ctx, op);
+                    tokenizer::Intrinsic::Load8 | tokenizer::Intrinsic::Load16 | tokenizer::Intrinsic::Load32 | tokenizer::Intrinsic::Load64 => {
                         check_signature(
-                            &op,
+                            op,
                             ctx,
                             vec![Signature {
-                                ins: a,
-                                outs: vec![tp(&op.word, DataType::BOOL)],
+                                ins: vec![tp(&op.word, DataType::PTR)],
+                                outs: vec![tp(&op.word, DataType::INT)],
                             }],
                         );
                     }
-                    tokenizer::Intrinsic::LessOrEqual => {
-                        let a = check_arity(2, ctx, op);
+                    tokenizer::Intrinsic::Exit => {
                         check_signature(
-                            &op,
+                            op,
                             ctx,
                             vec![Signature {
-                                ins: a,
-                                outs: vec![tp(&op.word, DataType::BOOL)],
+                                ins: vec![tp(&op.word, DataType::INT)],
+                                outs: vec![],
                             }],
                         );
                     }
-                    tokenizer::Intrinsic::GreaterOrEqual => {
-                        let a = check_arity(2, ctx, op);
+                    tokenizer::Intrinsic::Argc => {
                         check_signature(
-                            &op,
+                            op,
                             ctx,
                             vec![Signature {
-                                ins: a,
-                                outs: vec![tp(&op.word, DataType::BOOL)],
+                                ins: vec![],
+                                outs: vec![tp(&op.word, DataType::INT)],
                             }],
                         );
                     }
-                    tokenizer::Intrinsic::Store8 | tokenizer::Intrinsic::Store16 | tokenizer::Intrinsic::Store32 | tokenizer::Intrinsic::Store64 => {
+                    tokenizer::Intrinsic::Argv | tokenizer::Intrinsic::Envp => {
                         check_signature(
-                            &op,
+                            op,
                             ctx,
                             vec![Signature {
-                                ins: vec![tp(&op.word, DataType::INT), tp(&op.word, DataType::PTR)],
-                                outs: vec![],
+                                ins: vec![],
+                                outs: vec![tp(&op.word, DataType::PTR)],
                             }],
                         );
                     }
-                    tokenizer::Intrinsic::Load8 | tokenizer::Intrinsic::Load16 | tokenizer::Intrinsic::Load32 | tokenizer::Intrinsic::Load64 => {
+                    tokenizer::Intrinsic::Syscall0
+                    | tokenizer::Intrinsic::Syscall1
+                    | tokenizer::Intrinsic::Syscall2
+                    | tokenizer::Intrinsic::Syscall3
+                    | tokenizer::Intrinsic::Syscall4
+                    | tokenizer::Intrinsic::Syscall5
+                    | tokenizer::Intrinsic::Syscall6 => {
+                        //Arguments may be of any type, only the syscall number on top has to be an int
+                        let mut a = check_arity(tokenizer::get_syscall_arg_count(intrinsic) + 1, ctx, op);
+                        a.pop();
+                        a.push(tp(&op.word, DataType::INT));
                         check_signature(
-                            &op,
+                            op,
                             ctx,
                             vec![Signature {
-                                ins: vec![tp(&op.word, DataType::PTR)],
+                                ins: a,
                                 outs: vec![tp(&op.word, DataType::INT)],
                             }],
                         );
@@ -356,10 +408,10 @@ pub fn check_types(linker_context: &linker::LinkerContext, allowed_overflow: usi
                     std::process::exit(1);
                 }
             },
-            Instruction::Call => match function_signatures.get(&op.word.txt) {
+            Instruction::Call | Instruction::CallExtern => match function_signatures.get(&op.word.txt) {
                 None => {}
                 Some(sig) => {
-                    check_signature(&op, ctx, vec![sig.clone()]);
+                    check_signature(op, ctx, vec![sig.clone()]);
                     ctx.ptr += 1;
                 }
             },
@@ -409,7 +461,7 @@ pub fn check_types(linker_context: &linker::LinkerContext, allowed_overflow: usi
             },
             Instruction::JumpNeq => {
                 check_signature(
-                    &op,
+                    op,
                     ctx,
                     vec![Signature {
                         ins: vec![tp(&op.word, DataType::BOOL)],
@@ -424,6 +476,7 @@ pub fn check_types(linker_context: &linker::LinkerContext, allowed_overflow: usi
                             vars: ctx.vars.clone(),
                             ptr,
                             outs: ctx.outs.clone(),
+                            function: ctx.function.clone(),
                         };
                         contexts.push(new_ctx);
                         continue;
@@ -443,15 +496,15 @@ pub fn check_types(linker_context: &linker::LinkerContext, allowed_overflow: usi
             },
             Instruction::Do => {
                 check_signature(
-                    &op,
+                    op,
                     ctx,
                     vec![Signature {
                         ins: vec![tp(&op.word, DataType::BOOL)],
                         outs: vec![],
                     }],
                 );
-                if !visited_loops.contains_key(&ctx.ptr) {
-                    visited_loops.insert(ctx.ptr, ctx.stack.clone());
+                if let std::collections::hash_map::Entry::Vacant(e) = visited_loops.entry(ctx.ptr) {
+                    e.insert(ctx.stack.clone());
                     ctx.ptr += 1;
                     let jump_ptr = match op.data {
                         LinkedTokenData::JumpAddr(ptr) => ptr,
@@ -465,12 +518,13 @@ pub fn check_types(linker_context: &linker::LinkerContext, allowed_overflow: usi
                         vars: ctx.vars.clone(),
                         ptr: jump_ptr,
                         outs: ctx.outs.clone(),
+                        function: ctx.function.clone(),
                     };
                     contexts.push(new_ctx);
                     continue;
                 } else {
-                    let expected_types: Vec<DataType> = visited_loops.get(&ctx.ptr).unwrap().iter().map(|x| x.typ.clone()).collect();
-                    let actual_types: Vec<DataType> = ctx.stack.iter().map(|x| x.typ.clone()).collect();
+                    let expected_types: Vec<DataType> = visited_loops.get(&ctx.ptr).unwrap().iter().map(|x| x.typ).collect();
+                    let actual_types: Vec<DataType> = ctx.stack.iter().map(|x| x.typ).collect();
                     if expected_types != actual_types {
                         eprintln!("{}: ERROR: Loops are not allowed to modify the stack between iterations!", op.word);
                         eprintln!("{}: INFO : Stack before loop:", op.word);
@@ -490,6 +544,7 @@ pub fn check_types(linker_context: &linker::LinkerContext, allowed_overflow: usi
             }
         }
     }
+    linker_context.signed_ops = signed_ops;
 }
 
 fn check_arity(count: usize, ctx: &mut Context, op: &linker::LinkedToken) -> Vec<TypedPos> {
@@ -503,11 +558,36 @@ fn check_arity(count: usize, ctx: &mut Context, op: &linker::LinkedToken) -> Vec
         );
         std::process::exit(1);
     }
-    let mut result: Vec<TypedPos> = vec![];
-    for i in 0..count {
-        result.push(ctx.stack[ctx.stack.len() - 1 - i].clone());
+    ctx.stack[ctx.stack.len() - count..].to_vec()
+}
+
+fn get_integer_result_type(op: &linker::LinkedToken, args: &[TypedPos]) -> DataType {
+    let signed_count = args.iter().filter(|x| x.typ == DataType::SINT).count();
+    if signed_count == 0 {
+        return DataType::INT;
+    }
+    if signed_count != args.len() {
+        eprintln!(
+            "{}: ERROR: Cannot mix signed and unsigned values in '{}'. Use 'cast(int)' or 'cast(sint)' to convert explicitly.",
+            op.word, op.word.txt
+        );
+        for arg in args {
+            eprintln!("{}: INFO: Argument of type '{}' was found here", arg.word, arg.typ);
+        }
+        std::process::exit(1);
+    }
+    DataType::SINT
+}
+
+fn check_unsigned_operands(op: &linker::LinkedToken, args: &[TypedPos]) {
+    if let Some(arg) = args.iter().find(|x| x.typ == DataType::SINT) {
+        eprintln!(
+            "{}: ERROR: '{}' operates on unsigned values. Use 's{}' for signed values or 'cast(int)' to convert explicitly.",
+            op.word, op.word.txt, op.word.txt
+        );
+        eprintln!("{}: INFO: Argument of type '{}' was found here", arg.word, arg.typ);
+        std::process::exit(1);
     }
-    result
 }
 
 fn check_signature(op: &linker::LinkedToken, ctx: &mut Context, sigs: Vec<Signature>) {
@@ -534,8 +614,7 @@ fn check_signature(op: &linker::LinkedToken, ctx: &mut Context, sigs: Vec<Signat
         if stack.len() < inputs.len() {
             eprintln!("{}: ERROR: Not enough arguments were provided for '{}' '{}'.", op.word, op.instruction, op.word.txt);
             eprintln!("{}: INFO: Missing arguments:", op.word);
-            while !inputs.is_empty() {
-                let missing = inputs.pop().unwrap();
+            while let Some(missing) = inputs.pop() {
                 eprintln!("{}: INFO: {}", missing.word, missing.typ);
             }
             exit = true;
@@ -562,26 +641,33 @@ fn check_outputs(ctx: &mut Context, allowed_overflow: usize) {
         if expected.typ != actual.typ {
             eprintln!("{}: ERROR: Unexpected type '{}' placed on the stack.", actual.word, actual.typ);
             eprintln!("{}: INFO: Expected type was '{}' was found here", expected.word, expected.typ);
+            print_function_info(ctx);
             std::process::exit(1);
         }
     }
     if ctx.stack.len() - allowed_overflow > ctx.outs.len() {
         eprintln!("{}: ERROR: Found unhandled data on the stack.", ctx.stack.last().unwrap().word);
-        while !ctx.stack.is_empty() {
-            let unexpected = ctx.stack.pop().unwrap();
+        while let Some(unexpected) = ctx.stack.pop() {
             eprintln!("{}: INFO: Type '{}'", unexpected.word, unexpected.typ);
         }
+        print_function_info(ctx);
         std::process::exit(1);
     } else if ctx.stack.len() < ctx.outs.len() {
         eprintln!("{}: ERROR: Missing expected data on the stack:", ctx.outs.last().unwrap().word);
-        while !ctx.outs.is_empty() {
-            let missing = ctx.outs.pop().unwrap();
+        while let Some(missing) = ctx.outs.pop() {
             eprintln!("{}: INFO: Type '{}'", missing.word, missing.typ);
         }
+        print_function_info(ctx);
         std::process::exit(1);
     }
 }
 
+fn print_function_info(ctx: &Context) {
+    if let Some(function_word) = &ctx.function {
+        eprintln!("{}: INFO: Function '{}' is declared here", function_word, function_word.txt);
+    }
+}
+
 fn tp(word: &lexer::Word, typ: DataType) -> TypedPos {
     TypedPos { word: word.clone(), typ }
 }
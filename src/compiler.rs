@@ -1,12 +1,228 @@
-use crate::{compiler_asm_elf64, compiler_asm_win64, compiler_string, linker};
+use crate::checker::DataType;
+use crate::linker::{Instruction, LinkedToken, LinkedTokenData, LinkerContext};
+use crate::{
+    add_or_replace_extension, compiler_asm_aarch64, compiler_asm_elf64, compiler_asm_win64, compiler_c, compiler_elf64, compiler_gas_elf64, compiler_llvm, compiler_string,
+    compiler_wasm,
+};
 
-pub const KNOWN_COMPILERS: [&str; 3] = ["string", "asm-elf64", "asm-win64"];
+/// Size of the buffer used by native runtimes for standard output, it is flushed when full and before exiting.
+pub const OUT_BUFFER_SIZE: usize = 4096;
 
-pub fn compile(id: &str, input_path: &str, ctx: &linker::LinkerContext) {
-    match id {
-        "string" => compiler_string::process_program(input_path, ctx),
-        "asm-elf64" => compiler_asm_elf64::process_program(input_path, ctx),
-        "asm-win64" => compiler_asm_win64::process_program(input_path, ctx),
-        _ => panic!(),
+/// Every backend, in the order they are listed in the usage.
+pub const BACKENDS: [&dyn Compiler; 9] = [
+    &compiler_string::Listing,
+    &compiler_asm_elf64::AsmElf64,
+    &compiler_asm_win64::AsmWin64,
+    &compiler_asm_aarch64::AsmAarch64,
+    &compiler_elf64::Elf64,
+    &compiler_gas_elf64::GasElf64,
+    &compiler_c::C,
+    &compiler_llvm::Llvm,
+    &compiler_wasm::Wasm,
+];
+
+pub struct CompileOptions {
+    /// Also write the generated assembly of backends which do not need it to build the executable
+    pub emit_asm: bool,
+    /// Insert runtime checks which report the failing word instead of corrupting memory or crashing
+    pub checked: bool,
+    /// Object files and libraries linked into programs calling external functions, passed to the C compiler as is
+    pub link: Vec<String>,
+    /// Write an object exporting the `export function` definitions to C, along with a header declaring them
+    pub lib: bool,
+}
+
+/// A target of the `compile` command, selected with `--use=<id>`.
+///
+/// The output is built by calling the hooks in order: `prologue` starts it with the data of the program and its entry point,
+/// `runtime` adds the helper routines, `lower` is called for every linked instruction and `epilogue` ends the program for
+/// when execution falls off the last instruction. `finish` then writes the output, assembling and linking it if needed.
+pub trait Backend {
+    type Output;
+
+    fn id(&self) -> &'static str;
+
+    fn prologue(&self, file_path: &str, ctx: &LinkerContext, options: &CompileOptions) -> Self::Output;
+
+    fn runtime(&self, _out: &mut Self::Output) {}
+
+    fn lower(&self, out: &mut Self::Output, op: &LinkedToken);
+
+    fn epilogue(&self, _out: &mut Self::Output, _ctx: &LinkerContext) {}
+
+    fn finish(&self, file_path: &str, out: Self::Output, options: &CompileOptions);
+
+    /// Whether the `test` command can run the output of this backend
+    fn testable(&self) -> bool {
+        true
+    }
+
+    /// Whether the output can be compiled with runtime checks using `--checked`
+    fn checkable(&self) -> bool {
+        false
+    }
+
+    /// Whether the output can be a library linked into other programs using `--lib`
+    fn linkable(&self) -> bool {
+        false
+    }
+
+    /// The command running a compiled test program, or why it can not be run on this host
+    fn run_command(&self, file_path: &str) -> Result<std::process::Command, String> {
+        Ok(std::process::Command::new(add_or_replace_extension(file_path, "")))
+    }
+}
+
+/// Object safe view of a [`Backend`], so backends with different outputs can be kept in [`BACKENDS`].
+pub trait Compiler {
+    fn id(&self) -> &'static str;
+
+    fn compile(&self, file_path: &str, ctx: &LinkerContext, options: &CompileOptions);
+
+    fn testable(&self) -> bool;
+
+    fn checkable(&self) -> bool;
+
+    fn linkable(&self) -> bool;
+
+    fn run_command(&self, file_path: &str) -> Result<std::process::Command, String>;
+}
+
+impl<B: Backend> Compiler for B {
+    fn id(&self) -> &'static str {
+        Backend::id(self)
+    }
+
+    fn compile(&self, file_path: &str, ctx: &LinkerContext, options: &CompileOptions) {
+        let mut out = self.prologue(file_path, ctx, options);
+        self.runtime(&mut out);
+        for op in &ctx.result {
+            self.lower(&mut out, op);
+        }
+        self.epilogue(&mut out, ctx);
+        self.finish(file_path, out, options);
+        if options.lib {
+            write_c_header(file_path, ctx);
+        }
+    }
+
+    fn testable(&self) -> bool {
+        Backend::testable(self)
+    }
+
+    fn checkable(&self) -> bool {
+        Backend::checkable(self)
+    }
+
+    fn linkable(&self) -> bool {
+        Backend::linkable(self)
+    }
+
+    fn run_command(&self, file_path: &str) -> Result<std::process::Command, String> {
+        Backend::run_command(self, file_path)
+    }
+}
+
+pub fn find_backend(id: &str) -> Option<&'static dyn Compiler> {
+    BACKENDS.iter().find(|x| x.id() == id).copied()
+}
+
+/// The bytes a string literal is stored as, followed by the zero byte which is not part of its length.
+pub fn string_bytes(val: &str) -> Vec<u8> {
+    let mut bytes = val.as_bytes().to_vec();
+    bytes.push(0);
+    bytes
+}
+
+/// Assembles `file_path` with the extension `asm_extension` into an object, running `assembler` with `args` followed by
+/// the output and input files.
+pub fn compile_obj_file(file_path: &str, asm_extension: &str, assembler: &str, args: &[&str]) {
+    let asm_file_path = add_or_replace_extension(file_path, asm_extension);
+    let obj_file_path = add_or_replace_extension(file_path, "obj");
+    let cmd = std::process::Command::new(assembler)
+        .args(args)
+        .arg("-o")
+        .arg(&obj_file_path)
+        .arg(asm_file_path)
+        .output()
+        .unwrap_or_else(|err| {
+            eprintln!("ERROR: Could not compile assembly: {}", err);
+            std::process::exit(1);
+        });
+    if cmd.status.success() {
+        println!("SUCCESS: Written compiled assembly to: {}", obj_file_path);
+    } else {
+        eprintln!("ERROR: Could not compile assembly");
+        eprintln!("{}", String::from_utf8_lossy(&cmd.stderr));
+        std::process::exit(1);
+    }
+}
+
+/// Libraries are only entered through their exported functions, so everything outside of the functions would never run.
+pub fn check_library(ctx: &LinkerContext) {
+    let mut ptr = 0;
+    while ptr < ctx.result.len() {
+        match (&ctx.result[ptr].instruction, ctx.result[ptr].data, ctx.result.get(ptr + 1).map(|x| &x.instruction)) {
+            (Instruction::Jump, LinkedTokenData::JumpAddr(end_ptr), Some(Instruction::Function)) => ptr = end_ptr,
+            _ => {
+                eprintln!(
+                    "{}: ERROR: Libraries can only contain definitions, found '{}' outside of a function",
+                    ctx.result[ptr].word, ctx.result[ptr].word.txt
+                );
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+/// Writes a C header next to the library, declaring every exported function with the C types of its signature.
+fn write_c_header(file_path: &str, ctx: &LinkerContext) {
+    let output_file_path = add_or_replace_extension(file_path, "h");
+    let file_name = output_file_path.rsplit('/').next().unwrap();
+    let guard: String = file_name.chars().map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' }).collect();
+    let c_type = |typ: DataType| match typ {
+        DataType::INT => "uint64_t",
+        DataType::SINT => "int64_t",
+        DataType::PTR => "void *",
+        DataType::BOOL => "bool",
+    };
+
+    let mut functions: Vec<_> = ctx.functions.iter().filter(|(_, function)| function.export).collect();
+    functions.sort_by_key(|(_, function)| function.ptr);
+    let mut out: Vec<String> = vec![
+        format!("//Generated by feylon from {}", file_path),
+        format!("#ifndef FEYLON_{}", guard),
+        format!("#define FEYLON_{}", guard),
+        String::new(),
+        "#include <stdbool.h>".to_string(),
+        "#include <stdint.h>".to_string(),
+        String::new(),
+        "#ifdef __cplusplus".to_string(),
+        "extern \"C\" {".to_string(),
+        "#endif".to_string(),
+        String::new(),
+    ];
+    for (name, function) in functions {
+        let ins: Vec<&str> = function.ins.iter().map(|x| c_type(x.typ)).collect();
+        out.push(format!(
+            "{} {}({});",
+            function.outs.first().map_or("void", |x| c_type(x.typ)),
+            name,
+            if ins.is_empty() { "void".to_string() } else { ins.join(", ") }
+        ));
     }
+    out.extend([
+        String::new(),
+        "#ifdef __cplusplus".to_string(),
+        "}".to_string(),
+        "#endif".to_string(),
+        String::new(),
+        "#endif".to_string(),
+        String::new(),
+    ]);
+    std::fs::write(&output_file_path, out.join("\n")).unwrap_or_else(|e| {
+        eprintln!("ERROR: Could not write header: {}", e);
+        std::process::exit(1);
+    });
+    println!("SUCCESS: Written header to: {}", output_file_path);
 }
@@ -0,0 +1,463 @@
+use crate::compiler::{Backend, CompileOptions, OUT_BUFFER_SIZE};
+use crate::linker::{Instruction, LinkedToken, LinkerContext};
+use crate::simulator::RUNTIME_ERROR_EXIT_CODE;
+use crate::tokenizer::Intrinsic;
+use crate::{add_or_replace_extension, compiler, compiler_string, linker, tokenizer};
+use std::io::Write;
+
+const SYSCALL_ARG_REGISTERS: [&str; 6] = ["x0", "x1", "x2", "x3", "x4", "x5"];
+const SYS_WRITE: u64 = 64;
+const SYS_EXIT: u64 = 93;
+const DIVISION_BY_ZERO_MESSAGE: &str = "ERROR: Division by zero";
+
+//The data stack grows down from the initial stack pointer in x28, return addresses and vars live on the callstack in x27.
+//The runtime keeps its scratch space below the data stack and saves registers on the callstack, so sp itself is never moved
+pub struct AsmAarch64;
+
+impl Backend for AsmAarch64 {
+    type Output = std::fs::File;
+
+    fn id(&self) -> &'static str {
+        "asm-aarch64"
+    }
+
+    fn prologue(&self, file_path: &str, ctx: &LinkerContext, _options: &CompileOptions) -> std::fs::File {
+        let output_file_path = add_or_replace_extension(file_path, "s");
+        let mut out_file = std::fs::File::create(&output_file_path).unwrap_or_else(|e| {
+            eprintln!("ERROR: Could not open file for compilation: {}", e);
+            std::process::exit(1);
+        });
+        writeln!(&mut out_file, "    .global _start").unwrap();
+        writeln!(&mut out_file, "    .bss").unwrap();
+        writeln!(&mut out_file, "    .balign 16").unwrap();
+        writeln!(&mut out_file, "args_ptr: .skip 8").unwrap();
+        writeln!(&mut out_file, "out_len: .skip 8").unwrap();
+        writeln!(&mut out_file, "out_buf: .skip {}", OUT_BUFFER_SIZE).unwrap();
+        writeln!(&mut out_file, "callstack: .skip 65536").unwrap();
+        writeln!(&mut out_file, "callstack_top:").unwrap();
+        writeln!(&mut out_file, "mem: .skip {}", ctx.mem_size).unwrap();
+        writeln!(&mut out_file, "    .section .rodata").unwrap();
+        for (index, val) in ctx.strings.iter().enumerate() {
+            write_string_literal(&mut out_file, index, val);
+        }
+        writeln!(&mut out_file, "division_by_zero_message: .ascii \"{}\\n\"", DIVISION_BY_ZERO_MESSAGE).unwrap();
+        writeln!(&mut out_file, "    .text").unwrap();
+        writeln!(&mut out_file, "    .balign 4").unwrap();
+        writeln!(&mut out_file, "_start:").unwrap();
+        writeln!(&mut out_file, "    mov x28, sp").unwrap();
+        writeln!(&mut out_file, "    adrp x0, args_ptr").unwrap();
+        writeln!(&mut out_file, "    str x28, [x0, :lo12:args_ptr]").unwrap();
+        writeln!(&mut out_file, "    adrp x27, callstack_top").unwrap();
+        writeln!(&mut out_file, "    add x27, x27, :lo12:callstack_top").unwrap();
+        writeln!(&mut out_file, "    b addr_0").unwrap();
+        out_file
+    }
+
+    fn runtime(&self, out_file: &mut std::fs::File) {
+        writeln!(out_file, "print:").unwrap();
+        writeln!(out_file, "    sub x5, x28, #1").unwrap();
+        writeln!(out_file, "    mov x2, #10").unwrap();
+        writeln!(out_file, "    strb w2, [x5]").unwrap();
+        writeln!(out_file, "1:").unwrap();
+        writeln!(out_file, "    udiv x3, x0, x2").unwrap();
+        writeln!(out_file, "    msub x4, x3, x2, x0").unwrap();
+        writeln!(out_file, "    add x4, x4, #48").unwrap();
+        writeln!(out_file, "    strb w4, [x5, #-1]!").unwrap();
+        writeln!(out_file, "    mov x0, x3").unwrap();
+        writeln!(out_file, "    cbnz x0, 1b").unwrap();
+        writeln!(out_file, "    mov x1, x5").unwrap();
+        writeln!(out_file, "    sub x2, x28, x5").unwrap();
+        writeln!(out_file, "    str x30, [x27, #-8]!").unwrap();
+        writeln!(out_file, "    bl out_write").unwrap();
+        writeln!(out_file, "    ldr x30, [x27], #8").unwrap();
+        writeln!(out_file, "    ret").unwrap();
+        //Standard output is buffered, out_write appends x2 bytes at x1 to the buffer and out_flush writes it out
+        writeln!(out_file, "out_write:").unwrap();
+        writeln!(out_file, "    adrp x9, out_len").unwrap();
+        writeln!(out_file, "    ldr x10, [x9, :lo12:out_len]").unwrap();
+        writeln!(out_file, "    add x10, x10, x2").unwrap();
+        writeln!(out_file, "    mov x11, #{}", OUT_BUFFER_SIZE).unwrap();
+        writeln!(out_file, "    cmp x10, x11").unwrap();
+        writeln!(out_file, "    b.ls 2f").unwrap();
+        writeln!(out_file, "    stp x1, x2, [x27, #-16]!").unwrap();
+        writeln!(out_file, "    str x30, [x27, #-8]!").unwrap();
+        writeln!(out_file, "    bl out_flush").unwrap();
+        writeln!(out_file, "    ldr x30, [x27], #8").unwrap();
+        writeln!(out_file, "    ldp x1, x2, [x27], #16").unwrap();
+        writeln!(out_file, "    mov x11, #{}", OUT_BUFFER_SIZE).unwrap();
+        writeln!(out_file, "    cmp x2, x11").unwrap();
+        writeln!(out_file, "    b.ls 2f").unwrap();
+        writeln!(out_file, "    mov x0, #1").unwrap();
+        writeln!(out_file, "    mov x8, #{}", SYS_WRITE).unwrap();
+        writeln!(out_file, "    svc #0").unwrap();
+        writeln!(out_file, "    ret").unwrap();
+        writeln!(out_file, "2:").unwrap();
+        writeln!(out_file, "    adrp x9, out_len").unwrap();
+        writeln!(out_file, "    ldr x10, [x9, :lo12:out_len]").unwrap();
+        writeln!(out_file, "    adrp x11, out_buf").unwrap();
+        writeln!(out_file, "    add x11, x11, :lo12:out_buf").unwrap();
+        writeln!(out_file, "    add x11, x11, x10").unwrap();
+        writeln!(out_file, "    add x10, x10, x2").unwrap();
+        writeln!(out_file, "    str x10, [x9, :lo12:out_len]").unwrap();
+        writeln!(out_file, "    cbz x2, 4f").unwrap();
+        writeln!(out_file, "3:").unwrap();
+        writeln!(out_file, "    ldrb w12, [x1], #1").unwrap();
+        writeln!(out_file, "    strb w12, [x11], #1").unwrap();
+        writeln!(out_file, "    subs x2, x2, #1").unwrap();
+        writeln!(out_file, "    b.ne 3b").unwrap();
+        writeln!(out_file, "4:").unwrap();
+        writeln!(out_file, "    ret").unwrap();
+        writeln!(out_file, "out_flush:").unwrap();
+        writeln!(out_file, "    adrp x9, out_len").unwrap();
+        writeln!(out_file, "    ldr x2, [x9, :lo12:out_len]").unwrap();
+        writeln!(out_file, "    cbz x2, 1f").unwrap();
+        writeln!(out_file, "    str xzr, [x9, :lo12:out_len]").unwrap();
+        writeln!(out_file, "    mov x0, #1").unwrap();
+        writeln!(out_file, "    adrp x1, out_buf").unwrap();
+        writeln!(out_file, "    add x1, x1, :lo12:out_buf").unwrap();
+        writeln!(out_file, "    mov x8, #{}", SYS_WRITE).unwrap();
+        writeln!(out_file, "    svc #0").unwrap();
+        writeln!(out_file, "1:").unwrap();
+        writeln!(out_file, "    ret").unwrap();
+        //udiv and sdiv give 0 when dividing by zero, the error is reported with the exit code of the simulator instead
+        writeln!(out_file, "division_by_zero:").unwrap();
+        writeln!(out_file, "    bl out_flush").unwrap();
+        writeln!(out_file, "    mov x0, #2").unwrap();
+        writeln!(out_file, "    adrp x1, division_by_zero_message").unwrap();
+        writeln!(out_file, "    add x1, x1, :lo12:division_by_zero_message").unwrap();
+        writeln!(out_file, "    mov x2, #{}", DIVISION_BY_ZERO_MESSAGE.len() + 1).unwrap();
+        writeln!(out_file, "    mov x8, #{}", SYS_WRITE).unwrap();
+        writeln!(out_file, "    svc #0").unwrap();
+        writeln!(out_file, "    mov x0, #{}", RUNTIME_ERROR_EXIT_CODE).unwrap();
+        writeln!(out_file, "    mov x8, #{}", SYS_EXIT).unwrap();
+        writeln!(out_file, "    svc #0").unwrap();
+    }
+
+    fn lower(&self, out_file: &mut std::fs::File, op: &LinkedToken) {
+        writeln!(out_file, "addr_{}:    // {}", op.self_ptr, compiler_string::stringify_op(op)).unwrap();
+        match op.instruction {
+            Instruction::PushInt(x) => {
+                write_load_immediate(out_file, "x0", x);
+                write_push(out_file, "x0");
+            }
+            Instruction::PushSInt(x) => {
+                write_load_immediate(out_file, "x0", x as u64);
+                write_push(out_file, "x0");
+            }
+            Instruction::PushPtr(ptr) => {
+                write_load_immediate(out_file, "x0", ptr as u64);
+                write_push(out_file, "x0");
+            }
+            Instruction::PushMem(offset) => {
+                writeln!(out_file, "    adrp x0, mem+{}", offset).unwrap();
+                writeln!(out_file, "    add x0, x0, :lo12:mem+{}", offset).unwrap();
+                write_push(out_file, "x0");
+            }
+            Instruction::PushBool(x) => {
+                writeln!(out_file, "    mov x0, #{}", if x { 1 } else { 0 }).unwrap();
+                write_push(out_file, "x0");
+            }
+            Instruction::PushString(ref val) => match op.data {
+                linker::LinkedTokenData::Index(string_index) => {
+                    write_load_immediate(out_file, "x0", val.len() as u64);
+                    write_push(out_file, "x0");
+                    writeln!(out_file, "    adrp x0, str_{}", string_index).unwrap();
+                    writeln!(out_file, "    add x0, x0, :lo12:str_{}", string_index).unwrap();
+                    write_push(out_file, "x0");
+                }
+                _ => panic!(),
+            },
+            Instruction::Intrinsic(intrinsic) => match intrinsic {
+                Intrinsic::Dump => {
+                    write_pop(out_file, "x0");
+                    writeln!(out_file, "    bl print").unwrap();
+                }
+                Intrinsic::Puts => {
+                    write_pop(out_file, "x1");
+                    write_pop(out_file, "x2");
+                    writeln!(out_file, "    bl out_write").unwrap();
+                }
+                Intrinsic::EPuts => {
+                    writeln!(out_file, "    bl out_flush").unwrap();
+                    writeln!(out_file, "    mov x0, #2").unwrap();
+                    write_pop(out_file, "x1");
+                    write_pop(out_file, "x2");
+                    writeln!(out_file, "    mov x8, #{}", SYS_WRITE).unwrap();
+                    writeln!(out_file, "    svc #0").unwrap();
+                }
+                Intrinsic::Drop => {
+                    writeln!(out_file, "    add x28, x28, #8").unwrap();
+                }
+                Intrinsic::Dup => {
+                    writeln!(out_file, "    ldr x0, [x28]").unwrap();
+                    write_push(out_file, "x0");
+                }
+                Intrinsic::Over => {
+                    writeln!(out_file, "    ldr x0, [x28, #8]").unwrap();
+                    write_push(out_file, "x0");
+                }
+                Intrinsic::Swap => {
+                    writeln!(out_file, "    ldp x0, x1, [x28]").unwrap();
+                    writeln!(out_file, "    stp x1, x0, [x28]").unwrap();
+                }
+                Intrinsic::Rot => {
+                    writeln!(out_file, "    ldp x0, x1, [x28]").unwrap();
+                    writeln!(out_file, "    ldr x2, [x28, #16]").unwrap();
+                    writeln!(out_file, "    stp x2, x0, [x28]").unwrap();
+                    writeln!(out_file, "    str x1, [x28, #16]").unwrap();
+                }
+                Intrinsic::Add => write_binary(out_file, "add x1, x1, x0"),
+                Intrinsic::Subtract => write_binary(out_file, "sub x1, x1, x0"),
+                Intrinsic::Multiply => write_binary(out_file, "mul x1, x1, x0"),
+                Intrinsic::Divide => write_division(out_file, "udiv x1, x1, x0"),
+                Intrinsic::DivideSigned => write_division(out_file, "sdiv x1, x1, x0"),
+                Intrinsic::Modulo => {
+                    write_division(out_file, "udiv x2, x1, x0\n    msub x1, x2, x0, x1");
+                }
+                Intrinsic::ModuloSigned => {
+                    write_division(out_file, "sdiv x2, x1, x0\n    msub x1, x2, x0, x1");
+                }
+                Intrinsic::ShiftLeft => write_binary(out_file, "lsl x1, x1, x0"),
+                Intrinsic::ShiftRight => write_binary(out_file, "lsr x1, x1, x0"),
+                Intrinsic::ShiftRightSigned => write_binary(out_file, "asr x1, x1, x0"),
+                Intrinsic::BitAnd => write_binary(out_file, "and x1, x1, x0"),
+                Intrinsic::BitOr => write_binary(out_file, "orr x1, x1, x0"),
+                Intrinsic::BitXor => write_binary(out_file, "eor x1, x1, x0"),
+                Intrinsic::Equals => write_compare(out_file, "eq"),
+                Intrinsic::NotEquals => write_compare(out_file, "ne"),
+                Intrinsic::Less => write_compare(out_file, "lo"),
+                Intrinsic::Greater => write_compare(out_file, "hi"),
+                Intrinsic::LessOrEqual => write_compare(out_file, "ls"),
+                Intrinsic::GreaterOrEqual => write_compare(out_file, "hs"),
+                Intrinsic::LessSigned => write_compare(out_file, "lt"),
+                Intrinsic::GreaterSigned => write_compare(out_file, "gt"),
+                Intrinsic::LessOrEqualSigned => write_compare(out_file, "le"),
+                Intrinsic::GreaterOrEqualSigned => write_compare(out_file, "ge"),
+                Intrinsic::CastInt | Intrinsic::CastSInt | Intrinsic::CastPtr => {}
+                Intrinsic::Store8 => write_store(out_file, "strb w1"),
+                Intrinsic::Store16 => write_store(out_file, "strh w1"),
+                Intrinsic::Store32 => write_store(out_file, "str w1"),
+                Intrinsic::Store64 => write_store(out_file, "str x1"),
+                Intrinsic::Load8 => write_load(out_file, "ldrb w1"),
+                Intrinsic::Load16 => write_load(out_file, "ldrh w1"),
+                Intrinsic::Load32 => write_load(out_file, "ldr w1"),
+                Intrinsic::Load64 => write_load(out_file, "ldr x1"),
+                Intrinsic::Exit => {
+                    writeln!(out_file, "    bl out_flush").unwrap();
+                    write_pop(out_file, "x0");
+                    writeln!(out_file, "    mov x8, #{}", SYS_EXIT).unwrap();
+                    writeln!(out_file, "    svc #0").unwrap();
+                }
+                Intrinsic::Argc => {
+                    writeln!(out_file, "    adrp x0, args_ptr").unwrap();
+                    writeln!(out_file, "    ldr x0, [x0, :lo12:args_ptr]").unwrap();
+                    writeln!(out_file, "    ldr x0, [x0]").unwrap();
+                    write_push(out_file, "x0");
+                }
+                Intrinsic::Argv => {
+                    writeln!(out_file, "    adrp x0, args_ptr").unwrap();
+                    writeln!(out_file, "    ldr x0, [x0, :lo12:args_ptr]").unwrap();
+                    writeln!(out_file, "    add x0, x0, #8").unwrap();
+                    write_push(out_file, "x0");
+                }
+                Intrinsic::Envp => {
+                    writeln!(out_file, "    adrp x0, args_ptr").unwrap();
+                    writeln!(out_file, "    ldr x0, [x0, :lo12:args_ptr]").unwrap();
+                    writeln!(out_file, "    ldr x1, [x0]").unwrap();
+                    writeln!(out_file, "    add x0, x0, x1, lsl #3").unwrap();
+                    writeln!(out_file, "    add x0, x0, #16").unwrap();
+                    write_push(out_file, "x0");
+                }
+                Intrinsic::Syscall0 | Intrinsic::Syscall1 | Intrinsic::Syscall2 | Intrinsic::Syscall3 | Intrinsic::Syscall4 | Intrinsic::Syscall5 | Intrinsic::Syscall6 => {
+                    writeln!(out_file, "    bl out_flush").unwrap();
+                    write_pop(out_file, "x8");
+                    for reg in SYSCALL_ARG_REGISTERS.iter().take(tokenizer::get_syscall_arg_count(&intrinsic)) {
+                        write_pop(out_file, reg);
+                    }
+                    writeln!(out_file, "    svc #0").unwrap();
+                    write_push(out_file, "x0");
+                }
+            },
+            Instruction::Function => {
+                writeln!(out_file, "    str x30, [x27, #-8]!").unwrap();
+            }
+            Instruction::Call => match op.data {
+                linker::LinkedTokenData::JumpAddr(ptr) => {
+                    writeln!(out_file, "    bl addr_{}", ptr).unwrap();
+                }
+                _ => panic!(),
+            },
+            Instruction::CallExtern => {
+                eprintln!(
+                    "{}: ERROR: '{}' is not supported by asm-aarch64, external functions are only available with the x86-64 linux compilers",
+                    op.word, op.word.txt
+                );
+                std::process::exit(1);
+            }
+            Instruction::Return => {
+                writeln!(out_file, "    ldr x30, [x27], #8").unwrap();
+                writeln!(out_file, "    ret").unwrap();
+            }
+            Instruction::PushVars => match op.data {
+                linker::LinkedTokenData::Count(var_count) => {
+                    writeln!(out_file, "    sub x27, x27, #{}", var_count * 8).unwrap();
+                    for i in 0..var_count {
+                        write_pop(out_file, "x0");
+                        writeln!(out_file, "    str x0, [x27, #{}]", (var_count - 1 - i) * 8).unwrap();
+                    }
+                }
+                _ => panic!(),
+            },
+            Instruction::PopVars => match op.data {
+                linker::LinkedTokenData::Count(var_count) => {
+                    writeln!(out_file, "    add x27, x27, #{}", var_count * 8).unwrap();
+                }
+                _ => panic!(),
+            },
+            Instruction::ApplyVar => match op.data {
+                linker::LinkedTokenData::Index(var_index) => {
+                    writeln!(out_file, "    ldr x0, [x27, #{}]", var_index * 8).unwrap();
+                    write_push(out_file, "x0");
+                }
+                _ => panic!(),
+            },
+            Instruction::Jump => match op.data {
+                linker::LinkedTokenData::JumpAddr(ptr) => {
+                    writeln!(out_file, "    b addr_{}", ptr).unwrap();
+                }
+                _ => panic!(),
+            },
+            Instruction::JumpNeq | Instruction::Do => match op.data {
+                //cbz only reaches 1MiB, so the conditional branch skips over an unconditional one instead
+                linker::LinkedTokenData::JumpAddr(ptr) => {
+                    write_pop(out_file, "x0");
+                    writeln!(out_file, "    cbnz x0, 1f").unwrap();
+                    writeln!(out_file, "    b addr_{}", ptr).unwrap();
+                    writeln!(out_file, "1:").unwrap();
+                }
+                _ => panic!(),
+            },
+        }
+    }
+
+    fn epilogue(&self, out_file: &mut std::fs::File, ctx: &LinkerContext) {
+        writeln!(out_file, "addr_{}:", ctx.result.len()).unwrap();
+        writeln!(out_file, "addr_exit:").unwrap();
+        writeln!(out_file, "    bl out_flush").unwrap();
+        writeln!(out_file, "    mov x0, #0").unwrap();
+        writeln!(out_file, "    mov x8, #{}", SYS_EXIT).unwrap();
+        writeln!(out_file, "    svc #0").unwrap();
+    }
+
+    fn finish(&self, file_path: &str, mut out_file: std::fs::File, _options: &CompileOptions) {
+        let output_file_path = add_or_replace_extension(file_path, "s");
+        out_file.flush().unwrap_or_else(|e| {
+            eprintln!("ERROR: Could not open file for compilation: {}", e);
+            std::process::exit(1);
+        });
+        println!("SUCCESS: Written compilation to: {}", output_file_path);
+        compiler::compile_obj_file(file_path, "s", &format!("{}as", binutils_prefix()), &["-g"]);
+        link_obj_file(file_path);
+    }
+
+    fn run_command(&self, file_path: &str) -> Result<std::process::Command, String> {
+        //Other hosts run AArch64 executables through qemu user mode emulation
+        if cfg!(target_arch = "aarch64") {
+            Ok(std::process::Command::new(add_or_replace_extension(file_path, "")))
+        } else {
+            if std::process::Command::new("qemu-aarch64").arg("--version").output().is_err() {
+                return Err("qemu-aarch64 is not available".to_string());
+            }
+            let mut cmd = std::process::Command::new("qemu-aarch64");
+            cmd.arg(add_or_replace_extension(file_path, ""));
+            Ok(cmd)
+        }
+    }
+}
+
+fn write_push(out_file: &mut std::fs::File, reg: &str) {
+    writeln!(out_file, "    str {}, [x28, #-8]!", reg).unwrap();
+}
+
+fn write_pop(out_file: &mut std::fs::File, reg: &str) {
+    writeln!(out_file, "    ldr {}, [x28], #8", reg).unwrap();
+}
+
+//Immediates are built 16 bits at a time, skipping the zero chunks after the first one
+fn write_load_immediate(out_file: &mut std::fs::File, reg: &str, value: u64) {
+    writeln!(out_file, "    movz {}, #{}", reg, value & 0xffff).unwrap();
+    for shift in [16, 32, 48] {
+        let chunk = (value >> shift) & 0xffff;
+        if chunk != 0 {
+            writeln!(out_file, "    movk {}, #{}, lsl #{}", reg, chunk, shift).unwrap();
+        }
+    }
+}
+
+//Binary operations take the top of the stack in x0 and the value below it in x1, leaving the result in x1
+fn write_binary(out_file: &mut std::fs::File, operation: &str) {
+    write_pop(out_file, "x0");
+    write_pop(out_file, "x1");
+    writeln!(out_file, "    {}", operation).unwrap();
+    write_push(out_file, "x1");
+}
+
+fn write_division(out_file: &mut std::fs::File, operation: &str) {
+    write_pop(out_file, "x0");
+    write_pop(out_file, "x1");
+    writeln!(out_file, "    cbz x0, division_by_zero").unwrap();
+    writeln!(out_file, "    {}", operation).unwrap();
+    write_push(out_file, "x1");
+}
+
+fn write_compare(out_file: &mut std::fs::File, cond: &str) {
+    write_pop(out_file, "x0");
+    write_pop(out_file, "x1");
+    writeln!(out_file, "    cmp x1, x0").unwrap();
+    writeln!(out_file, "    cset x1, {}", cond).unwrap();
+    write_push(out_file, "x1");
+}
+
+fn write_store(out_file: &mut std::fs::File, store: &str) {
+    write_pop(out_file, "x0");
+    write_pop(out_file, "x1");
+    writeln!(out_file, "    {}, [x0]", store).unwrap();
+}
+
+fn write_load(out_file: &mut std::fs::File, load: &str) {
+    write_pop(out_file, "x0");
+    writeln!(out_file, "    {}, [x0]", load).unwrap();
+    write_push(out_file, "x1");
+}
+
+fn write_string_literal(out_file: &mut std::fs::File, index: usize, val: &str) {
+    let bytes: Vec<String> = compiler::string_bytes(val).iter().map(|x| x.to_string()).collect();
+    writeln!(out_file, "str_{}: .byte {}", index, bytes.join(", ")).unwrap();
+}
+
+//Cross binutils are used unless the host itself is an AArch64 machine
+fn binutils_prefix() -> &'static str {
+    if cfg!(target_arch = "aarch64") { "" } else { "aarch64-linux-gnu-" }
+}
+
+fn link_obj_file(file_path: &str) {
+    let obj_file_path = add_or_replace_extension(file_path, "obj");
+    let exe_file_path = add_or_replace_extension(file_path, "");
+    let cmd = std::process::Command::new(format!("{}ld", binutils_prefix()))
+        .arg("-o")
+        .arg(&exe_file_path)
+        .arg(obj_file_path)
+        .output()
+        .unwrap_or_else(|err| {
+            eprintln!("ERROR: Could not make executable: {}", err);
+            std::process::exit(1);
+        });
+    if cmd.status.success() {
+        println!("SUCCESS: Written executable to: {}", exe_file_path);
+    } else {
+        eprintln!("ERROR: Could not make executable");
+        eprintln!("{}", String::from_utf8_lossy(&cmd.stdout));
+        eprintln!("{}", String::from_utf8_lossy(&cmd.stderr));
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,372 @@
+use crate::compiler::{Backend, CompileOptions};
+use crate::linker::{Instruction, LinkedToken, LinkerContext};
+use crate::tokenizer::Intrinsic;
+use crate::{add_or_replace_extension, compiler, compiler_string, linker, tokenizer};
+use std::collections::HashSet;
+use std::io::Write;
+
+/// Number of 64 bit cells of the data, var and return stacks in the generated C program.
+const STACK_SIZE: usize = 1 << 20;
+
+/// The C source being written, with the instructions which need a label.
+pub struct Source {
+    out_file: std::fs::File,
+    jump_targets: HashSet<usize>,
+    return_targets: HashSet<usize>,
+}
+
+pub struct C;
+
+impl Backend for C {
+    type Output = Source;
+
+    fn id(&self) -> &'static str {
+        "c"
+    }
+
+    fn prologue(&self, file_path: &str, ctx: &LinkerContext, _options: &CompileOptions) -> Source {
+        let output_file_path = add_or_replace_extension(file_path, "c");
+        let mut out_file = std::fs::File::create(&output_file_path).unwrap_or_else(|e| {
+            eprintln!("ERROR: Could not open file for compilation: {}", e);
+            std::process::exit(1);
+        });
+        let uses_syscalls = ctx.result.iter().any(|op| {
+            matches!(
+                op.instruction,
+                Instruction::Intrinsic(
+                    Intrinsic::Syscall0 | Intrinsic::Syscall1 | Intrinsic::Syscall2 | Intrinsic::Syscall3 | Intrinsic::Syscall4 | Intrinsic::Syscall5 | Intrinsic::Syscall6
+                )
+            )
+        });
+        if uses_syscalls {
+            writeln!(&mut out_file, "#define _GNU_SOURCE").unwrap();
+        }
+        writeln!(&mut out_file, "#include <inttypes.h>").unwrap();
+        writeln!(&mut out_file, "#include <stdint.h>").unwrap();
+        writeln!(&mut out_file, "#include <stdio.h>").unwrap();
+        writeln!(&mut out_file, "#include <stdlib.h>").unwrap();
+        writeln!(&mut out_file, "#include <string.h>").unwrap();
+        writeln!(&mut out_file).unwrap();
+        writeln!(&mut out_file, "static uint64_t stack[{}];", STACK_SIZE).unwrap();
+        writeln!(&mut out_file, "static uint64_t vars[{}];", STACK_SIZE).unwrap();
+        writeln!(&mut out_file, "static uint64_t calls[{}];", STACK_SIZE).unwrap();
+        writeln!(&mut out_file, "static uint8_t mem[{}];", ctx.mem_size.max(1)).unwrap();
+        for (index, val) in ctx.strings.iter().enumerate() {
+            write_string_literal(&mut out_file, index, val);
+        }
+        writeln!(&mut out_file).unwrap();
+        writeln!(&mut out_file, "#define PUSH(x) do {{ uint64_t pushed = (uint64_t)(x); stack[sp++] = pushed; }} while (0)").unwrap();
+        writeln!(&mut out_file, "#define POP() (stack[--sp])").unwrap();
+        writeln!(&mut out_file, "#define PTR(x) ((void *)(uintptr_t)(x))").unwrap();
+        writeln!(&mut out_file).unwrap();
+        //Memory accesses go through memcpy as Feylon allows unaligned pointers
+        for bits in [8, 16, 32, 64] {
+            writeln!(
+                &mut out_file,
+                "static uint64_t load{0}(uint64_t ptr) {{ uint{0}_t x; memcpy(&x, PTR(ptr), sizeof(x)); return x; }}",
+                bits
+            )
+            .unwrap();
+            writeln!(
+                &mut out_file,
+                "static void store{0}(uint64_t ptr, uint64_t val) {{ uint{0}_t x = (uint{0}_t)val; memcpy(PTR(ptr), &x, sizeof(x)); }}",
+                bits
+            )
+            .unwrap();
+        }
+        if uses_syscalls {
+            //Syscall numbers are those of linux x86-64, compiling the generated code for other targets fails
+            writeln!(&mut out_file, "#if !defined(__linux__) || !defined(__x86_64__)").unwrap();
+            writeln!(&mut out_file, "#error \"syscalls are only available on linux x86-64\"").unwrap();
+            writeln!(&mut out_file, "#endif").unwrap();
+            writeln!(&mut out_file, "#include <errno.h>").unwrap();
+            writeln!(&mut out_file, "#include <unistd.h>").unwrap();
+            writeln!(
+                &mut out_file,
+                "static uint64_t feylon_syscall(long result) {{ return result == -1 ? (uint64_t)-errno : (uint64_t)result; }}"
+            )
+            .unwrap();
+            writeln!(&mut out_file).unwrap();
+        }
+        writeln!(&mut out_file, "int main(int argc, char **argv, char **envp) {{").unwrap();
+        writeln!(&mut out_file, "    uint64_t a, b, args[6];").unwrap();
+        writeln!(&mut out_file, "    size_t sp = 0, vp = 0, cp = 0, pc = 0;").unwrap();
+        writeln!(&mut out_file, "    (void)a, (void)b, (void)args, (void)argc, (void)argv, (void)envp, (void)vp, (void)cp;").unwrap();
+        //Static jumps use goto, returns go through the switch as their target is only known at runtime
+        if ctx.result.iter().any(|op| matches!(op.instruction, Instruction::Return)) {
+            writeln!(&mut out_file, "dispatch:").unwrap();
+        }
+        writeln!(&mut out_file, "    switch (pc) {{").unwrap();
+        let mut jump_targets: HashSet<usize> = HashSet::new();
+        let mut return_targets: HashSet<usize> = HashSet::from([0]);
+        for op in &ctx.result {
+            if let linker::LinkedTokenData::JumpAddr(ptr) = op.data {
+                jump_targets.insert(ptr);
+            }
+            if let Instruction::Call = op.instruction {
+                return_targets.insert(op.self_ptr + 1);
+            }
+        }
+        Source {
+            out_file,
+            jump_targets,
+            return_targets,
+        }
+    }
+
+    fn lower(&self, out: &mut Source, op: &LinkedToken) {
+        let mut labels: Vec<String> = vec![];
+        if out.return_targets.contains(&op.self_ptr) {
+            labels.push(format!("case {}:", op.self_ptr));
+        }
+        if out.jump_targets.contains(&op.self_ptr) {
+            labels.push(format!("addr_{}:", op.self_ptr));
+        }
+        writeln!(
+            &mut out.out_file,
+            "    {}{}// {}",
+            labels.join(" "),
+            if labels.is_empty() { "" } else { " " },
+            compiler_string::stringify_op(op)
+        )
+        .unwrap();
+        write_op(&mut out.out_file, op);
+    }
+
+    fn epilogue(&self, out: &mut Source, ctx: &LinkerContext) {
+        if out.jump_targets.contains(&ctx.result.len()) {
+            writeln!(&mut out.out_file, "    addr_{}: break;", ctx.result.len()).unwrap();
+        }
+        writeln!(&mut out.out_file, "    default: break;").unwrap();
+        writeln!(&mut out.out_file, "    }}").unwrap();
+        writeln!(&mut out.out_file, "    return 0;").unwrap();
+        writeln!(&mut out.out_file, "}}").unwrap();
+    }
+
+    fn finish(&self, file_path: &str, mut out: Source, _options: &CompileOptions) {
+        let output_file_path = add_or_replace_extension(file_path, "c");
+        out.out_file.flush().unwrap_or_else(|e| {
+            eprintln!("ERROR: Could not open file for compilation: {}", e);
+            std::process::exit(1);
+        });
+        println!("SUCCESS: Written compilation to: {}", output_file_path);
+        compile_c_file(file_path);
+    }
+}
+
+fn write_op(out_file: &mut std::fs::File, op: &linker::LinkedToken) {
+    match op.instruction {
+        Instruction::PushInt(x) => {
+            writeln!(out_file, "        PUSH(UINT64_C({}));", x).unwrap();
+        }
+        Instruction::PushSInt(x) => {
+            writeln!(out_file, "        PUSH(UINT64_C({}));", x as u64).unwrap();
+        }
+        Instruction::PushPtr(ptr) => {
+            writeln!(out_file, "        PUSH(UINT64_C({}));", ptr).unwrap();
+        }
+        Instruction::PushMem(offset) => {
+            writeln!(out_file, "        PUSH((uintptr_t)(mem + {}));", offset).unwrap();
+        }
+        Instruction::PushBool(x) => {
+            writeln!(out_file, "        PUSH({});", if x { 1 } else { 0 }).unwrap();
+        }
+        Instruction::PushString(ref val) => match op.data {
+            linker::LinkedTokenData::Index(string_index) => {
+                writeln!(out_file, "        PUSH({});", val.len()).unwrap();
+                writeln!(out_file, "        PUSH((uintptr_t)str_{});", string_index).unwrap();
+            }
+            _ => panic!(),
+        },
+        Instruction::Intrinsic(intrinsic) => write_intrinsic(out_file, intrinsic),
+        Instruction::Function => {}
+        Instruction::Call => match op.data {
+            linker::LinkedTokenData::JumpAddr(ptr) => {
+                writeln!(out_file, "        calls[cp++] = {};", op.self_ptr + 1).unwrap();
+                writeln!(out_file, "        goto addr_{};", ptr).unwrap();
+            }
+            _ => panic!(),
+        },
+        Instruction::CallExtern => {
+            eprintln!(
+                "{}: ERROR: '{}' is not supported by c, external functions are only available with the x86-64 linux compilers",
+                op.word, op.word.txt
+            );
+            std::process::exit(1);
+        }
+        Instruction::Return => {
+            writeln!(out_file, "        pc = calls[--cp];").unwrap();
+            writeln!(out_file, "        goto dispatch;").unwrap();
+        }
+        Instruction::PushVars => match op.data {
+            linker::LinkedTokenData::Count(var_count) => {
+                for _ in 0..var_count {
+                    writeln!(out_file, "        vars[vp++] = POP();").unwrap();
+                }
+            }
+            _ => panic!(),
+        },
+        Instruction::PopVars => match op.data {
+            linker::LinkedTokenData::Count(var_count) => {
+                writeln!(out_file, "        vp -= {};", var_count).unwrap();
+            }
+            _ => panic!(),
+        },
+        Instruction::ApplyVar => match op.data {
+            linker::LinkedTokenData::Index(var_index) => {
+                writeln!(out_file, "        PUSH(vars[vp - {}]);", var_index + 1).unwrap();
+            }
+            _ => panic!(),
+        },
+        Instruction::Jump => match op.data {
+            linker::LinkedTokenData::JumpAddr(ptr) => {
+                writeln!(out_file, "        goto addr_{};", ptr).unwrap();
+            }
+            _ => panic!(),
+        },
+        Instruction::JumpNeq | Instruction::Do => match op.data {
+            linker::LinkedTokenData::JumpAddr(ptr) => {
+                writeln!(out_file, "        if (POP() == 0) goto addr_{};", ptr).unwrap();
+            }
+            _ => panic!(),
+        },
+    }
+}
+
+fn write_intrinsic(out_file: &mut std::fs::File, intrinsic: Intrinsic) {
+    //Binary operators pop the right hand side into a and the left hand side into b
+    let binary = |out_file: &mut std::fs::File, expr: &str| {
+        writeln!(out_file, "        a = POP();").unwrap();
+        writeln!(out_file, "        b = POP();").unwrap();
+        writeln!(out_file, "        PUSH({});", expr).unwrap();
+    };
+    match intrinsic {
+        Intrinsic::Dump => {
+            writeln!(out_file, "        printf(\"%\" PRIu64 \"\\n\", POP());").unwrap();
+        }
+        Intrinsic::Puts | Intrinsic::EPuts => {
+            if matches!(intrinsic, Intrinsic::EPuts) {
+                writeln!(out_file, "        fflush(stdout);").unwrap();
+            }
+            writeln!(out_file, "        a = POP();").unwrap();
+            writeln!(out_file, "        b = POP();").unwrap();
+            writeln!(
+                out_file,
+                "        fwrite(PTR(a), 1, b, {});",
+                if matches!(intrinsic, Intrinsic::Puts) { "stdout" } else { "stderr" }
+            )
+            .unwrap();
+        }
+        Intrinsic::Drop => {
+            writeln!(out_file, "        sp--;").unwrap();
+        }
+        Intrinsic::Dup => {
+            writeln!(out_file, "        PUSH(stack[sp - 1]);").unwrap();
+        }
+        Intrinsic::Over => {
+            writeln!(out_file, "        PUSH(stack[sp - 2]);").unwrap();
+        }
+        Intrinsic::Swap => {
+            writeln!(out_file, "        a = stack[sp - 1];").unwrap();
+            writeln!(out_file, "        stack[sp - 1] = stack[sp - 2];").unwrap();
+            writeln!(out_file, "        stack[sp - 2] = a;").unwrap();
+        }
+        Intrinsic::Rot => {
+            writeln!(out_file, "        a = stack[sp - 3];").unwrap();
+            writeln!(out_file, "        stack[sp - 3] = stack[sp - 2];").unwrap();
+            writeln!(out_file, "        stack[sp - 2] = stack[sp - 1];").unwrap();
+            writeln!(out_file, "        stack[sp - 1] = a;").unwrap();
+        }
+        Intrinsic::Add => binary(out_file, "b + a"),
+        Intrinsic::Subtract => binary(out_file, "b - a"),
+        Intrinsic::Multiply => binary(out_file, "b * a"),
+        Intrinsic::Divide => binary(out_file, "b / a"),
+        Intrinsic::Modulo => binary(out_file, "b % a"),
+        //Dividing the smallest integer by -1 overflows, it wraps around like in the simulator
+        Intrinsic::DivideSigned => binary(out_file, "(int64_t)a == -1 ? 0 - b : (uint64_t)((int64_t)b / (int64_t)a)"),
+        Intrinsic::ModuloSigned => binary(out_file, "(int64_t)a == -1 ? 0 : (uint64_t)((int64_t)b % (int64_t)a)"),
+        Intrinsic::ShiftLeft => binary(out_file, "b << (a & 63)"),
+        Intrinsic::ShiftRight => binary(out_file, "b >> (a & 63)"),
+        Intrinsic::ShiftRightSigned => binary(out_file, "(uint64_t)((int64_t)b >> (a & 63))"),
+        Intrinsic::BitAnd => binary(out_file, "b & a"),
+        Intrinsic::BitOr => binary(out_file, "b | a"),
+        Intrinsic::BitXor => binary(out_file, "b ^ a"),
+        Intrinsic::Equals => binary(out_file, "b == a"),
+        Intrinsic::NotEquals => binary(out_file, "b != a"),
+        Intrinsic::Less => binary(out_file, "b < a"),
+        Intrinsic::Greater => binary(out_file, "b > a"),
+        Intrinsic::LessOrEqual => binary(out_file, "b <= a"),
+        Intrinsic::GreaterOrEqual => binary(out_file, "b >= a"),
+        Intrinsic::LessSigned => binary(out_file, "(int64_t)b < (int64_t)a"),
+        Intrinsic::GreaterSigned => binary(out_file, "(int64_t)b > (int64_t)a"),
+        Intrinsic::LessOrEqualSigned => binary(out_file, "(int64_t)b <= (int64_t)a"),
+        Intrinsic::GreaterOrEqualSigned => binary(out_file, "(int64_t)b >= (int64_t)a"),
+        Intrinsic::CastInt | Intrinsic::CastSInt | Intrinsic::CastPtr => {}
+        Intrinsic::Store8 | Intrinsic::Store16 | Intrinsic::Store32 | Intrinsic::Store64 => {
+            writeln!(out_file, "        a = POP();").unwrap();
+            writeln!(out_file, "        b = POP();").unwrap();
+            writeln!(out_file, "        store{}(a, b);", access_bits(intrinsic)).unwrap();
+        }
+        Intrinsic::Load8 | Intrinsic::Load16 | Intrinsic::Load32 | Intrinsic::Load64 => {
+            writeln!(out_file, "        stack[sp - 1] = load{}(stack[sp - 1]);", access_bits(intrinsic)).unwrap();
+        }
+        Intrinsic::Exit => {
+            writeln!(out_file, "        fflush(stdout);").unwrap();
+            writeln!(out_file, "        exit((int)POP());").unwrap();
+        }
+        Intrinsic::Argc => {
+            writeln!(out_file, "        PUSH(argc);").unwrap();
+        }
+        Intrinsic::Argv => {
+            writeln!(out_file, "        PUSH((uintptr_t)argv);").unwrap();
+        }
+        Intrinsic::Envp => {
+            writeln!(out_file, "        PUSH((uintptr_t)envp);").unwrap();
+        }
+        Intrinsic::Syscall0 | Intrinsic::Syscall1 | Intrinsic::Syscall2 | Intrinsic::Syscall3 | Intrinsic::Syscall4 | Intrinsic::Syscall5 | Intrinsic::Syscall6 => {
+            let arg_count = tokenizer::get_syscall_arg_count(&intrinsic);
+            writeln!(out_file, "        fflush(stdout);").unwrap();
+            writeln!(out_file, "        a = POP();").unwrap();
+            for i in 0..arg_count {
+                writeln!(out_file, "        args[{}] = POP();", i).unwrap();
+            }
+            let args: Vec<String> = (0..arg_count).map(|i| format!(", args[{}]", i)).collect();
+            writeln!(out_file, "        PUSH(feylon_syscall(syscall(a{})));", args.join("")).unwrap();
+        }
+    }
+}
+
+fn access_bits(intrinsic: Intrinsic) -> usize {
+    match intrinsic {
+        Intrinsic::Store8 | Intrinsic::Load8 => 8,
+        Intrinsic::Store16 | Intrinsic::Load16 => 16,
+        Intrinsic::Store32 | Intrinsic::Load32 => 32,
+        _ => 64,
+    }
+}
+
+fn write_string_literal(out_file: &mut std::fs::File, index: usize, val: &str) {
+    let bytes: Vec<String> = compiler::string_bytes(val).iter().map(|x| x.to_string()).collect();
+    writeln!(out_file, "static const uint8_t str_{}[] = {{{}}};", index, bytes.join(", ")).unwrap();
+}
+
+//The C compiler can be changed through the CC environment variable
+fn compile_c_file(file_path: &str) {
+    let c_file_path = add_or_replace_extension(file_path, "c");
+    let exe_file_path = add_or_replace_extension(file_path, "");
+    let compiler = std::env::var("CC").unwrap_or_else(|_| "cc".to_string());
+    let cmd = match std::process::Command::new(&compiler).args(vec!["-O2", "-o"]).arg(&exe_file_path).arg(&c_file_path).output() {
+        Ok(cmd) => cmd,
+        Err(err) => {
+            println!("INFO: Could not run '{}', only the C source was written: {}", compiler, err);
+            return;
+        }
+    };
+    if cmd.status.success() {
+        println!("SUCCESS: Written executable to: {}", exe_file_path);
+    } else {
+        eprintln!("ERROR: Could not make executable");
+        eprintln!("{}", String::from_utf8_lossy(&cmd.stderr));
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,286 @@
+use crate::compiler::{Backend, CompileOptions};
+use crate::compiler_x86_64::{self, Generator, Os};
+use crate::elf64::{Relocation, RelocationKind, RelocationTarget, Symbol, SymbolKind};
+use crate::linker::{Instruction, LinkedToken, LinkedTokenData, LinkerContext};
+use crate::x86_64::Program;
+use crate::{add_or_replace_extension, compiler_asm_elf64, dwarf, elf64, lexer, x86_64};
+use std::collections::HashMap;
+
+/// Static Linux x86-64 executable, encoded and written without any external tools.
+/// It comes with a symbol for every function and memory, and DWARF line information mapping the code back to the source.
+/// Programs calling external functions are written as an object instead and linked with libc by the C compiler,
+/// libraries are written as an object to be linked into a C program.
+pub struct Elf64;
+
+/// The generated program along with the source locations needed for its debug information.
+pub struct Output {
+    generator: Generator,
+    //The label of every instruction and the word it was linked from
+    lines: Vec<(String, lexer::Word)>,
+    functions: Vec<Function>,
+    //Name, offset in the memory and size of every memory
+    memories: Vec<(String, usize, usize)>,
+}
+
+struct Function {
+    name: String,
+    start_label: String,
+    end_label: String,
+    word: lexer::Word,
+}
+
+impl Backend for Elf64 {
+    type Output = Output;
+
+    fn id(&self) -> &'static str {
+        "elf64"
+    }
+
+    fn prologue(&self, _file_path: &str, ctx: &LinkerContext, options: &CompileOptions) -> Output {
+        Output {
+            generator: compiler_x86_64::prologue(Os::Linux, ctx, options),
+            lines: vec![],
+            functions: vec![],
+            memories: vec![],
+        }
+    }
+
+    fn runtime(&self, out: &mut Output) {
+        compiler_x86_64::runtime(Os::Linux, &mut out.generator);
+    }
+
+    fn lower(&self, out: &mut Output, op: &LinkedToken) {
+        compiler_x86_64::lower(Os::Linux, &mut out.generator, op);
+        out.lines.push((format!("addr_{}", op.self_ptr), op.word.clone()));
+    }
+
+    fn epilogue(&self, out: &mut Output, ctx: &LinkerContext) {
+        compiler_x86_64::epilogue(Os::Linux, &mut out.generator);
+        //Symbols are listed by address, the order of the hash maps would make the output differ between runs
+        let mut functions: Vec<_> = ctx.functions.iter().collect();
+        functions.sort_by_key(|(_, function)| function.ptr);
+        for (name, function) in functions {
+            //A function ends where the jump over its body lands
+            let end_ptr = match (&ctx.result[function.ptr - 1].instruction, ctx.result[function.ptr - 1].data) {
+                (Instruction::Jump, LinkedTokenData::JumpAddr(ptr)) => ptr,
+                _ => panic!("Function '{}' is not preceded by a jump over its body. This is a linking error.", name),
+            };
+            out.functions.push(Function {
+                name: name.clone(),
+                start_label: format!("addr_{}", function.ptr),
+                end_label: format!("addr_{}", end_ptr),
+                word: ctx.result[function.ptr].word.clone(),
+            });
+        }
+        for (name, memory) in &ctx.memories {
+            out.memories.push((name.clone(), memory.ptr, memory.size));
+        }
+        out.memories.sort_by_key(|(_, ptr, _)| *ptr);
+    }
+
+    fn finish(&self, file_path: &str, out: Output, options: &CompileOptions) {
+        let program = &out.generator.program;
+        if options.emit_asm {
+            compiler_asm_elf64::write_asm_file(file_path, program);
+        }
+        if options.lib {
+            write_obj_file(file_path, program, &out.functions, &out.memories);
+            return;
+        }
+        if !program.externs.is_empty() {
+            write_obj_file(file_path, program, &out.functions, &out.memories);
+            compiler_asm_elf64::link_obj_file(file_path, true, &options.link);
+            return;
+        }
+        let mut assembled = x86_64::assemble(&program.text);
+
+        let mut rodata: Vec<u8> = vec![];
+        let mut rodata_offsets: Vec<(&str, usize)> = vec![];
+        for (name, bytes) in &program.rodata {
+            rodata_offsets.push((name, rodata.len()));
+            rodata.extend_from_slice(bytes);
+        }
+        let layout = elf64::layout(assembled.code.len(), rodata.len());
+
+        let mut symbols: HashMap<String, u64> = HashMap::new();
+        for (name, offset) in rodata_offsets {
+            symbols.insert(name.to_string(), layout.rodata_addr + offset as u64);
+        }
+        let mut bss_size: usize = 0;
+        for (name, size) in &program.bss {
+            symbols.insert(name.to_string(), layout.bss_addr + bss_size as u64);
+            bss_size = (bss_size + size).next_multiple_of(8);
+        }
+
+        assembled.link(layout.text_addr, &symbols).unwrap_or_else(|e| {
+            eprintln!("ERROR: Could not link program: {}", e);
+            std::process::exit(1);
+        });
+        let entry_label = program.entry.as_deref().unwrap_or_default();
+        let entry = match assembled.labels.get(entry_label) {
+            Some(offset) => layout.text_addr + *offset as u64,
+            None => {
+                eprintln!("ERROR: Could not find entry point '{}'", entry_label);
+                std::process::exit(1);
+            }
+        };
+
+        let label_addr = |label: &str| layout.text_addr + assembled.labels[label] as u64;
+        let mut elf_symbols: Vec<Symbol> = vec![];
+        let mut subprograms: Vec<dwarf::Subprogram> = vec![];
+        for function in &out.functions {
+            let low_pc = label_addr(&function.start_label);
+            let high_pc = label_addr(&function.end_label);
+            elf_symbols.push(Symbol {
+                name: function.name.clone(),
+                kind: SymbolKind::Function,
+                addr: low_pc,
+                size: high_pc - low_pc,
+            });
+            subprograms.push(dwarf::Subprogram {
+                name: function.name.clone(),
+                low_pc,
+                high_pc,
+                file: function.word.file.clone(),
+                row: function.word.row,
+            });
+        }
+        for (name, offset, size) in &out.memories {
+            elf_symbols.push(Symbol {
+                name: name.clone(),
+                kind: SymbolKind::Object,
+                addr: symbols["mem"] + *offset as u64,
+                size: *size as u64,
+            });
+        }
+        let debug = dwarf::DebugInfo {
+            name: file_path.to_string(),
+            comp_dir: std::env::current_dir().map(|x| x.to_string_lossy().to_string()).unwrap_or_default(),
+            low_pc: out.lines.first().map_or(label_addr("addr_exit"), |(label, _)| label_addr(label)),
+            high_pc: label_addr("addr_exit"),
+            lines: out
+                .lines
+                .iter()
+                .map(|(label, word)| dwarf::Line {
+                    addr: label_addr(label),
+                    file: word.file.clone(),
+                    row: word.row,
+                    col: word.col,
+                })
+                .collect(),
+            subprograms,
+        };
+        let debug_sections = dwarf::write_sections(&debug);
+        let sections = [
+            (".debug_abbrev", debug_sections.abbrev),
+            (".debug_info", debug_sections.info),
+            (".debug_line", debug_sections.line),
+        ];
+        let executable = elf64::write_executable(&layout, entry, &assembled.code, &rodata, bss_size, &elf_symbols, &sections);
+
+        let output_file_path = add_or_replace_extension(file_path, "");
+        std::fs::write(&output_file_path, executable).unwrap_or_else(|e| {
+            eprintln!("ERROR: Could not write executable: {}", e);
+            std::process::exit(1);
+        });
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&output_file_path, std::fs::Permissions::from_mode(0o755)).unwrap_or_else(|e| {
+                eprintln!("ERROR: Could not make executable runnable: {}", e);
+                std::process::exit(1);
+            });
+        }
+        println!("SUCCESS: Written executable to: {}", output_file_path);
+    }
+
+    fn checkable(&self) -> bool {
+        true
+    }
+
+    fn linkable(&self) -> bool {
+        true
+    }
+}
+
+/// Writes the program as a relocatable object, with the functions and memories as local symbols and only the entry point
+/// or the exported functions global. The object has no debug information, as its addresses would need relocations as well.
+fn write_obj_file(file_path: &str, program: &Program, functions: &[Function], memories: &[(String, usize, usize)]) {
+    let mut assembled = x86_64::assemble(&program.text);
+
+    let mut data: HashMap<&str, (RelocationTarget, u64)> = HashMap::new();
+    let mut rodata: Vec<u8> = vec![];
+    for (name, bytes) in &program.rodata {
+        data.insert(name, (RelocationTarget::Rodata, rodata.len() as u64));
+        rodata.extend_from_slice(bytes);
+    }
+    let mut bss_size: usize = 0;
+    for (name, size) in &program.bss {
+        data.insert(name, (RelocationTarget::Bss, bss_size as u64));
+        bss_size = (bss_size + size).next_multiple_of(8);
+    }
+
+    let mut relocations: Vec<Relocation> = vec![];
+    for fixup in assembled.link_labels() {
+        let (target, offset) = if let Some(offset) = assembled.labels.get(&fixup.symbol) {
+            (RelocationTarget::Text, *offset as u64)
+        } else if let Some((target, offset)) = data.get(fixup.symbol.as_str()) {
+            (target.clone(), *offset)
+        } else if program.externs.contains(&fixup.symbol) {
+            (RelocationTarget::External(fixup.symbol.clone()), 0)
+        } else {
+            eprintln!("ERROR: Could not link program: Undefined symbol '{}'", fixup.symbol);
+            std::process::exit(1);
+        };
+        let kind = match &target {
+            RelocationTarget::External(_) => RelocationKind::Plt32,
+            _ => RelocationKind::Pc32,
+        };
+        //The linker computes relative fields from their own address, the encoder from the end of the instruction
+        let addend = offset as i64 + fixup.addend - 4;
+        relocations.push(Relocation {
+            offset: fixup.offset as u64,
+            kind,
+            target,
+            addend,
+        });
+    }
+
+    let label_offset = |label: &str| assembled.labels[label] as u64;
+    let mut symbols: Vec<Symbol> = vec![];
+    //The symbol of an exported function is its wrapper, which is global
+    for function in functions.iter().filter(|x| !program.exports.contains(&x.name)) {
+        let start = label_offset(&function.start_label);
+        symbols.push(Symbol {
+            name: function.name.clone(),
+            kind: SymbolKind::Function,
+            addr: start,
+            size: label_offset(&function.end_label) - start,
+        });
+    }
+    for (name, offset, size) in memories {
+        symbols.push(Symbol {
+            name: name.clone(),
+            kind: SymbolKind::Object,
+            addr: data["mem"].1 + *offset as u64,
+            size: *size as u64,
+        });
+    }
+    let globals: Vec<Symbol> = program
+        .globals()
+        .map(|name| Symbol {
+            name: name.clone(),
+            kind: SymbolKind::Function,
+            addr: label_offset(name),
+            size: 0,
+        })
+        .collect();
+    let object = elf64::write_object(&assembled.code, &rodata, bss_size, &symbols, &globals, &relocations);
+
+    let output_file_path = add_or_replace_extension(file_path, "obj");
+    std::fs::write(&output_file_path, object).unwrap_or_else(|e| {
+        eprintln!("ERROR: Could not write object: {}", e);
+        std::process::exit(1);
+    });
+    println!("SUCCESS: Written object to: {}", output_file_path);
+}
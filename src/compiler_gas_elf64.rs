@@ -0,0 +1,57 @@
+use crate::compiler::{self, Backend, CompileOptions};
+use crate::compiler_x86_64::{self, Generator, Os};
+use crate::linker::{LinkedToken, LinkerContext};
+use crate::{add_or_replace_extension, compiler_asm_elf64};
+use std::io::Write;
+
+/// Linux x86-64 executable, assembled with GNU as and linked with ld.
+pub struct GasElf64;
+
+impl Backend for GasElf64 {
+    type Output = Generator;
+
+    fn id(&self) -> &'static str {
+        "gas-elf64"
+    }
+
+    fn prologue(&self, _file_path: &str, ctx: &LinkerContext, options: &CompileOptions) -> Generator {
+        compiler_x86_64::prologue(Os::Linux, ctx, options)
+    }
+
+    fn runtime(&self, out: &mut Generator) {
+        compiler_x86_64::runtime(Os::Linux, out);
+    }
+
+    fn lower(&self, out: &mut Generator, op: &LinkedToken) {
+        compiler_x86_64::lower(Os::Linux, out, op);
+    }
+
+    fn epilogue(&self, out: &mut Generator, _ctx: &LinkerContext) {
+        compiler_x86_64::epilogue(Os::Linux, out);
+    }
+
+    fn finish(&self, file_path: &str, out: Generator, options: &CompileOptions) {
+        let output_file_path = add_or_replace_extension(file_path, "s");
+        let mut out_file = std::fs::File::create(&output_file_path).unwrap_or_else(|e| {
+            eprintln!("ERROR: Could not open file for compilation: {}", e);
+            std::process::exit(1);
+        });
+        out_file.write_all(out.program.to_gas().as_bytes()).unwrap_or_else(|e| {
+            eprintln!("ERROR: Could not open file for compilation: {}", e);
+            std::process::exit(1);
+        });
+        println!("SUCCESS: Written compilation to: {}", output_file_path);
+        compiler::compile_obj_file(file_path, "s", "as", &["--64", "-g"]);
+        if !options.lib {
+            compiler_asm_elf64::link_obj_file(file_path, !out.program.externs.is_empty(), &options.link);
+        }
+    }
+
+    fn checkable(&self) -> bool {
+        true
+    }
+
+    fn linkable(&self) -> bool {
+        true
+    }
+}
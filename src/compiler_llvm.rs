@@ -0,0 +1,523 @@
+use crate::compiler::{Backend, CompileOptions};
+use crate::linker::{Instruction, LinkedToken, LinkerContext};
+use crate::tokenizer::Intrinsic;
+use crate::{add_or_replace_extension, compiler, compiler_string, linker, tokenizer};
+use std::collections::HashSet;
+use std::io::Write;
+
+/// Number of 64 bit cells of the data, var and return stacks in the generated module.
+const STACK_SIZE: usize = 1 << 20;
+
+/// Oldest LLVM release reading the opaque `ptr` type without extra options.
+const MIN_CLANG_VERSION: u32 = 15;
+
+const SYSCALL_ARG_REGISTERS: [&str; 6] = ["rdi", "rsi", "rdx", "r10", "r8", "r9"];
+
+/// Writes the body of `main`, every value popped from the stacks gets a new temporary.
+pub struct IrWriter {
+    out_file: std::fs::File,
+    tmp_count: usize,
+}
+
+pub struct Llvm;
+
+impl Backend for Llvm {
+    type Output = IrWriter;
+
+    fn id(&self) -> &'static str {
+        "llvm"
+    }
+
+    fn prologue(&self, file_path: &str, ctx: &LinkerContext, _options: &CompileOptions) -> IrWriter {
+        let output_file_path = add_or_replace_extension(file_path, "ll");
+        let out_file = std::fs::File::create(&output_file_path).unwrap_or_else(|e| {
+            eprintln!("ERROR: Could not open file for compilation: {}", e);
+            std::process::exit(1);
+        });
+        let mut ir = IrWriter { out_file, tmp_count: 0 };
+        ir.line(&format!("; ModuleID = '{}'", file_path));
+        ir.line(&format!("@stack = internal global [{} x i64] zeroinitializer", STACK_SIZE));
+        ir.line(&format!("@vars = internal global [{} x i64] zeroinitializer", STACK_SIZE));
+        ir.line(&format!("@calls = internal global [{} x i64] zeroinitializer", STACK_SIZE));
+        ir.line(&format!("@mem = internal global [{} x i8] zeroinitializer", ctx.mem_size.max(1)));
+        for (index, val) in ctx.strings.iter().enumerate() {
+            write_string_literal(&mut ir, index, val);
+        }
+        ir.line("@dump_format = private unnamed_addr constant [6 x i8] c\"%llu\\0A\\00\"");
+        ir.line("");
+        ir.line("declare i32 @printf(ptr, ...)");
+        ir.line("declare i32 @fflush(ptr)");
+        ir.line("declare i64 @write(i32, ptr, i64)");
+        ir.line("declare void @exit(i32)");
+        ir.line("");
+        ir.line("define i32 @main(i32 %argc, ptr %argv, ptr %envp) {");
+        ir.line("entry:");
+        for reg in ["sp", "vp", "cp", "pc"] {
+            ir.line(&format!("  %{} = alloca i64", reg));
+            ir.line(&format!("  store i64 0, ptr %{}", reg));
+        }
+        ir.line("  br label %addr_0");
+
+        //Blocks following a call are the only ones a return can jump to, they are reached through the dispatch block
+        let return_targets: Vec<usize> = ctx
+            .result
+            .iter()
+            .filter(|op| matches!(op.instruction, Instruction::Call))
+            .map(|op| op.self_ptr + 1)
+            .collect();
+        if ctx.result.iter().any(|op| matches!(op.instruction, Instruction::Return)) {
+            ir.line("dispatch:");
+            let pc = ir.load("pc");
+            let unique: HashSet<usize> = return_targets.iter().copied().collect();
+            let mut targets: Vec<usize> = unique.into_iter().collect();
+            targets.sort();
+            let cases: Vec<String> = targets.iter().map(|x| format!("i64 {}, label %addr_{}", x, x)).collect();
+            ir.line(&format!("  switch i64 {}, label %addr_{} [{}]", pc, ctx.result.len(), cases.join(" ")));
+        }
+        ir
+    }
+
+    fn lower(&self, ir: &mut IrWriter, op: &LinkedToken) {
+        ir.line(&format!("addr_{}: ; {} {}", op.self_ptr, op.word, compiler_string::stringify_op(op)));
+        if write_op(ir, op) {
+            ir.line(&format!("  br label %addr_{}", op.self_ptr + 1));
+        }
+    }
+
+    fn epilogue(&self, ir: &mut IrWriter, ctx: &LinkerContext) {
+        ir.line(&format!("addr_{}:", ctx.result.len()));
+        ir.line("  ret i32 0");
+        ir.line("}");
+    }
+
+    fn finish(&self, file_path: &str, mut ir: IrWriter, _options: &CompileOptions) {
+        let output_file_path = add_or_replace_extension(file_path, "ll");
+        ir.out_file.flush().unwrap_or_else(|e| {
+            eprintln!("ERROR: Could not open file for compilation: {}", e);
+            std::process::exit(1);
+        });
+        println!("SUCCESS: Written compilation to: {}", output_file_path);
+        compile_ir_file(file_path);
+    }
+
+    fn run_command(&self, file_path: &str) -> Result<std::process::Command, String> {
+        //Without clang only the IR is written, there is no executable to run
+        let compiler = clang();
+        if std::process::Command::new(&compiler).arg("--version").output().is_err() {
+            return Err(format!("{} is not available", compiler));
+        }
+        Ok(std::process::Command::new(add_or_replace_extension(file_path, "")))
+    }
+}
+
+/// Writes the instructions of one op, returns false when the op already ends its block with a jump.
+fn write_op(ir: &mut IrWriter, op: &linker::LinkedToken) -> bool {
+    match op.instruction {
+        Instruction::PushInt(x) => ir.push(&x.to_string()),
+        Instruction::PushSInt(x) => ir.push(&x.to_string()),
+        Instruction::PushPtr(ptr) => ir.push(&ptr.to_string()),
+        Instruction::PushMem(offset) => {
+            let val = ir.tmp();
+            ir.line(&format!("  {} = ptrtoint ptr getelementptr (i8, ptr @mem, i64 {}) to i64", val, offset));
+            ir.push(&val);
+        }
+        Instruction::PushBool(x) => ir.push(if x { "1" } else { "0" }),
+        Instruction::PushString(ref val) => match op.data {
+            linker::LinkedTokenData::Index(string_index) => {
+                ir.push(&val.len().to_string());
+                let ptr = ir.tmp();
+                ir.line(&format!("  {} = ptrtoint ptr @str_{} to i64", ptr, string_index));
+                ir.push(&ptr);
+            }
+            _ => panic!(),
+        },
+        Instruction::Intrinsic(intrinsic) => write_intrinsic(ir, intrinsic),
+        Instruction::Function => {}
+        Instruction::Call => match op.data {
+            linker::LinkedTokenData::JumpAddr(ptr) => {
+                ir.push_to("calls", "cp", &(op.self_ptr + 1).to_string());
+                ir.line(&format!("  br label %addr_{}", ptr));
+                return false;
+            }
+            _ => panic!(),
+        },
+        Instruction::CallExtern => {
+            eprintln!(
+                "{}: ERROR: '{}' is not supported by llvm, external functions are only available with the x86-64 linux compilers",
+                op.word, op.word.txt
+            );
+            std::process::exit(1);
+        }
+        Instruction::Return => {
+            let target = ir.pop_from("calls", "cp");
+            ir.line(&format!("  store i64 {}, ptr %pc", target));
+            ir.line("  br label %dispatch");
+            return false;
+        }
+        Instruction::PushVars => match op.data {
+            linker::LinkedTokenData::Count(var_count) => {
+                for _ in 0..var_count {
+                    let val = ir.pop();
+                    ir.push_to("vars", "vp", &val);
+                }
+            }
+            _ => panic!(),
+        },
+        Instruction::PopVars => match op.data {
+            linker::LinkedTokenData::Count(var_count) => {
+                let vp = ir.load("vp");
+                let new_vp = ir.tmp();
+                ir.line(&format!("  {} = sub i64 {}, {}", new_vp, vp, var_count));
+                ir.line(&format!("  store i64 {}, ptr %vp", new_vp));
+            }
+            _ => panic!(),
+        },
+        Instruction::ApplyVar => match op.data {
+            linker::LinkedTokenData::Index(var_index) => {
+                let val = ir.peek_from("vars", "vp", var_index + 1);
+                ir.push(&val);
+            }
+            _ => panic!(),
+        },
+        Instruction::Jump => match op.data {
+            linker::LinkedTokenData::JumpAddr(ptr) => {
+                ir.line(&format!("  br label %addr_{}", ptr));
+                return false;
+            }
+            _ => panic!(),
+        },
+        Instruction::JumpNeq | Instruction::Do => match op.data {
+            linker::LinkedTokenData::JumpAddr(ptr) => {
+                let flag = ir.pop();
+                let cond = ir.tmp();
+                ir.line(&format!("  {} = icmp ne i64 {}, 0", cond, flag));
+                ir.line(&format!("  br i1 {}, label %addr_{}, label %addr_{}", cond, op.self_ptr + 1, ptr));
+                return false;
+            }
+            _ => panic!(),
+        },
+    }
+    true
+}
+
+fn write_intrinsic(ir: &mut IrWriter, intrinsic: Intrinsic) {
+    match intrinsic {
+        Intrinsic::Dump => {
+            let val = ir.pop();
+            ir.line(&format!("  call i32 (ptr, ...) @printf(ptr @dump_format, i64 {})", val));
+        }
+        Intrinsic::Puts | Intrinsic::EPuts => {
+            let ptr = ir.pop_ptr();
+            let len = ir.pop();
+            let fd = if matches!(intrinsic, Intrinsic::Puts) { 1 } else { 2 };
+            ir.line("  call i32 @fflush(ptr null)");
+            ir.line(&format!("  call i64 @write(i32 {}, ptr {}, i64 {})", fd, ptr, len));
+        }
+        Intrinsic::Drop => {
+            ir.pop();
+        }
+        Intrinsic::Dup => {
+            let a = ir.pop();
+            ir.push(&a);
+            ir.push(&a);
+        }
+        Intrinsic::Over => {
+            let a = ir.pop();
+            let b = ir.pop();
+            ir.push(&b);
+            ir.push(&a);
+            ir.push(&b);
+        }
+        Intrinsic::Swap => {
+            let a = ir.pop();
+            let b = ir.pop();
+            ir.push(&a);
+            ir.push(&b);
+        }
+        Intrinsic::Rot => {
+            let a = ir.pop();
+            let b = ir.pop();
+            let c = ir.pop();
+            ir.push(&b);
+            ir.push(&a);
+            ir.push(&c);
+        }
+        Intrinsic::Add => ir.binary("add"),
+        Intrinsic::Subtract => ir.binary("sub"),
+        Intrinsic::Multiply => ir.binary("mul"),
+        Intrinsic::Divide => ir.binary("udiv"),
+        Intrinsic::Modulo => ir.binary("urem"),
+        //Dividing the smallest integer by -1 is undefined in LLVM, it wraps around like in the simulator
+        Intrinsic::DivideSigned | Intrinsic::ModuloSigned => {
+            let a = ir.pop();
+            let b = ir.pop();
+            let minus_one = ir.tmp();
+            ir.line(&format!("  {} = icmp eq i64 {}, -1", minus_one, a));
+            let divisor = ir.tmp();
+            ir.line(&format!("  {} = select i1 {}, i64 1, i64 {}", divisor, minus_one, a));
+            let result = ir.tmp();
+            let special = if matches!(intrinsic, Intrinsic::DivideSigned) {
+                ir.line(&format!("  {} = sdiv i64 {}, {}", result, b, divisor));
+                let negated = ir.tmp();
+                ir.line(&format!("  {} = sub i64 0, {}", negated, b));
+                negated
+            } else {
+                ir.line(&format!("  {} = srem i64 {}, {}", result, b, divisor));
+                "0".to_string()
+            };
+            let val = ir.tmp();
+            ir.line(&format!("  {} = select i1 {}, i64 {}, i64 {}", val, minus_one, special, result));
+            ir.push(&val);
+        }
+        Intrinsic::ShiftLeft | Intrinsic::ShiftRight | Intrinsic::ShiftRightSigned => {
+            let op = match intrinsic {
+                Intrinsic::ShiftLeft => "shl",
+                Intrinsic::ShiftRight => "lshr",
+                _ => "ashr",
+            };
+            let a = ir.pop();
+            let b = ir.pop();
+            let count = ir.tmp();
+            ir.line(&format!("  {} = and i64 {}, 63", count, a));
+            let val = ir.tmp();
+            ir.line(&format!("  {} = {} i64 {}, {}", val, op, b, count));
+            ir.push(&val);
+        }
+        Intrinsic::BitAnd => ir.binary("and"),
+        Intrinsic::BitOr => ir.binary("or"),
+        Intrinsic::BitXor => ir.binary("xor"),
+        Intrinsic::Equals => ir.compare("eq"),
+        Intrinsic::NotEquals => ir.compare("ne"),
+        Intrinsic::Less => ir.compare("ult"),
+        Intrinsic::Greater => ir.compare("ugt"),
+        Intrinsic::LessOrEqual => ir.compare("ule"),
+        Intrinsic::GreaterOrEqual => ir.compare("uge"),
+        Intrinsic::LessSigned => ir.compare("slt"),
+        Intrinsic::GreaterSigned => ir.compare("sgt"),
+        Intrinsic::LessOrEqualSigned => ir.compare("sle"),
+        Intrinsic::GreaterOrEqualSigned => ir.compare("sge"),
+        Intrinsic::CastInt | Intrinsic::CastSInt | Intrinsic::CastPtr => {}
+        Intrinsic::Store8 | Intrinsic::Store16 | Intrinsic::Store32 | Intrinsic::Store64 => {
+            let typ = access_type(intrinsic);
+            let ptr = ir.pop_ptr();
+            let val = ir.pop();
+            let truncated = if typ == "i64" {
+                val
+            } else {
+                let truncated = ir.tmp();
+                ir.line(&format!("  {} = trunc i64 {} to {}", truncated, val, typ));
+                truncated
+            };
+            ir.line(&format!("  store {} {}, ptr {}, align 1", typ, truncated, ptr));
+        }
+        Intrinsic::Load8 | Intrinsic::Load16 | Intrinsic::Load32 | Intrinsic::Load64 => {
+            let typ = access_type(intrinsic);
+            let ptr = ir.pop_ptr();
+            let val = ir.tmp();
+            ir.line(&format!("  {} = load {}, ptr {}, align 1", val, typ, ptr));
+            if typ == "i64" {
+                ir.push(&val);
+            } else {
+                let extended = ir.tmp();
+                ir.line(&format!("  {} = zext {} {} to i64", extended, typ, val));
+                ir.push(&extended);
+            }
+        }
+        Intrinsic::Exit => {
+            let code = ir.pop();
+            let truncated = ir.tmp();
+            ir.line(&format!("  {} = trunc i64 {} to i32", truncated, code));
+            ir.line(&format!("  call void @exit(i32 {})", truncated));
+        }
+        Intrinsic::Argc => {
+            let val = ir.tmp();
+            ir.line(&format!("  {} = sext i32 %argc to i64", val));
+            ir.push(&val);
+        }
+        Intrinsic::Argv | Intrinsic::Envp => {
+            let val = ir.tmp();
+            ir.line(&format!(
+                "  {} = ptrtoint ptr %{} to i64",
+                val,
+                if matches!(intrinsic, Intrinsic::Argv) { "argv" } else { "envp" }
+            ));
+            ir.push(&val);
+        }
+        //Syscalls use inline assembly, so the module only compiles for linux x86-64
+        Intrinsic::Syscall0 | Intrinsic::Syscall1 | Intrinsic::Syscall2 | Intrinsic::Syscall3 | Intrinsic::Syscall4 | Intrinsic::Syscall5 | Intrinsic::Syscall6 => {
+            let arg_count = tokenizer::get_syscall_arg_count(&intrinsic);
+            ir.line("  call i32 @fflush(ptr null)");
+            let mut args = vec![format!("i64 {}", ir.pop())];
+            let mut constraints = vec!["={rax}".to_string(), "{rax}".to_string()];
+            for reg in SYSCALL_ARG_REGISTERS.iter().take(arg_count) {
+                args.push(format!("i64 {}", ir.pop()));
+                constraints.push(format!("{{{}}}", reg));
+            }
+            constraints.extend(["~{rcx}", "~{r11}", "~{memory}"].map(|x| x.to_string()));
+            let val = ir.tmp();
+            ir.line(&format!(
+                "  {} = call i64 asm sideeffect \"syscall\", \"{}\"({})",
+                val,
+                constraints.join(","),
+                args.join(", ")
+            ));
+            ir.push(&val);
+        }
+    }
+}
+
+fn access_type(intrinsic: Intrinsic) -> &'static str {
+    match intrinsic {
+        Intrinsic::Store8 | Intrinsic::Load8 => "i8",
+        Intrinsic::Store16 | Intrinsic::Load16 => "i16",
+        Intrinsic::Store32 | Intrinsic::Load32 => "i32",
+        _ => "i64",
+    }
+}
+
+impl IrWriter {
+    fn line(&mut self, txt: &str) {
+        writeln!(self.out_file, "{}", txt).unwrap();
+    }
+
+    fn tmp(&mut self) -> String {
+        self.tmp_count += 1;
+        format!("%t{}", self.tmp_count)
+    }
+
+    fn load(&mut self, reg: &str) -> String {
+        let val = self.tmp();
+        self.line(&format!("  {} = load i64, ptr %{}", val, reg));
+        val
+    }
+
+    fn cell(&mut self, stack: &str, index: &str) -> String {
+        let ptr = self.tmp();
+        self.line(&format!("  {} = getelementptr inbounds [{} x i64], ptr @{}, i64 0, i64 {}", ptr, STACK_SIZE, stack, index));
+        ptr
+    }
+
+    fn push_to(&mut self, stack: &str, reg: &str, val: &str) {
+        let index = self.load(reg);
+        let ptr = self.cell(stack, &index);
+        self.line(&format!("  store i64 {}, ptr {}", val, ptr));
+        let new_index = self.tmp();
+        self.line(&format!("  {} = add i64 {}, 1", new_index, index));
+        self.line(&format!("  store i64 {}, ptr %{}", new_index, reg));
+    }
+
+    fn pop_from(&mut self, stack: &str, reg: &str) -> String {
+        let index = self.load(reg);
+        let new_index = self.tmp();
+        self.line(&format!("  {} = sub i64 {}, 1", new_index, index));
+        self.line(&format!("  store i64 {}, ptr %{}", new_index, reg));
+        let ptr = self.cell(stack, &new_index);
+        let val = self.tmp();
+        self.line(&format!("  {} = load i64, ptr {}", val, ptr));
+        val
+    }
+
+    fn peek_from(&mut self, stack: &str, reg: &str, depth: usize) -> String {
+        let index = self.load(reg);
+        let cell_index = self.tmp();
+        self.line(&format!("  {} = sub i64 {}, {}", cell_index, index, depth));
+        let ptr = self.cell(stack, &cell_index);
+        let val = self.tmp();
+        self.line(&format!("  {} = load i64, ptr {}", val, ptr));
+        val
+    }
+
+    fn push(&mut self, val: &str) {
+        self.push_to("stack", "sp", val);
+    }
+
+    fn pop(&mut self) -> String {
+        self.pop_from("stack", "sp")
+    }
+
+    fn pop_ptr(&mut self) -> String {
+        let val = self.pop();
+        let ptr = self.tmp();
+        self.line(&format!("  {} = inttoptr i64 {} to ptr", ptr, val));
+        ptr
+    }
+
+    fn binary(&mut self, op: &str) {
+        let a = self.pop();
+        let b = self.pop();
+        let val = self.tmp();
+        self.line(&format!("  {} = {} i64 {}, {}", val, op, b, a));
+        self.push(&val);
+    }
+
+    fn compare(&mut self, cond: &str) {
+        let a = self.pop();
+        let b = self.pop();
+        let flag = self.tmp();
+        self.line(&format!("  {} = icmp {} i64 {}, {}", flag, cond, b, a));
+        let val = self.tmp();
+        self.line(&format!("  {} = zext i1 {} to i64", val, flag));
+        self.push(&val);
+    }
+}
+
+fn write_string_literal(ir: &mut IrWriter, index: usize, val: &str) {
+    let bytes = compiler::string_bytes(val);
+    let mut escaped = String::new();
+    for byte in &bytes {
+        if byte.is_ascii_graphic() && *byte != b'"' && *byte != b'\\' || *byte == b' ' {
+            escaped.push(*byte as char);
+        } else {
+            escaped.push_str(&format!("\\{:02X}", byte));
+        }
+    }
+    ir.line(&format!("@str_{} = private unnamed_addr constant [{} x i8] c\"{}\"", index, bytes.len(), escaped));
+}
+
+//The compiler can be changed through the CLANG environment variable
+fn clang() -> String {
+    std::env::var("CLANG").unwrap_or_else(|_| "clang".to_string())
+}
+
+/// Major LLVM version of clang, as printed first by `clang --version`, like "Ubuntu clang version 14.0.0-1ubuntu1".
+/// Apple numbers its releases differently, their LLVM version is not known.
+fn clang_version(compiler: &str) -> Option<u32> {
+    let output = std::process::Command::new(compiler).arg("--version").output().ok()?;
+    let output = String::from_utf8_lossy(&output.stdout);
+    let line = output.lines().next().filter(|x| !x.starts_with("Apple"))?;
+    let version = line.split("version ").nth(1)?;
+    version.split('.').next()?.parse().ok()
+}
+
+fn compile_ir_file(file_path: &str) {
+    let ir_file_path = add_or_replace_extension(file_path, "ll");
+    let exe_file_path = add_or_replace_extension(file_path, "");
+    let compiler = clang();
+    //The IR uses opaque pointers, which older versions only read with -opaque-pointers
+    if let Some(version) = clang_version(&compiler).filter(|x| *x < MIN_CLANG_VERSION) {
+        eprintln!(
+            "ERROR: Could not make executable, {} is version {} but LLVM {} or newer is required",
+            compiler, version, MIN_CLANG_VERSION
+        );
+        std::process::exit(1);
+    }
+    let cmd = match std::process::Command::new(&compiler)
+        .args(vec!["-O2", "-Wno-override-module", "-o"])
+        .arg(&exe_file_path)
+        .arg(&ir_file_path)
+        .output()
+    {
+        Ok(cmd) => cmd,
+        Err(err) => {
+            println!("INFO: Could not run '{}', only the LLVM IR was written: {}", compiler, err);
+            return;
+        }
+    };
+    if cmd.status.success() {
+        println!("SUCCESS: Written executable to: {}", exe_file_path);
+    } else {
+        eprintln!("ERROR: Could not make executable");
+        eprintln!("{}", String::from_utf8_lossy(&cmd.stderr));
+        std::process::exit(1);
+    }
+}
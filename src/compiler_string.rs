@@ -1,34 +1,57 @@
+use crate::compiler::{Backend, CompileOptions};
 use crate::{add_or_replace_extension, linker};
 use linker::Instruction;
 use std::io::Write;
 
-pub fn process_program(file_path: &str, ctx: &linker::LinkerContext) {
-    let output_file_path = add_or_replace_extension(file_path, "cfc");
-    let mut out_file = std::fs::File::create(&output_file_path).unwrap_or_else(|e| {
-        eprintln!("ERROR: Could not open file for compilation: {}", e);
-        std::process::exit(1);
-    });
-    for token in &ctx.result {
-        let op_str = stringify_op(&token);
-        out_file
-            .write_all(format!("[{}]{:32} //{}\n", token.self_ptr, op_str, token.word).as_bytes())
-            .unwrap_or_else(|e| {
+/// Textual listing of the linked program, one instruction per line.
+pub struct Listing;
+
+impl Backend for Listing {
+    type Output = Vec<String>;
+
+    fn id(&self) -> &'static str {
+        "string"
+    }
+
+    fn prologue(&self, _file_path: &str, _ctx: &linker::LinkerContext, _options: &CompileOptions) -> Vec<String> {
+        vec![]
+    }
+
+    fn lower(&self, out: &mut Vec<String>, op: &linker::LinkedToken) {
+        out.push(format!("[{}]{:32} //{}\n", op.self_ptr, stringify_op(op), op.word));
+    }
+
+    fn finish(&self, file_path: &str, out: Vec<String>, _options: &CompileOptions) {
+        let output_file_path = add_or_replace_extension(file_path, "cfc");
+        let mut out_file = std::fs::File::create(&output_file_path).unwrap_or_else(|e| {
+            eprintln!("ERROR: Could not open file for compilation: {}", e);
+            std::process::exit(1);
+        });
+        for line in out {
+            out_file.write_all(line.as_bytes()).unwrap_or_else(|e| {
                 eprintln!("ERROR: Could not open file for compilation: {}", e);
                 std::process::exit(1);
             });
+        }
+        out_file.flush().unwrap_or_else(|e| {
+            eprintln!("ERROR: Could not open file for compilation: {}", e);
+            std::process::exit(1);
+        });
+        println!("SUCCESS: Written compilation to: {}", output_file_path);
+    }
+
+    fn testable(&self) -> bool {
+        false
     }
-    out_file.flush().unwrap_or_else(|e| {
-        eprintln!("ERROR: Could not open file for compilation: {}", e);
-        std::process::exit(1);
-    });
-    println!("SUCCESS: Written compilation to: {}", output_file_path);
 }
 
 pub fn stringify_op(op: &linker::LinkedToken) -> String {
     let base: String = match &op.instruction {
         Instruction::PushInt(val) => format!("PUSH_INT({})", val),
-        Instruction::PushString(val) => format!("PUSH_STRING({})", val),
+        Instruction::PushSInt(val) => format!("PUSH_SINT({})", val),
+        Instruction::PushString(val) => format!("PUSH_STRING({:?})", val),
         Instruction::Intrinsic(val) => val.to_string(),
+        Instruction::CallExtern => format!("CALL_EXTERN({})", op.word.txt),
 
         _ => op.instruction.to_string(),
     };
@@ -0,0 +1,440 @@
+use crate::compiler::{Backend, CompileOptions};
+use crate::linker::{Instruction, LinkedToken, LinkerContext};
+use crate::tokenizer::Intrinsic;
+use crate::wasm::{Func, FuncType, Import, Instr, MemOp, Module, Op, ValType};
+use crate::{add_or_replace_extension, compiler, compiler_string, linker, wasm};
+use std::collections::{BTreeSet, HashMap};
+use std::io::Write;
+
+/// Number of 64 bit cells of the data, var and return stacks in linear memory.
+const STACK_SIZE: usize = 1 << 16;
+//The first bytes of memory are left unused so that no valid pointer is zero
+const STRINGS_ADDR: usize = 16;
+
+const HOST: &str = include_str!("wasm_host.js");
+
+//Functions imported from the host, their index is also their type
+const DUMP: u32 = 0;
+const WRITE: u32 = 1;
+const EXIT: u32 = 2;
+const MAIN: u32 = 3;
+
+//Locals of the main function
+const SP: u32 = 0;
+const VP: u32 = 1;
+const CP: u32 = 2;
+const PC: u32 = 3;
+const A: u32 = 4;
+const B: u32 = 5;
+const C: u32 = 6;
+
+/// The program is split into basic blocks, which are cases of a `br_table` inside a loop. Falling through goes to the
+/// next case and jumping sets `pc` to the case and restarts the loop.
+pub struct Generator {
+    body: Vec<Instr>,
+    blocks: HashMap<usize, u32>,
+    block_count: u32,
+    current_block: u32,
+    mem_addr: usize,
+    string_addrs: Vec<usize>,
+    data: Vec<u8>,
+    memory_size: usize,
+}
+
+pub struct Wasm;
+
+impl Backend for Wasm {
+    type Output = Generator;
+
+    fn id(&self) -> &'static str {
+        "wasm"
+    }
+
+    fn prologue(&self, _file_path: &str, ctx: &LinkerContext, _options: &CompileOptions) -> Generator {
+        let mut data: Vec<u8> = vec![];
+        let mut string_addrs: Vec<usize> = vec![];
+        for val in &ctx.strings {
+            string_addrs.push(STRINGS_ADDR + data.len());
+            data.extend(compiler::string_bytes(val));
+        }
+        let mem_addr = (STRINGS_ADDR + data.len()).next_multiple_of(8);
+        let stack_addr = (mem_addr + ctx.mem_size).next_multiple_of(8);
+        let vars_addr = stack_addr + STACK_SIZE * 8;
+        let calls_addr = vars_addr + STACK_SIZE * 8;
+        let memory_size = calls_addr + STACK_SIZE * 8;
+
+        //Every jump target and every op following a jump starts a new block, the end of the program is the last block
+        let mut leaders: BTreeSet<usize> = BTreeSet::from([0, ctx.result.len()]);
+        for op in &ctx.result {
+            if let linker::LinkedTokenData::JumpAddr(ptr) = op.data {
+                leaders.insert(ptr);
+            }
+            if matches!(
+                op.instruction,
+                Instruction::Jump | Instruction::JumpNeq | Instruction::Do | Instruction::Call | Instruction::Return
+            ) {
+                leaders.insert(op.self_ptr + 1);
+            }
+        }
+        let blocks: HashMap<usize, u32> = leaders.iter().enumerate().map(|(i, x)| (*x, i as u32)).collect();
+        let mut generator = Generator {
+            body: vec![],
+            blocks,
+            block_count: leaders.len() as u32,
+            current_block: 0,
+            mem_addr,
+            string_addrs,
+            data,
+            memory_size,
+        };
+        generator.write_start(stack_addr, vars_addr, calls_addr);
+        generator
+    }
+
+    fn lower(&self, generator: &mut Generator, op: &LinkedToken) {
+        if op.self_ptr > 0
+            && let Some(block) = generator.blocks.get(&op.self_ptr)
+        {
+            generator.current_block = *block;
+            generator.emit(&[Instr::End]);
+        }
+        generator.emit(&[Instr::Comment(format!("addr_{}: {} {}", op.self_ptr, op.word, compiler_string::stringify_op(op)))]);
+        generator.write_op(op);
+    }
+
+    fn epilogue(&self, generator: &mut Generator, ctx: &LinkerContext) {
+        //Closes the block of the end of the program and the loop, the function then returns
+        if !ctx.result.is_empty() {
+            generator.emit(&[Instr::End]);
+        }
+        generator.emit(&[Instr::End]);
+    }
+
+    fn finish(&self, file_path: &str, generator: Generator, _options: &CompileOptions) {
+        let module = Module {
+            types: vec![
+                FuncType {
+                    params: vec![ValType::I64],
+                    results: vec![],
+                },
+                FuncType {
+                    params: vec![ValType::I32, ValType::I32, ValType::I32],
+                    results: vec![],
+                },
+                FuncType {
+                    params: vec![ValType::I32],
+                    results: vec![],
+                },
+                FuncType { params: vec![], results: vec![] },
+            ],
+            imports: ["dump", "write", "exit"]
+                .iter()
+                .enumerate()
+                .map(|(i, name)| Import {
+                    module: "env".to_string(),
+                    name: name.to_string(),
+                    typ: i as u32,
+                })
+                .collect(),
+            funcs: vec![Func {
+                typ: MAIN,
+                locals: vec![ValType::I32, ValType::I32, ValType::I32, ValType::I32, ValType::I64, ValType::I64, ValType::I64],
+                body: generator.body,
+            }],
+            memory_pages: generator.memory_size.div_ceil(wasm::PAGE_SIZE) as u32,
+            exports: vec![("main".to_string(), MAIN)],
+            data: if generator.data.is_empty() {
+                vec![]
+            } else {
+                vec![(STRINGS_ADDR as u32, generator.data)]
+            },
+        };
+
+        let binary = module.encode();
+        if let Err(err) = wasm::validate(&binary) {
+            eprintln!("ERROR: Generated an invalid WebAssembly module: {}", err);
+            std::process::exit(1);
+        }
+        write_output_file(file_path, "wat", module.to_wat().as_bytes());
+        write_output_file(file_path, "wasm", &binary);
+        write_output_file(file_path, "js", HOST.as_bytes());
+    }
+
+    //WebAssembly modules are run by their host interface, which requires node
+    fn run_command(&self, file_path: &str) -> Result<std::process::Command, String> {
+        if std::process::Command::new("node").arg("--version").output().is_err() {
+            return Err("node is not available".to_string());
+        }
+        let mut cmd = std::process::Command::new("node");
+        cmd.arg(add_or_replace_extension(file_path, "js"));
+        Ok(cmd)
+    }
+}
+
+fn write_output_file(file_path: &str, extension: &str, content: &[u8]) {
+    let output_file_path = add_or_replace_extension(file_path, extension);
+    let mut out_file = std::fs::File::create(&output_file_path).unwrap_or_else(|e| {
+        eprintln!("ERROR: Could not open file for compilation: {}", e);
+        std::process::exit(1);
+    });
+    out_file.write_all(content).unwrap_or_else(|e| {
+        eprintln!("ERROR: Could not open file for compilation: {}", e);
+        std::process::exit(1);
+    });
+    println!("SUCCESS: Written compilation to: {}", output_file_path);
+}
+
+impl Generator {
+    fn write_start(&mut self, stack_addr: usize, vars_addr: usize, calls_addr: usize) {
+        for (local, addr) in [(SP, stack_addr), (VP, vars_addr), (CP, calls_addr)] {
+            self.emit(&[Instr::I32Const(addr as i32), Instr::LocalSet(local)]);
+        }
+        self.emit(&[Instr::Loop]);
+        for _ in 0..self.block_count {
+            self.emit(&[Instr::Block]);
+        }
+        let depths: Vec<u32> = (0..self.block_count).collect();
+        self.emit(&[Instr::LocalGet(PC), Instr::BrTable(depths, self.block_count - 1), Instr::End]);
+    }
+
+    fn emit(&mut self, instrs: &[Instr]) {
+        self.body.extend_from_slice(instrs);
+    }
+
+    /// Jumps to the block starting at `ptr`, `nesting` is the number of blocks opened since the start of the current one.
+    fn jump(&mut self, ptr: usize, nesting: u32) {
+        let block = self.blocks[&ptr];
+        let loop_depth = self.block_count - 1 - self.current_block + nesting;
+        self.emit(&[Instr::I32Const(block as i32), Instr::LocalSet(PC), Instr::Br(loop_depth)]);
+    }
+
+    fn push(&mut self, value: &[Instr]) {
+        self.push_to(SP, value);
+    }
+
+    fn push_to(&mut self, reg: u32, value: &[Instr]) {
+        self.emit(&[Instr::LocalGet(reg)]);
+        self.emit(value);
+        self.emit(&[
+            Instr::Mem(MemOp::I64Store, 0),
+            Instr::LocalGet(reg),
+            Instr::I32Const(8),
+            Instr::Op(Op::I32Add),
+            Instr::LocalSet(reg),
+        ]);
+    }
+
+    fn pop_into(&mut self, local: u32) {
+        self.pop_from_into(SP, local);
+    }
+
+    fn pop_from_into(&mut self, reg: u32, local: u32) {
+        self.emit(&[
+            Instr::LocalGet(reg),
+            Instr::I32Const(8),
+            Instr::Op(Op::I32Sub),
+            Instr::LocalTee(reg),
+            Instr::Mem(MemOp::I64Load, 0),
+            Instr::LocalSet(local),
+        ]);
+    }
+
+    fn binary(&mut self, ops: &[Op]) {
+        self.pop_into(A);
+        self.pop_into(B);
+        let mut value = vec![Instr::LocalGet(B), Instr::LocalGet(A)];
+        value.extend(ops.iter().map(|x| Instr::Op(*x)));
+        self.push(&value);
+    }
+
+    fn write_op(&mut self, op: &linker::LinkedToken) {
+        match op.instruction {
+            Instruction::PushInt(x) => self.push(&[Instr::I64Const(x as i64)]),
+            Instruction::PushSInt(x) => self.push(&[Instr::I64Const(x)]),
+            Instruction::PushPtr(ptr) => self.push(&[Instr::I64Const(ptr as i64)]),
+            Instruction::PushMem(offset) => self.push(&[Instr::I64Const((self.mem_addr + offset) as i64)]),
+            Instruction::PushBool(x) => self.push(&[Instr::I64Const(if x { 1 } else { 0 })]),
+            Instruction::PushString(ref val) => match op.data {
+                linker::LinkedTokenData::Index(string_index) => {
+                    self.push(&[Instr::I64Const(val.len() as i64)]);
+                    self.push(&[Instr::I64Const(self.string_addrs[string_index] as i64)]);
+                }
+                _ => panic!(),
+            },
+            Instruction::Intrinsic(intrinsic) => self.write_intrinsic(op, intrinsic),
+            Instruction::Function => {}
+            Instruction::Call => match op.data {
+                linker::LinkedTokenData::JumpAddr(ptr) => {
+                    let return_block = self.blocks[&(op.self_ptr + 1)];
+                    self.push_to(CP, &[Instr::I64Const(return_block as i64)]);
+                    self.jump(ptr, 0);
+                }
+                _ => panic!(),
+            },
+            Instruction::CallExtern => {
+                eprintln!(
+                    "{}: ERROR: '{}' is not supported by wasm, external functions are only available with the x86-64 linux compilers",
+                    op.word, op.word.txt
+                );
+                std::process::exit(1);
+            }
+            Instruction::Return => {
+                self.pop_from_into(CP, A);
+                let loop_depth = self.block_count - 1 - self.current_block;
+                self.emit(&[Instr::LocalGet(A), Instr::Op(Op::I32WrapI64), Instr::LocalSet(PC), Instr::Br(loop_depth)]);
+            }
+            Instruction::PushVars => match op.data {
+                linker::LinkedTokenData::Count(var_count) => {
+                    for _ in 0..var_count {
+                        self.pop_into(A);
+                        self.push_to(VP, &[Instr::LocalGet(A)]);
+                    }
+                }
+                _ => panic!(),
+            },
+            Instruction::PopVars => match op.data {
+                linker::LinkedTokenData::Count(var_count) => {
+                    self.emit(&[Instr::LocalGet(VP), Instr::I32Const(var_count as i32 * 8), Instr::Op(Op::I32Sub), Instr::LocalSet(VP)]);
+                }
+                _ => panic!(),
+            },
+            Instruction::ApplyVar => match op.data {
+                linker::LinkedTokenData::Index(var_index) => {
+                    self.push(&[
+                        Instr::LocalGet(VP),
+                        Instr::I32Const((var_index as i32 + 1) * 8),
+                        Instr::Op(Op::I32Sub),
+                        Instr::Mem(MemOp::I64Load, 0),
+                    ]);
+                }
+                _ => panic!(),
+            },
+            Instruction::Jump => match op.data {
+                linker::LinkedTokenData::JumpAddr(ptr) => self.jump(ptr, 0),
+                _ => panic!(),
+            },
+            Instruction::JumpNeq | Instruction::Do => match op.data {
+                linker::LinkedTokenData::JumpAddr(ptr) => {
+                    self.pop_into(A);
+                    self.emit(&[Instr::LocalGet(A), Instr::Op(Op::I64Eqz), Instr::If(None)]);
+                    self.jump(ptr, 1);
+                    self.emit(&[Instr::End]);
+                }
+                _ => panic!(),
+            },
+        }
+    }
+
+    fn write_intrinsic(&mut self, op: &linker::LinkedToken, intrinsic: Intrinsic) {
+        match intrinsic {
+            Intrinsic::Dump => {
+                self.pop_into(A);
+                self.emit(&[Instr::LocalGet(A), Instr::Call(DUMP)]);
+            }
+            Intrinsic::Puts | Intrinsic::EPuts => {
+                self.pop_into(A);
+                self.pop_into(B);
+                let fd = if matches!(intrinsic, Intrinsic::Puts) { 1 } else { 2 };
+                self.emit(&[
+                    Instr::I32Const(fd),
+                    Instr::LocalGet(A),
+                    Instr::Op(Op::I32WrapI64),
+                    Instr::LocalGet(B),
+                    Instr::Op(Op::I32WrapI64),
+                    Instr::Call(WRITE),
+                ]);
+            }
+            Intrinsic::Drop => {
+                self.emit(&[Instr::LocalGet(SP), Instr::I32Const(8), Instr::Op(Op::I32Sub), Instr::LocalSet(SP)]);
+            }
+            Intrinsic::Dup | Intrinsic::Over => {
+                let offset = if matches!(intrinsic, Intrinsic::Dup) { 8 } else { 16 };
+                self.push(&[Instr::LocalGet(SP), Instr::I32Const(offset), Instr::Op(Op::I32Sub), Instr::Mem(MemOp::I64Load, 0)]);
+            }
+            Intrinsic::Swap => {
+                self.pop_into(A);
+                self.pop_into(B);
+                self.push(&[Instr::LocalGet(A)]);
+                self.push(&[Instr::LocalGet(B)]);
+            }
+            Intrinsic::Rot => {
+                self.pop_into(A);
+                self.pop_into(B);
+                self.pop_into(C);
+                self.push(&[Instr::LocalGet(B)]);
+                self.push(&[Instr::LocalGet(A)]);
+                self.push(&[Instr::LocalGet(C)]);
+            }
+            Intrinsic::Add => self.binary(&[Op::I64Add]),
+            Intrinsic::Subtract => self.binary(&[Op::I64Sub]),
+            Intrinsic::Multiply => self.binary(&[Op::I64Mul]),
+            Intrinsic::Divide => self.binary(&[Op::I64DivU]),
+            Intrinsic::Modulo => self.binary(&[Op::I64RemU]),
+            //Dividing the smallest integer by -1 traps in WebAssembly, it wraps around like in the simulator
+            Intrinsic::DivideSigned | Intrinsic::ModuloSigned => {
+                self.pop_into(A);
+                self.pop_into(B);
+                let (special, division) = if matches!(intrinsic, Intrinsic::DivideSigned) {
+                    (vec![Instr::I64Const(0), Instr::LocalGet(B), Instr::Op(Op::I64Sub)], Op::I64DivS)
+                } else {
+                    (vec![Instr::I64Const(0)], Op::I64RemS)
+                };
+                let mut value = vec![Instr::LocalGet(A), Instr::I64Const(-1), Instr::Op(Op::I64Eq), Instr::If(Some(ValType::I64))];
+                value.extend(special);
+                value.extend([Instr::Else, Instr::LocalGet(B), Instr::LocalGet(A), Instr::Op(division), Instr::End]);
+                self.push(&value);
+            }
+            //Shift counts are taken modulo 64 by WebAssembly, like the simulator does
+            Intrinsic::ShiftLeft => self.binary(&[Op::I64Shl]),
+            Intrinsic::ShiftRight => self.binary(&[Op::I64ShrU]),
+            Intrinsic::ShiftRightSigned => self.binary(&[Op::I64ShrS]),
+            Intrinsic::BitAnd => self.binary(&[Op::I64And]),
+            Intrinsic::BitOr => self.binary(&[Op::I64Or]),
+            Intrinsic::BitXor => self.binary(&[Op::I64Xor]),
+            Intrinsic::Equals => self.binary(&[Op::I64Eq, Op::I64ExtendI32U]),
+            Intrinsic::NotEquals => self.binary(&[Op::I64Ne, Op::I64ExtendI32U]),
+            Intrinsic::Less => self.binary(&[Op::I64LtU, Op::I64ExtendI32U]),
+            Intrinsic::Greater => self.binary(&[Op::I64GtU, Op::I64ExtendI32U]),
+            Intrinsic::LessOrEqual => self.binary(&[Op::I64LeU, Op::I64ExtendI32U]),
+            Intrinsic::GreaterOrEqual => self.binary(&[Op::I64GeU, Op::I64ExtendI32U]),
+            Intrinsic::LessSigned => self.binary(&[Op::I64LtS, Op::I64ExtendI32U]),
+            Intrinsic::GreaterSigned => self.binary(&[Op::I64GtS, Op::I64ExtendI32U]),
+            Intrinsic::LessOrEqualSigned => self.binary(&[Op::I64LeS, Op::I64ExtendI32U]),
+            Intrinsic::GreaterOrEqualSigned => self.binary(&[Op::I64GeS, Op::I64ExtendI32U]),
+            Intrinsic::CastInt | Intrinsic::CastSInt | Intrinsic::CastPtr => {}
+            Intrinsic::Store8 | Intrinsic::Store16 | Intrinsic::Store32 | Intrinsic::Store64 => {
+                let store = match intrinsic {
+                    Intrinsic::Store8 => MemOp::I64Store8,
+                    Intrinsic::Store16 => MemOp::I64Store16,
+                    Intrinsic::Store32 => MemOp::I64Store32,
+                    _ => MemOp::I64Store,
+                };
+                self.pop_into(A);
+                self.pop_into(B);
+                self.emit(&[Instr::LocalGet(A), Instr::Op(Op::I32WrapI64), Instr::LocalGet(B), Instr::Mem(store, 0)]);
+            }
+            Intrinsic::Load8 | Intrinsic::Load16 | Intrinsic::Load32 | Intrinsic::Load64 => {
+                let load = match intrinsic {
+                    Intrinsic::Load8 => MemOp::I64Load8U,
+                    Intrinsic::Load16 => MemOp::I64Load16U,
+                    Intrinsic::Load32 => MemOp::I64Load32U,
+                    _ => MemOp::I64Load,
+                };
+                self.pop_into(A);
+                self.push(&[Instr::LocalGet(A), Instr::Op(Op::I32WrapI64), Instr::Mem(load, 0)]);
+            }
+            Intrinsic::Exit => {
+                self.pop_into(A);
+                self.emit(&[Instr::LocalGet(A), Instr::Op(Op::I32WrapI64), Instr::Call(EXIT), Instr::Return]);
+            }
+            Intrinsic::Argc | Intrinsic::Argv | Intrinsic::Envp => {
+                eprintln!("{}: ERROR: '{}' is not supported by wasm", op.word, op.word.txt);
+                std::process::exit(1);
+            }
+            Intrinsic::Syscall0 | Intrinsic::Syscall1 | Intrinsic::Syscall2 | Intrinsic::Syscall3 | Intrinsic::Syscall4 | Intrinsic::Syscall5 | Intrinsic::Syscall6 => {
+                eprintln!("{}: ERROR: '{}' is not supported by wasm, syscalls are only available on linux", op.word, op.word.txt);
+                std::process::exit(1);
+            }
+        }
+    }
+}
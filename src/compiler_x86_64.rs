@@ -0,0 +1,1247 @@
+use crate::checker::DataType;
+use crate::compiler::{CompileOptions, OUT_BUFFER_SIZE};
+use crate::linker::{Instruction, LinkerContext};
+use crate::simulator::RUNTIME_ERROR_EXIT_CODE;
+use crate::tokenizer::Intrinsic;
+use crate::x86_64::Reg::*;
+use crate::x86_64::{AluOp, Asm, Cond, Mem, Program, Reg, ShiftOp, Size, UnaryOp};
+use crate::{compiler, compiler_string, linker, tokenizer};
+use std::collections::HashSet;
+
+const SYSCALL_ARG_REGISTERS: [Reg; 6] = [Rdi, Rsi, Rdx, R10, R8, R9];
+const EXTERN_ARG_REGISTERS: [Reg; 6] = [Rdi, Rsi, Rdx, Rcx, R8, R9];
+//Registers holding the top of the data stack, the instructions only use rax, rbx, rcx and rdx as scratch registers
+const CACHE_REGISTERS: [Reg; 4] = [R8, R9, R10, R11];
+const SIGSEGV: i64 = 11;
+const SA_RESTORER: i64 = 0x04000000;
+
+/// Operating system targeted by an x86-64 backend. Only the entry point, the runtime and the intrinsics talking to the
+/// system differ, the instruction selection of everything else is shared by all x86-64 backends.
+#[derive(Clone, Copy)]
+pub enum Os {
+    Linux,
+    Windows,
+}
+
+/// How a program is linked: on its own, with libc when it calls external functions, or into a C program as a library.
+#[derive(Clone, Copy, PartialEq)]
+enum Linkage {
+    Static,
+    Libc,
+    Library,
+}
+
+/// A program being generated along with the state carried from one instruction to the next.
+pub struct Generator {
+    pub program: Program,
+    cache: StackCache,
+    jump_targets: HashSet<usize>,
+    checks: Option<Checks>,
+    linkage: Linkage,
+    externs: Vec<linker::ExternRef>,
+    exports: Vec<Export>,
+    //Pointer right after the last instruction
+    end_ptr: usize,
+}
+
+/// A function of a library callable from C, through a wrapper moving its arguments to the data stack.
+struct Export {
+    name: String,
+    location: String,
+    ptr: usize,
+    ins: Vec<DataType>,
+    outs: usize,
+}
+
+/// Runtime checks of `--checked` programs. A failing check jumps to a stub which reports the word of the failing
+/// instruction like the simulator does, and exits with the same code. The stubs are written after the program.
+struct Checks {
+    //Label and message of every stub
+    stubs: Vec<(String, String)>,
+    mem_size: usize,
+    //Size of the strings at the start of the read-only data, which may be loaded from
+    strings_size: usize,
+    //Whether a SIGSEGV handler reports the accesses faulting outside of the program image
+    fault_handler: bool,
+}
+
+#[derive(Clone, Copy)]
+enum RuntimeCheck {
+    StackUnderflow,
+    DivisionByZero,
+    CallstackOverflow,
+    MemoryOutOfBounds(usize),
+}
+
+impl Checks {
+    /// Returns the label of the stub reporting the error at the instruction, adding the stub the first time.
+    fn fail(&mut self, op: &linker::LinkedToken, check: RuntimeCheck) -> String {
+        let label = check_label(op, check);
+        if !self.stubs.iter().any(|(x, _)| *x == label) {
+            let error = match check {
+                RuntimeCheck::StackUnderflow => "Stack underflow".to_string(),
+                RuntimeCheck::DivisionByZero => "Division by zero".to_string(),
+                RuntimeCheck::CallstackOverflow => "Callstack overflow".to_string(),
+                RuntimeCheck::MemoryOutOfBounds(width) => format!("Memory access of {} byte(s) is out of bounds", width),
+            };
+            self.stubs.push((label.clone(), format!("{}: ERROR: {} in '{}'\n", op.word, error, op.word.txt)));
+        }
+        label
+    }
+}
+
+fn check_label(op: &linker::LinkedToken, check: RuntimeCheck) -> String {
+    let suffix = match check {
+        RuntimeCheck::StackUnderflow => "underflow",
+        RuntimeCheck::DivisionByZero => "div_zero",
+        RuntimeCheck::CallstackOverflow => "callstack_overflow",
+        RuntimeCheck::MemoryOutOfBounds(_) => "out_of_bounds",
+    };
+    format!("addr_{}_{}", op.self_ptr, suffix)
+}
+
+/// Keeps the top values of the data stack in registers, the values below them stay on the stack in memory. The cache is
+/// spilled when it is full and before everything expecting the whole stack in memory: jump targets, jumps, calls and
+/// intrinsics calling into the runtime or the system.
+#[derive(Default)]
+struct StackCache {
+    //The last slot is the top of the stack
+    cached: Vec<Slot>,
+    //Registers popped or allocated by the current instruction, they are not reused until it is lowered
+    live: Vec<Reg>,
+    //In checked mode the label jumped to when the current instruction pops from an empty stack, and whether it did
+    underflow: Option<String>,
+    underflow_used: bool,
+}
+
+/// A cached stack value, constants fitting a sign extended 32 bit immediate are only put in a register when needed.
+#[derive(Clone, Copy, PartialEq)]
+enum Slot {
+    Reg(Reg),
+    Imm(i32),
+}
+
+impl StackCache {
+    fn alloc(&mut self, asm: &mut Vec<Asm>) -> Reg {
+        loop {
+            if let Some(reg) = CACHE_REGISTERS.iter().find(|x| !self.cached.contains(&Slot::Reg(**x)) && !self.live.contains(x)) {
+                self.live.push(*reg);
+                return *reg;
+            }
+            let bottom = self.cached.remove(0);
+            write_spill(asm, bottom);
+        }
+    }
+
+    fn pop(&mut self, asm: &mut Vec<Asm>) -> Reg {
+        match self.pop_slot(asm) {
+            Slot::Reg(reg) => reg,
+            Slot::Imm(val) => {
+                let reg = self.alloc(asm);
+                asm.push(Asm::MovImm(reg, val as i64));
+                reg
+            }
+        }
+    }
+
+    fn pop_slot(&mut self, asm: &mut Vec<Asm>) -> Slot {
+        match self.cached.pop() {
+            Some(Slot::Reg(reg)) => {
+                self.live.push(reg);
+                Slot::Reg(reg)
+            }
+            Some(imm) => imm,
+            None => {
+                let reg = self.alloc(asm);
+                if let Some(label) = &self.underflow {
+                    asm.push(Asm::AluLoad(AluOp::Cmp, Rsp, Mem::symbol("stack_base")));
+                    asm.push(Asm::Jcc(Cond::AE, label.clone()));
+                    self.underflow_used = true;
+                }
+                asm.push(Asm::Pop(reg));
+                Slot::Reg(reg)
+            }
+        }
+    }
+
+    //Values computed in a scratch register are moved to a cache register, the operands of the instruction are dead by then
+    fn push(&mut self, asm: &mut Vec<Asm>, reg: Reg) {
+        if CACHE_REGISTERS.contains(&reg) {
+            self.live.retain(|x| *x != reg);
+            self.cached.push(Slot::Reg(reg));
+        } else {
+            self.live.clear();
+            let cache_reg = self.alloc(asm);
+            asm.push(Asm::Mov(cache_reg, reg));
+            self.push(asm, cache_reg);
+        }
+    }
+
+    fn push_slot(&mut self, asm: &mut Vec<Asm>, slot: Slot) {
+        match slot {
+            Slot::Reg(reg) => self.push(asm, reg),
+            Slot::Imm(_) => self.cached.push(slot),
+        }
+    }
+
+    fn push_const(&mut self, asm: &mut Vec<Asm>, val: i64) {
+        match i32::try_from(val) {
+            Ok(imm) => self.cached.push(Slot::Imm(imm)),
+            Err(_) => {
+                let reg = self.alloc(asm);
+                asm.push(Asm::MovImm(reg, val));
+                self.push(asm, reg);
+            }
+        }
+    }
+
+    //Constants are shared, registers are copied
+    fn push_copy(&mut self, asm: &mut Vec<Asm>, slot: Slot) {
+        match slot {
+            Slot::Reg(reg) => {
+                let copy = self.alloc(asm);
+                asm.push(Asm::Mov(copy, reg));
+                self.push(asm, copy);
+            }
+            Slot::Imm(_) => self.cached.push(slot),
+        }
+    }
+
+    fn flush(&mut self, asm: &mut Vec<Asm>) {
+        for slot in self.cached.drain(..) {
+            write_spill(asm, slot);
+        }
+    }
+}
+
+fn write_spill(asm: &mut Vec<Asm>, slot: Slot) {
+    match slot {
+        Slot::Reg(reg) => asm.push(Asm::Push(reg)),
+        Slot::Imm(val) => asm.push(Asm::PushImm(val)),
+    }
+}
+
+/// Starts the program with its data and the entry point, which sets up the callstack and jumps to the first instruction.
+/// Programs linked with libc start at `main` instead of `_start`, so the C runtime is initialised before them.
+/// Libraries have no entry point, they are entered through the wrappers of their exported functions.
+pub fn prologue(os: Os, ctx: &LinkerContext, options: &CompileOptions) -> Generator {
+    let mut rodata: Vec<(String, Vec<u8>)> = vec![];
+    for (index, val) in ctx.strings.iter().enumerate() {
+        rodata.push((format!("str_{}", index), compiler::string_bytes(val)));
+    }
+    let mut bss: Vec<(String, usize)> = vec![];
+    let mut externs: Vec<String> = vec![];
+    let linkage = match os {
+        Os::Linux if options.lib => Linkage::Library,
+        Os::Linux if !ctx.externs.is_empty() => Linkage::Libc,
+        _ => Linkage::Static,
+    };
+    match os {
+        Os::Linux => {
+            bss.push(("args_ptr".to_string(), 8));
+            if linkage != Linkage::Static {
+                externs = vec!["exit".to_string(), "fflush".to_string()];
+            }
+        }
+        Os::Windows => {
+            rodata.push(("newline".to_string(), vec![13, 10, 0]));
+            bss.push(("itoabuffer".to_string(), 20));
+            externs = vec!["GetStdHandle".to_string(), "WriteFile".to_string(), "ExitProcess".to_string()];
+        }
+    }
+    bss.extend([
+        ("out_len".to_string(), 8),
+        ("out_buf".to_string(), OUT_BUFFER_SIZE),
+        ("callstack_rsp".to_string(), 8),
+        ("callstack".to_string(), 65536),
+        ("callstack_top".to_string(), 0),
+        ("mem".to_string(), ctx.mem_size),
+    ]);
+    for extern_ref in &ctx.externs {
+        if !externs.contains(&extern_ref.name) {
+            externs.push(extern_ref.name.clone());
+        }
+    }
+    let entry = match linkage {
+        Linkage::Static => Some("_start".to_string()),
+        Linkage::Libc => Some("main".to_string()),
+        Linkage::Library => None,
+    };
+    let mut asm: Vec<Asm> = vec![];
+    let fault_handler = options.checked && matches!(os, Os::Linux);
+    if fault_handler {
+        asm.push(Asm::Label("image_start".to_string()));
+        bss.extend([("fault_stub".to_string(), 8), ("default_sigaction".to_string(), 32)]);
+    }
+    if let Some(entry) = &entry {
+        asm.push(Asm::Label(entry.clone()));
+        match os {
+            //main gets argv in rsi, which points right after argc on the initial stack
+            Os::Linux if linkage == Linkage::Libc => {
+                asm.push(Asm::Lea(Rax, Mem::base_disp(Rsi, -8)));
+                asm.push(Asm::Store(Size::Qword, Mem::symbol("args_ptr"), Rax));
+            }
+            Os::Linux => asm.push(Asm::Store(Size::Qword, Mem::symbol("args_ptr"), Rsp)),
+            Os::Windows => {}
+        }
+        if fault_handler {
+            write_fault_handler_setup(&mut asm);
+        }
+        if options.checked {
+            //Popping while the data stack pointer is at its initial value is an underflow
+            bss.push(("stack_base".to_string(), 8));
+            asm.push(Asm::Store(Size::Qword, Mem::symbol("stack_base"), Rsp));
+        }
+        asm.push(Asm::LeaSymbol(Rax, "callstack_top".to_string()));
+        asm.push(Asm::Store(Size::Qword, Mem::symbol("callstack_rsp"), Rax));
+        asm.push(Asm::Jmp("addr_0".to_string()));
+    }
+    let mut exports: Vec<Export> = vec![];
+    if linkage == Linkage::Library {
+        for (name, function) in ctx.functions.iter().filter(|(_, function)| function.export) {
+            exports.push(Export {
+                name: name.clone(),
+                location: ctx.result[function.ptr].word.to_string(),
+                ptr: function.ptr,
+                ins: function.ins.iter().map(|x| x.typ).collect(),
+                outs: function.outs.len(),
+            });
+        }
+        exports.sort_by_key(|x| x.ptr);
+    }
+    let mut jump_targets: HashSet<usize> = HashSet::new();
+    for op in &ctx.result {
+        if let linker::LinkedTokenData::JumpAddr(ptr) = op.data {
+            jump_targets.insert(ptr);
+        }
+    }
+    Generator {
+        program: Program {
+            entry,
+            exports: exports.iter().map(|x| x.name.clone()).collect(),
+            externs,
+            bss,
+            rodata,
+            text: asm,
+        },
+        cache: StackCache::default(),
+        jump_targets,
+        checks: options.checked.then(|| Checks {
+            stubs: vec![],
+            mem_size: ctx.mem_size,
+            strings_size: ctx.strings.iter().map(|x| x.len() + 1).sum(),
+            fault_handler,
+        }),
+        linkage,
+        externs: ctx.externs.clone(),
+        exports,
+        end_ptr: ctx.result.len(),
+    }
+}
+
+/// Adds `print`, which writes an unsigned number and a newline, and the buffered standard output used by it.
+/// In checked mode the routines used by the runtime checks are added as well.
+pub fn runtime(os: Os, generator: &mut Generator) {
+    match os {
+        Os::Linux => write_linux_runtime(&mut generator.program.text, generator.linkage != Linkage::Static),
+        Os::Windows => write_windows_runtime(&mut generator.program.text),
+    }
+    if let Some(checks) = &generator.checks {
+        write_checks_runtime(os, &mut generator.program.text, checks);
+    }
+}
+
+pub fn lower(os: Os, generator: &mut Generator, op: &linker::LinkedToken) {
+    let asm = &mut generator.program.text;
+    if generator.jump_targets.contains(&op.self_ptr) {
+        generator.cache.flush(asm);
+    }
+    asm.push(Asm::Label(format!("addr_{}", op.self_ptr)));
+    asm.push(Asm::Comment(compiler_string::stringify_op(op)));
+    generator.cache.underflow = generator.checks.as_ref().map(|_| check_label(op, RuntimeCheck::StackUnderflow));
+    generator.cache.underflow_used = false;
+    write_op(os, asm, &mut generator.cache, &mut generator.checks, generator.linkage, &generator.externs, op);
+    if generator.cache.underflow_used
+        && let Some(checks) = &mut generator.checks
+    {
+        checks.fail(op, RuntimeCheck::StackUnderflow);
+    }
+}
+
+/// Flushes standard output and exits with code 0 once the last instruction is done, followed by the stubs of the checks
+/// and the wrappers of the exported functions.
+pub fn epilogue(os: Os, generator: &mut Generator) {
+    let asm = &mut generator.program.text;
+    generator.cache.flush(asm);
+    asm.push(Asm::Label(format!("addr_{}", generator.end_ptr)));
+    asm.push(Asm::Label("addr_exit".to_string()));
+    asm.push(Asm::Call("out_flush".to_string()));
+    match os {
+        //exit also flushes the output buffered by C code
+        Os::Linux if generator.linkage != Linkage::Static => {
+            asm.push(Asm::Alu(AluOp::Xor, Rdi, Rdi));
+            asm.push(Asm::AluImm(AluOp::And, Rsp, -16));
+            asm.push(Asm::Call("exit".to_string()));
+        }
+        Os::Linux => {
+            asm.push(Asm::MovImm(Rax, 60));
+            asm.push(Asm::MovImm(Rdi, 0));
+            asm.push(Asm::Syscall);
+        }
+        Os::Windows => {
+            asm.push(Asm::AluImm(AluOp::Sub, Rsp, 8));
+            asm.push(Asm::Alu(AluOp::Xor, Rcx, Rcx));
+            asm.push(Asm::Call("ExitProcess".to_string()));
+        }
+    }
+    if let Some(checks) = &generator.checks {
+        //runtime_error writes the message in rsi/rdx on Linux and rdx/r8 on Windows, like out_write
+        let (message_reg, len_reg) = match os {
+            Os::Linux => (Rsi, Rdx),
+            Os::Windows => (Rdx, R8),
+        };
+        for (index, (label, message)) in checks.stubs.iter().enumerate() {
+            let symbol = format!("check_msg_{}", index);
+            generator.program.rodata.push((symbol.clone(), message.as_bytes().to_vec()));
+            asm.push(Asm::Label(label.clone()));
+            asm.push(Asm::LeaSymbol(message_reg, symbol));
+            asm.push(Asm::MovImm(len_reg, message.len() as i64));
+            asm.push(Asm::Jmp("runtime_error".to_string()));
+        }
+        if checks.fault_handler {
+            generator.program.bss.push(("image_end".to_string(), 0));
+        }
+    }
+    write_exports(generator);
+}
+
+/// Writes a wrapper for each exported function, which moves the arguments from the registers of the System V calling
+/// convention to the data stack, calls the function and returns its output in rax.
+fn write_exports(generator: &mut Generator) {
+    let program = &mut generator.program;
+    let mut symbols: HashSet<&str> = program.externs.iter().map(|x| x.as_str()).collect();
+    symbols.extend(program.bss.iter().map(|(name, _)| name.as_str()));
+    symbols.extend(program.rodata.iter().map(|(name, _)| name.as_str()));
+    for asm in &program.text {
+        if let Asm::Label(label) = asm {
+            symbols.insert(label);
+        }
+    }
+    for export in &generator.exports {
+        if symbols.contains(export.name.as_str()) {
+            eprintln!(
+                "{}: ERROR: Exported function name '{}' is already used by a symbol of the compiled library",
+                export.location, export.name
+            );
+            std::process::exit(1);
+        }
+    }
+    let asm = &mut program.text;
+    for export in &generator.exports {
+        asm.push(Asm::Label(export.name.clone()));
+        //rbx is preserved for the caller, the code of the program uses it as a scratch register
+        asm.push(Asm::Push(Rbx));
+        for (reg, typ) in EXTERN_ARG_REGISTERS.iter().zip(&export.ins) {
+            //Only the lowest byte of a bool argument is defined by the calling convention
+            if *typ == DataType::BOOL {
+                asm.push(Asm::AluImm(AluOp::And, *reg, 0xff));
+            }
+            asm.push(Asm::Push(*reg));
+        }
+        //There is no entry point to set up the callstack, it is set up by the first call into the library
+        let ready = format!("{}_ready", export.name);
+        asm.push(Asm::Load(Size::Qword, Rax, Mem::symbol("callstack_rsp")));
+        asm.push(Asm::Alu(AluOp::Test, Rax, Rax));
+        asm.push(Asm::Jcc(Cond::NE, ready.clone()));
+        asm.push(Asm::LeaSymbol(Rax, "callstack_top".to_string()));
+        asm.push(Asm::Store(Size::Qword, Mem::symbol("callstack_rsp"), Rax));
+        asm.push(Asm::Label(ready));
+        asm.push(Asm::Mov(Rax, Rsp));
+        asm.push(Asm::Load(Size::Qword, Rsp, Mem::symbol("callstack_rsp")));
+        asm.push(Asm::Call(format!("addr_{}", export.ptr)));
+        asm.push(Asm::Store(Size::Qword, Mem::symbol("callstack_rsp"), Rsp));
+        asm.push(Asm::Mov(Rsp, Rax));
+        asm.push(Asm::Call("out_flush".to_string()));
+        if export.outs > 0 {
+            asm.push(Asm::Pop(Rax));
+        }
+        asm.push(Asm::Pop(Rbx));
+        asm.push(Asm::Ret);
+    }
+}
+
+/// Installs `segv_handler` for SIGSEGV, restoring the default action when it returns. The kernel only delivers signals
+/// to handlers with a restorer, which makes the rt_sigreturn syscall the C library would make.
+fn write_fault_handler_setup(asm: &mut Vec<Asm>) {
+    asm.push(Asm::AluImm(AluOp::Sub, Rsp, 32));
+    asm.push(Asm::LeaSymbol(Rax, "segv_handler".to_string()));
+    asm.push(Asm::Store(Size::Qword, Mem::base(Rsp), Rax));
+    asm.push(Asm::MovImm(Rax, SA_RESTORER));
+    asm.push(Asm::Store(Size::Qword, Mem::base_disp(Rsp, 8), Rax));
+    asm.push(Asm::LeaSymbol(Rax, "signal_return".to_string()));
+    asm.push(Asm::Store(Size::Qword, Mem::base_disp(Rsp, 16), Rax));
+    asm.push(Asm::StoreImm(Size::Qword, Mem::base_disp(Rsp, 24), 0));
+    asm.push(Asm::Mov(Rsi, Rsp));
+    write_sigaction(asm);
+    asm.push(Asm::AluImm(AluOp::Add, Rsp, 32));
+}
+
+//Sets the action of SIGSEGV to the one at rsi with the rt_sigaction syscall
+fn write_sigaction(asm: &mut Vec<Asm>) {
+    asm.push(Asm::MovImm(Rax, 13));
+    asm.push(Asm::MovImm(Rdi, SIGSEGV));
+    asm.push(Asm::MovImm(Rdx, 0));
+    asm.push(Asm::MovImm(R10, 8));
+    asm.push(Asm::Syscall);
+}
+
+/// Adds `runtime_error`, which writes a message to standard error and exits, and `check_access`, which sets rdx to zero
+/// when the access of rcx bytes at rax lies within the memory or the strings. On Linux accesses outside of the program
+/// image are valid as well, they may go to memory from syscalls or C, and faulting ones are reported by `segv_handler`.
+fn write_checks_runtime(os: Os, asm: &mut Vec<Asm>, checks: &Checks) {
+    asm.push(Asm::Label("runtime_error".to_string()));
+    match os {
+        Os::Linux => {
+            asm.push(Asm::Push(Rsi));
+            asm.push(Asm::Push(Rdx));
+            asm.push(Asm::Call("out_flush".to_string()));
+            asm.push(Asm::Pop(Rdx));
+            asm.push(Asm::Pop(Rsi));
+            asm.push(Asm::MovImm(Rax, 1));
+            asm.push(Asm::MovImm(Rdi, 2));
+            asm.push(Asm::Syscall);
+            asm.push(Asm::MovImm(Rax, 60));
+            asm.push(Asm::MovImm(Rdi, RUNTIME_ERROR_EXIT_CODE as i64));
+            asm.push(Asm::Syscall);
+        }
+        Os::Windows => {
+            asm.push(Asm::Push(Rdx));
+            asm.push(Asm::Push(R8));
+            asm.push(Asm::Call("out_flush".to_string()));
+            asm.push(Asm::Pop(R8));
+            asm.push(Asm::Pop(Rdx));
+            asm.push(Asm::MovImm(Rcx, -12));
+            asm.push(Asm::Call("write_string".to_string()));
+            asm.push(Asm::MovImm(Rcx, RUNTIME_ERROR_EXIT_CODE as i64));
+            asm.push(Asm::AluImm(AluOp::And, Rsp, -16));
+            asm.push(Asm::AluImm(AluOp::Sub, Rsp, 32));
+            asm.push(Asm::Call("ExitProcess".to_string()));
+        }
+    }
+    //Every region is checked as start <= rax <= end - rcx, which can not overflow like rax + rcx <= end
+    asm.push(Asm::Label("check_access".to_string()));
+    let mut regions = vec![("mem", checks.mem_size)];
+    if checks.strings_size > 0 {
+        regions.push(("str_0", checks.strings_size));
+    }
+    for (index, (symbol, size)) in regions.iter().enumerate() {
+        let next = format!(".region{}", index + 1);
+        asm.push(Asm::Lea(Rdx, Mem::symbol(symbol)));
+        asm.push(Asm::Alu(AluOp::Cmp, Rax, Rdx));
+        asm.push(Asm::Jcc(Cond::B, next.clone()));
+        asm.push(Asm::Lea(
+            Rdx,
+            Mem {
+                disp: *size as i32,
+                ..Mem::symbol(symbol)
+            },
+        ));
+        asm.push(Asm::Alu(AluOp::Sub, Rdx, Rcx));
+        asm.push(Asm::Alu(AluOp::Cmp, Rax, Rdx));
+        asm.push(Asm::Jcc(Cond::BE, ".valid".to_string()));
+        asm.push(Asm::Label(next));
+    }
+    if checks.fault_handler {
+        //Valid when rax >= image_end or rax <= image_start - rcx, the program image ends with the zeroed data
+        asm.push(Asm::LeaSymbol(Rdx, "image_end".to_string()));
+        asm.push(Asm::Alu(AluOp::Cmp, Rax, Rdx));
+        asm.push(Asm::Jcc(Cond::AE, ".valid".to_string()));
+        asm.push(Asm::LeaSymbol(Rdx, "image_start".to_string()));
+        asm.push(Asm::Alu(AluOp::Sub, Rdx, Rcx));
+        asm.push(Asm::Alu(AluOp::Cmp, Rax, Rdx));
+        asm.push(Asm::Jcc(Cond::BE, ".valid".to_string()));
+    }
+    asm.push(Asm::MovImm(Rdx, 1));
+    asm.push(Asm::Ret);
+    asm.push(Asm::Label(".valid".to_string()));
+    asm.push(Asm::Alu(AluOp::Xor, Rdx, Rdx));
+    asm.push(Asm::Ret);
+    if checks.fault_handler {
+        //Jumps to the stub of the access being made, other faults happen again with the default action after returning
+        asm.push(Asm::Label("segv_handler".to_string()));
+        asm.push(Asm::Load(Size::Qword, Rax, Mem::symbol("fault_stub")));
+        asm.push(Asm::Alu(AluOp::Test, Rax, Rax));
+        asm.push(Asm::Jcc(Cond::E, ".unknown_fault".to_string()));
+        asm.push(Asm::Push(Rax));
+        asm.push(Asm::Ret);
+        asm.push(Asm::Label(".unknown_fault".to_string()));
+        asm.push(Asm::LeaSymbol(Rsi, "default_sigaction".to_string()));
+        write_sigaction(asm);
+        asm.push(Asm::Ret);
+        asm.push(Asm::Label("signal_return".to_string()));
+        asm.push(Asm::MovImm(Rax, 15));
+        asm.push(Asm::Syscall);
+    }
+}
+
+fn write_linux_runtime(asm: &mut Vec<Asm>, libc: bool) {
+    //Prints rdi as unsigned decimal followed by a newline
+    asm.push(Asm::Label("print".to_string()));
+    asm.push(Asm::MovImm(R9, -3689348814741910323));
+    asm.push(Asm::AluImm(AluOp::Sub, Rsp, 40));
+    asm.push(Asm::StoreImm(Size::Byte, Mem::base_disp(Rsp, 31), 10));
+    asm.push(Asm::Lea(Rcx, Mem::base_disp(Rsp, 30)));
+    asm.push(Asm::Label(".L2".to_string()));
+    asm.push(Asm::Mov(Rax, Rdi));
+    asm.push(Asm::Lea(R8, Mem::base_disp(Rsp, 32)));
+    asm.push(Asm::Unary(UnaryOp::Mul, R9));
+    asm.push(Asm::Mov(Rax, Rdi));
+    asm.push(Asm::Alu(AluOp::Sub, R8, Rcx));
+    asm.push(Asm::ShiftImm(ShiftOp::Shr, Rdx, 3));
+    asm.push(Asm::Lea(Rsi, Mem::base_index(Rdx, Rdx, 4, 0)));
+    asm.push(Asm::Alu(AluOp::Add, Rsi, Rsi));
+    asm.push(Asm::Alu(AluOp::Sub, Rax, Rsi));
+    asm.push(Asm::AluImm(AluOp::Add, Rax, 48));
+    asm.push(Asm::Store(Size::Byte, Mem::base(Rcx), Rax));
+    asm.push(Asm::Mov(Rax, Rdi));
+    asm.push(Asm::Mov(Rdi, Rdx));
+    asm.push(Asm::Mov(Rdx, Rcx));
+    asm.push(Asm::AluImm(AluOp::Sub, Rcx, 1));
+    asm.push(Asm::AluImm(AluOp::Cmp, Rax, 9));
+    asm.push(Asm::Jcc(Cond::A, ".L2".to_string()));
+    asm.push(Asm::Lea(Rax, Mem::base_disp(Rsp, 32)));
+    asm.push(Asm::Alu(AluOp::Sub, Rdx, Rax));
+    asm.push(Asm::Lea(Rsi, Mem::base_index(Rsp, Rdx, 1, 32)));
+    asm.push(Asm::Mov(Rdx, R8));
+    asm.push(Asm::Call("out_write".to_string()));
+    asm.push(Asm::AluImm(AluOp::Add, Rsp, 40));
+    asm.push(Asm::Ret);
+    //Standard output is buffered, out_write appends rdx bytes at rsi to the buffer and out_flush writes it out
+    asm.push(Asm::Label("out_write".to_string()));
+    asm.push(Asm::Load(Size::Qword, Rax, Mem::symbol("out_len")));
+    asm.push(Asm::Alu(AluOp::Add, Rax, Rdx));
+    asm.push(Asm::AluImm(AluOp::Cmp, Rax, OUT_BUFFER_SIZE as i32));
+    asm.push(Asm::Jcc(Cond::BE, ".copy".to_string()));
+    asm.push(Asm::Push(Rsi));
+    asm.push(Asm::Push(Rdx));
+    asm.push(Asm::Call("out_flush".to_string()));
+    asm.push(Asm::Pop(Rdx));
+    asm.push(Asm::Pop(Rsi));
+    asm.push(Asm::AluImm(AluOp::Cmp, Rdx, OUT_BUFFER_SIZE as i32));
+    asm.push(Asm::Jcc(Cond::BE, ".copy".to_string()));
+    asm.push(Asm::MovImm(Rax, 1));
+    asm.push(Asm::MovImm(Rdi, 1));
+    asm.push(Asm::Syscall);
+    asm.push(Asm::Ret);
+    asm.push(Asm::Label(".copy".to_string()));
+    asm.push(Asm::LeaSymbol(Rdi, "out_buf".to_string()));
+    asm.push(Asm::AluLoad(AluOp::Add, Rdi, Mem::symbol("out_len")));
+    asm.push(Asm::Mov(Rcx, Rdx));
+    asm.push(Asm::AluStore(AluOp::Add, Mem::symbol("out_len"), Rdx));
+    asm.push(Asm::RepMovsb);
+    asm.push(Asm::Ret);
+    asm.push(Asm::Label("out_flush".to_string()));
+    asm.push(Asm::Load(Size::Qword, Rdx, Mem::symbol("out_len")));
+    asm.push(Asm::Alu(AluOp::Test, Rdx, Rdx));
+    asm.push(Asm::Jcc(Cond::E, ".done".to_string()));
+    if libc {
+        //Output written by C code before this output is buffered by libc, it has to be written first
+        asm.push(Asm::Mov(Rbx, Rsp));
+        asm.push(Asm::AluImm(AluOp::And, Rsp, -16));
+        asm.push(Asm::Alu(AluOp::Xor, Rdi, Rdi));
+        asm.push(Asm::Call("fflush".to_string()));
+        asm.push(Asm::Mov(Rsp, Rbx));
+        asm.push(Asm::Load(Size::Qword, Rdx, Mem::symbol("out_len")));
+    }
+    asm.push(Asm::StoreImm(Size::Qword, Mem::symbol("out_len"), 0));
+    asm.push(Asm::MovImm(Rax, 1));
+    asm.push(Asm::MovImm(Rdi, 1));
+    asm.push(Asm::LeaSymbol(Rsi, "out_buf".to_string()));
+    asm.push(Asm::Syscall);
+    asm.push(Asm::Label(".done".to_string()));
+    asm.push(Asm::Ret);
+}
+
+fn write_windows_runtime(asm: &mut Vec<Asm>) {
+    //Prints rcx as unsigned decimal followed by a newline
+    asm.push(Asm::Label("print".to_string()));
+    asm.push(Asm::AluImm(AluOp::Sub, Rsp, 40));
+    asm.push(Asm::Mov(Rax, Rcx));
+    asm.push(Asm::Lea(
+        Rdi,
+        Mem {
+            disp: 20,
+            ..Mem::symbol("itoabuffer")
+        },
+    ));
+    asm.push(Asm::MovImm(Rcx, 10));
+    asm.push(Asm::Alu(AluOp::Xor, R8, R8));
+    asm.push(Asm::Label(".divloop".to_string()));
+    asm.push(Asm::Alu(AluOp::Xor, Rdx, Rdx));
+    asm.push(Asm::Unary(UnaryOp::Div, Rcx));
+    asm.push(Asm::AluImm(AluOp::Add, Rdx, 48));
+    asm.push(Asm::AluImm(AluOp::Sub, Rdi, 1));
+    asm.push(Asm::Store(Size::Byte, Mem::base(Rdi), Rdx));
+    asm.push(Asm::AluImm(AluOp::Add, R8, 1));
+    asm.push(Asm::Alu(AluOp::Test, Rax, Rax));
+    asm.push(Asm::Jcc(Cond::NE, ".divloop".to_string()));
+    asm.push(Asm::Mov(Rdx, Rdi));
+    asm.push(Asm::Call("out_write".to_string()));
+    asm.push(Asm::LeaSymbol(Rdx, "newline".to_string()));
+    asm.push(Asm::MovImm(R8, 2));
+    asm.push(Asm::Call("out_write".to_string()));
+    asm.push(Asm::AluImm(AluOp::Add, Rsp, 40));
+    asm.push(Asm::Ret);
+    //Standard output is buffered, out_write appends r8 bytes at rdx to the buffer and out_flush writes it out
+    asm.push(Asm::Label("out_write".to_string()));
+    asm.push(Asm::Load(Size::Qword, Rax, Mem::symbol("out_len")));
+    asm.push(Asm::Alu(AluOp::Add, Rax, R8));
+    asm.push(Asm::AluImm(AluOp::Cmp, Rax, OUT_BUFFER_SIZE as i32));
+    asm.push(Asm::Jcc(Cond::BE, ".copy".to_string()));
+    asm.push(Asm::Push(Rdx));
+    asm.push(Asm::Push(R8));
+    asm.push(Asm::Call("out_flush".to_string()));
+    asm.push(Asm::Pop(R8));
+    asm.push(Asm::Pop(Rdx));
+    asm.push(Asm::AluImm(AluOp::Cmp, R8, OUT_BUFFER_SIZE as i32));
+    asm.push(Asm::Jcc(Cond::BE, ".copy".to_string()));
+    asm.push(Asm::MovImm(Rcx, -11));
+    asm.push(Asm::Jmp("write_string".to_string()));
+    asm.push(Asm::Label(".copy".to_string()));
+    asm.push(Asm::Mov(Rsi, Rdx));
+    asm.push(Asm::LeaSymbol(Rdi, "out_buf".to_string()));
+    asm.push(Asm::AluLoad(AluOp::Add, Rdi, Mem::symbol("out_len")));
+    asm.push(Asm::Mov(Rcx, R8));
+    asm.push(Asm::AluStore(AluOp::Add, Mem::symbol("out_len"), R8));
+    asm.push(Asm::RepMovsb);
+    asm.push(Asm::Ret);
+    asm.push(Asm::Label("out_flush".to_string()));
+    asm.push(Asm::Load(Size::Qword, R8, Mem::symbol("out_len")));
+    asm.push(Asm::Alu(AluOp::Test, R8, R8));
+    asm.push(Asm::Jcc(Cond::E, ".done".to_string()));
+    asm.push(Asm::StoreImm(Size::Qword, Mem::symbol("out_len"), 0));
+    asm.push(Asm::MovImm(Rcx, -11));
+    asm.push(Asm::LeaSymbol(Rdx, "out_buf".to_string()));
+    asm.push(Asm::Jmp("write_string".to_string()));
+    asm.push(Asm::Label(".done".to_string()));
+    asm.push(Asm::Ret);
+    //Writes r8 bytes at rdx to the standard handle in ecx, aligning the stack for the Windows ABI
+    asm.push(Asm::Label("write_string".to_string()));
+    asm.push(Asm::Push(Rbp));
+    asm.push(Asm::Mov(Rbp, Rsp));
+    asm.push(Asm::AluImm(AluOp::And, Rsp, -16));
+    asm.push(Asm::AluImm(AluOp::Sub, Rsp, 48));
+    asm.push(Asm::Mov(Rsi, Rdx));
+    asm.push(Asm::Mov(Rdi, R8));
+    asm.push(Asm::Call("GetStdHandle".to_string()));
+    asm.push(Asm::Mov(Rcx, Rax));
+    asm.push(Asm::Mov(Rdx, Rsi));
+    asm.push(Asm::Mov(R8, Rdi));
+    asm.push(Asm::Lea(R9, Mem::base_disp(Rsp, 40)));
+    asm.push(Asm::StoreImm(Size::Qword, Mem::base_disp(Rsp, 32), 0));
+    asm.push(Asm::Call("WriteFile".to_string()));
+    asm.push(Asm::Mov(Rsp, Rbp));
+    asm.push(Asm::Pop(Rbp));
+    asm.push(Asm::Ret);
+}
+
+fn write_op(os: Os, asm: &mut Vec<Asm>, cache: &mut StackCache, checks: &mut Option<Checks>, linkage: Linkage, externs: &[linker::ExternRef], op: &linker::LinkedToken) {
+    cache.live.clear();
+    match op.instruction {
+        Instruction::PushInt(x) => cache.push_const(asm, x as i64),
+        Instruction::PushSInt(x) => cache.push_const(asm, x),
+        Instruction::PushPtr(ptr) => cache.push_const(asm, ptr as i64),
+        Instruction::PushMem(offset) => {
+            let reg = cache.alloc(asm);
+            asm.push(Asm::LeaSymbol(reg, "mem".to_string()));
+            asm.push(Asm::AluImm(AluOp::Add, reg, offset as i32));
+            cache.push(asm, reg);
+        }
+        Instruction::PushBool(x) => cache.push_const(asm, if x { 1 } else { 0 }),
+        Instruction::PushString(ref val) => match op.data {
+            linker::LinkedTokenData::Index(string_index) => {
+                cache.push_const(asm, val.len() as i64);
+                let ptr = cache.alloc(asm);
+                asm.push(Asm::LeaSymbol(ptr, format!("str_{}", string_index)));
+                cache.push(asm, ptr);
+            }
+            _ => panic!(),
+        },
+        Instruction::Intrinsic(intrinsic) => match intrinsic {
+            Intrinsic::Dump | Intrinsic::Puts | Intrinsic::EPuts => {
+                cache.flush(asm);
+                write_depth_check(asm, checks, op, popped_by_system_intrinsic(intrinsic));
+                write_system_intrinsic(os, asm, op, intrinsic, linkage);
+            }
+            Intrinsic::Drop => {
+                cache.pop_slot(asm);
+            }
+            Intrinsic::Dup => {
+                let a = cache.pop_slot(asm);
+                cache.push_slot(asm, a);
+                cache.push_copy(asm, a);
+            }
+            Intrinsic::Over => {
+                let a = cache.pop_slot(asm);
+                let b = cache.pop_slot(asm);
+                cache.push_slot(asm, b);
+                cache.push_slot(asm, a);
+                cache.push_copy(asm, b);
+            }
+            Intrinsic::Swap => {
+                let a = cache.pop_slot(asm);
+                let b = cache.pop_slot(asm);
+                cache.push_slot(asm, a);
+                cache.push_slot(asm, b);
+            }
+            Intrinsic::Rot => {
+                let a = cache.pop_slot(asm);
+                let b = cache.pop_slot(asm);
+                let c = cache.pop_slot(asm);
+                cache.push_slot(asm, b);
+                cache.push_slot(asm, a);
+                cache.push_slot(asm, c);
+            }
+            Intrinsic::Add | Intrinsic::Subtract | Intrinsic::BitAnd | Intrinsic::BitOr | Intrinsic::BitXor => {
+                let alu = match intrinsic {
+                    Intrinsic::Add => AluOp::Add,
+                    Intrinsic::Subtract => AluOp::Sub,
+                    Intrinsic::BitAnd => AluOp::And,
+                    Intrinsic::BitOr => AluOp::Or,
+                    _ => AluOp::Xor,
+                };
+                let a = cache.pop_slot(asm);
+                let b = cache.pop(asm);
+                write_alu(asm, alu, b, a);
+                cache.push(asm, b);
+            }
+            Intrinsic::Multiply => {
+                let a = cache.pop(asm);
+                let b = cache.pop(asm);
+                asm.push(Asm::Mov(Rax, a));
+                asm.push(Asm::Unary(UnaryOp::Mul, b));
+                cache.push(asm, Rax);
+            }
+            Intrinsic::Divide | Intrinsic::Modulo => {
+                //The 32 bit division is used when both operands fit in it
+                let a = cache.pop(asm);
+                let b = cache.pop(asm);
+                write_division_check(asm, checks, op, a);
+                asm.push(Asm::Mov(Rax, b));
+                asm.push(Asm::Mov(Rdx, b));
+                asm.push(Asm::Alu(AluOp::Or, Rdx, a));
+                asm.push(Asm::ShiftImm(ShiftOp::Shr, Rdx, 32));
+                asm.push(Asm::Jcc(Cond::NE, format!("addr_{}_div64", op.self_ptr)));
+                asm.push(Asm::Div32(a));
+                asm.push(Asm::Jmp(format!("addr_{}_done", op.self_ptr)));
+                asm.push(Asm::Label(format!("addr_{}_div64", op.self_ptr)));
+                asm.push(Asm::Alu(AluOp::Xor, Rdx, Rdx));
+                asm.push(Asm::Unary(UnaryOp::Div, a));
+                asm.push(Asm::Label(format!("addr_{}_done", op.self_ptr)));
+                cache.push(asm, if matches!(intrinsic, Intrinsic::Divide) { Rax } else { Rdx });
+            }
+            Intrinsic::DivideSigned => {
+                let a = cache.pop(asm);
+                let b = cache.pop(asm);
+                write_division_check(asm, checks, op, a);
+                asm.push(Asm::Mov(Rax, b));
+                asm.push(Asm::AluImm(AluOp::Cmp, a, -1));
+                asm.push(Asm::Jcc(Cond::NE, format!("addr_{}_idiv", op.self_ptr)));
+                asm.push(Asm::Unary(UnaryOp::Neg, Rax));
+                asm.push(Asm::Jmp(format!("addr_{}_done", op.self_ptr)));
+                asm.push(Asm::Label(format!("addr_{}_idiv", op.self_ptr)));
+                asm.push(Asm::Cqo);
+                asm.push(Asm::Unary(UnaryOp::IDiv, a));
+                asm.push(Asm::Label(format!("addr_{}_done", op.self_ptr)));
+                cache.push(asm, Rax);
+            }
+            Intrinsic::ModuloSigned => {
+                let a = cache.pop(asm);
+                let b = cache.pop(asm);
+                write_division_check(asm, checks, op, a);
+                asm.push(Asm::Mov(Rax, b));
+                asm.push(Asm::Alu(AluOp::Xor, Rdx, Rdx));
+                asm.push(Asm::AluImm(AluOp::Cmp, a, -1));
+                asm.push(Asm::Jcc(Cond::E, format!("addr_{}_done", op.self_ptr)));
+                asm.push(Asm::Cqo);
+                asm.push(Asm::Unary(UnaryOp::IDiv, a));
+                asm.push(Asm::Label(format!("addr_{}_done", op.self_ptr)));
+                cache.push(asm, Rdx);
+            }
+            Intrinsic::ShiftLeft | Intrinsic::ShiftRight | Intrinsic::ShiftRightSigned => {
+                let shift = match intrinsic {
+                    Intrinsic::ShiftLeft => ShiftOp::Shl,
+                    Intrinsic::ShiftRight => ShiftOp::Shr,
+                    _ => ShiftOp::Sar,
+                };
+                let a = cache.pop_slot(asm);
+                let b = cache.pop(asm);
+                match a {
+                    //Like shifting by cl, only the low 6 bits of the count are used
+                    Slot::Imm(count) => asm.push(Asm::ShiftImm(shift, b, (count & 63) as u8)),
+                    Slot::Reg(reg) => {
+                        asm.push(Asm::Mov(Rcx, reg));
+                        asm.push(Asm::Shift(shift, b));
+                    }
+                }
+                cache.push(asm, b);
+            }
+            Intrinsic::Equals
+            | Intrinsic::NotEquals
+            | Intrinsic::Less
+            | Intrinsic::Greater
+            | Intrinsic::LessOrEqual
+            | Intrinsic::GreaterOrEqual
+            | Intrinsic::LessSigned
+            | Intrinsic::GreaterSigned
+            | Intrinsic::LessOrEqualSigned
+            | Intrinsic::GreaterOrEqualSigned => {
+                let cond = match intrinsic {
+                    Intrinsic::Equals => Cond::E,
+                    Intrinsic::NotEquals => Cond::NE,
+                    Intrinsic::Less => Cond::B,
+                    Intrinsic::Greater => Cond::A,
+                    Intrinsic::LessOrEqual => Cond::BE,
+                    Intrinsic::GreaterOrEqual => Cond::AE,
+                    Intrinsic::LessSigned => Cond::L,
+                    Intrinsic::GreaterSigned => Cond::G,
+                    Intrinsic::LessOrEqualSigned => Cond::LE,
+                    _ => Cond::GE,
+                };
+                //mov leaves the flags untouched, so the result can overwrite the left hand side after the comparison
+                let a = cache.pop_slot(asm);
+                let b = cache.pop(asm);
+                asm.push(Asm::MovImm(Rdx, 1));
+                write_alu(asm, AluOp::Cmp, b, a);
+                asm.push(Asm::MovImm(b, 0));
+                asm.push(Asm::Cmov(cond, b, Rdx));
+                cache.push(asm, b);
+            }
+            Intrinsic::CastInt | Intrinsic::CastSInt | Intrinsic::CastPtr => {}
+            Intrinsic::Store8 | Intrinsic::Store16 | Intrinsic::Store32 | Intrinsic::Store64 => {
+                let ptr = cache.pop(asm);
+                let val = cache.pop_slot(asm);
+                write_access_check(asm, checks, op, ptr, access_size(intrinsic));
+                match val {
+                    Slot::Reg(val) => asm.push(Asm::Store(access_size(intrinsic), Mem::base(ptr), val)),
+                    Slot::Imm(val) => asm.push(Asm::StoreImm(access_size(intrinsic), Mem::base(ptr), val)),
+                }
+                write_access_done(asm, checks);
+            }
+            Intrinsic::Load8 | Intrinsic::Load16 | Intrinsic::Load32 | Intrinsic::Load64 => {
+                let ptr = cache.pop(asm);
+                write_access_check(asm, checks, op, ptr, access_size(intrinsic));
+                asm.push(Asm::Load(access_size(intrinsic), ptr, Mem::base(ptr)));
+                write_access_done(asm, checks);
+                cache.push(asm, ptr);
+            }
+            Intrinsic::Exit
+            | Intrinsic::Argc
+            | Intrinsic::Argv
+            | Intrinsic::Envp
+            | Intrinsic::Syscall0
+            | Intrinsic::Syscall1
+            | Intrinsic::Syscall2
+            | Intrinsic::Syscall3
+            | Intrinsic::Syscall4
+            | Intrinsic::Syscall5
+            | Intrinsic::Syscall6 => {
+                cache.flush(asm);
+                write_depth_check(asm, checks, op, popped_by_system_intrinsic(intrinsic));
+                write_system_intrinsic(os, asm, op, intrinsic, linkage);
+            }
+        },
+        Instruction::Function => {
+            asm.push(Asm::Store(Size::Qword, Mem::symbol("callstack_rsp"), Rsp));
+            asm.push(Asm::Mov(Rsp, Rax));
+        }
+        Instruction::Call => match op.data {
+            linker::LinkedTokenData::JumpAddr(ptr) => {
+                cache.flush(asm);
+                if let Some(checks) = checks {
+                    //The return address needs one more slot on the callstack
+                    asm.push(Asm::Lea(
+                        Rcx,
+                        Mem {
+                            disp: 8,
+                            ..Mem::symbol("callstack")
+                        },
+                    ));
+                    asm.push(Asm::AluLoad(AluOp::Cmp, Rcx, Mem::symbol("callstack_rsp")));
+                    asm.push(Asm::Jcc(Cond::A, checks.fail(op, RuntimeCheck::CallstackOverflow)));
+                }
+                asm.push(Asm::Mov(Rax, Rsp));
+                asm.push(Asm::Load(Size::Qword, Rsp, Mem::symbol("callstack_rsp")));
+                asm.push(Asm::Call(format!("addr_{}", ptr)));
+                asm.push(Asm::Store(Size::Qword, Mem::symbol("callstack_rsp"), Rsp));
+                asm.push(Asm::Mov(Rsp, Rax));
+            }
+            _ => panic!(),
+        },
+        Instruction::CallExtern => match (os, op.data) {
+            (Os::Linux, linker::LinkedTokenData::Index(extern_index)) => {
+                let extern_ref = &externs[extern_index];
+                cache.flush(asm);
+                write_depth_check(asm, checks, op, extern_ref.ins.len());
+                asm.push(Asm::Call("out_flush".to_string()));
+                for reg in EXTERN_ARG_REGISTERS.iter().take(extern_ref.ins.len()).rev() {
+                    asm.push(Asm::Pop(*reg));
+                }
+                //C code runs on the data stack, which has to be aligned to 16 bytes at the call. rbx is preserved by the callee
+                asm.push(Asm::Mov(Rbx, Rsp));
+                asm.push(Asm::AluImm(AluOp::And, Rsp, -16));
+                //Variadic functions like printf expect the number of vector registers used for arguments in al
+                asm.push(Asm::Alu(AluOp::Xor, Rax, Rax));
+                asm.push(Asm::Call(extern_ref.name.clone()));
+                asm.push(Asm::Mov(Rsp, Rbx));
+                if let Some(out) = extern_ref.outs.first() {
+                    if out.typ == DataType::BOOL {
+                        //Only the lowest byte of a returned bool is defined
+                        asm.push(Asm::AluImm(AluOp::And, Rax, 0xff));
+                    }
+                    cache.push(asm, Rax);
+                }
+            }
+            (Os::Windows, _) => {
+                eprintln!(
+                    "{}: ERROR: '{}' is not supported by asm-win64, external functions are only available on linux",
+                    op.word, op.word.txt
+                );
+                std::process::exit(1);
+            }
+            _ => panic!(),
+        },
+        Instruction::Return => {
+            cache.flush(asm);
+            asm.push(Asm::Mov(Rax, Rsp));
+            asm.push(Asm::Load(Size::Qword, Rsp, Mem::symbol("callstack_rsp")));
+            asm.push(Asm::Ret);
+        }
+        Instruction::PushVars => match op.data {
+            linker::LinkedTokenData::Count(var_count) => {
+                asm.push(Asm::Load(Size::Qword, Rax, Mem::symbol("callstack_rsp")));
+                asm.push(Asm::AluImm(AluOp::Sub, Rax, (var_count * 8) as i32));
+                if let Some(checks) = checks {
+                    asm.push(Asm::Lea(Rcx, Mem::symbol("callstack")));
+                    asm.push(Asm::Alu(AluOp::Cmp, Rax, Rcx));
+                    asm.push(Asm::Jcc(Cond::B, checks.fail(op, RuntimeCheck::CallstackOverflow)));
+                }
+                asm.push(Asm::Store(Size::Qword, Mem::symbol("callstack_rsp"), Rax));
+                for i in 0..var_count {
+                    let var = Mem::base_disp(Rax, ((var_count - 1 - i) * 8) as i32);
+                    match cache.pop_slot(asm) {
+                        Slot::Reg(val) => asm.push(Asm::Store(Size::Qword, var, val)),
+                        Slot::Imm(val) => asm.push(Asm::StoreImm(Size::Qword, var, val)),
+                    }
+                    cache.live.clear();
+                }
+            }
+            _ => panic!(),
+        },
+        Instruction::PopVars => match op.data {
+            linker::LinkedTokenData::Count(var_count) => {
+                asm.push(Asm::Load(Size::Qword, Rax, Mem::symbol("callstack_rsp")));
+                asm.push(Asm::AluImm(AluOp::Add, Rax, (var_count * 8) as i32));
+                asm.push(Asm::Store(Size::Qword, Mem::symbol("callstack_rsp"), Rax));
+            }
+            _ => panic!(),
+        },
+        Instruction::ApplyVar => match op.data {
+            linker::LinkedTokenData::Index(var_index) => {
+                let reg = cache.alloc(asm);
+                asm.push(Asm::Load(Size::Qword, reg, Mem::symbol("callstack_rsp")));
+                asm.push(Asm::Load(Size::Qword, reg, Mem::base_disp(reg, (var_index * 8) as i32)));
+                cache.push(asm, reg);
+            }
+            _ => panic!(),
+        },
+        Instruction::Jump => match op.data {
+            linker::LinkedTokenData::JumpAddr(ptr) => {
+                cache.flush(asm);
+                asm.push(Asm::Jmp(format!("addr_{}", ptr)));
+            }
+            _ => panic!(),
+        },
+        Instruction::JumpNeq | Instruction::Do => match op.data {
+            //push leaves the flags untouched, so the rest of the cache is spilled between the test and the jump
+            linker::LinkedTokenData::JumpAddr(ptr) => {
+                let flag = cache.pop(asm);
+                asm.push(Asm::Alu(AluOp::Test, flag, flag));
+                cache.flush(asm);
+                asm.push(Asm::Jcc(Cond::E, format!("addr_{}", ptr)));
+            }
+            _ => panic!(),
+        },
+    }
+}
+
+fn write_division_check(asm: &mut Vec<Asm>, checks: &mut Option<Checks>, op: &linker::LinkedToken, divisor: Reg) {
+    if let Some(checks) = checks {
+        asm.push(Asm::Alu(AluOp::Test, divisor, divisor));
+        asm.push(Asm::Jcc(Cond::E, checks.fail(op, RuntimeCheck::DivisionByZero)));
+    }
+}
+
+fn write_access_check(asm: &mut Vec<Asm>, checks: &mut Option<Checks>, op: &linker::LinkedToken, ptr: Reg, size: Size) {
+    if let Some(checks) = checks {
+        let width = match size {
+            Size::Byte => 1,
+            Size::Word => 2,
+            Size::Dword => 4,
+            Size::Qword => 8,
+        };
+        let stub = checks.fail(op, RuntimeCheck::MemoryOutOfBounds(width as usize));
+        asm.push(Asm::Mov(Rax, ptr));
+        asm.push(Asm::MovImm(Rcx, width));
+        asm.push(Asm::Call("check_access".to_string()));
+        asm.push(Asm::Alu(AluOp::Test, Rdx, Rdx));
+        asm.push(Asm::Jcc(Cond::NE, stub.clone()));
+        if checks.fault_handler {
+            asm.push(Asm::LeaSymbol(Rdx, stub));
+            asm.push(Asm::Store(Size::Qword, Mem::symbol("fault_stub"), Rdx));
+        }
+    }
+}
+
+//Faults after the access, for example in C code, are not reported as the access failing
+fn write_access_done(asm: &mut Vec<Asm>, checks: &Option<Checks>) {
+    if checks.as_ref().is_some_and(|x| x.fault_handler) {
+        asm.push(Asm::StoreImm(Size::Qword, Mem::symbol("fault_stub"), 0));
+    }
+}
+
+//The system intrinsics and external calls pop their arguments themselves, so the whole stack is in memory and its depth
+//can be checked at once
+fn write_depth_check(asm: &mut Vec<Asm>, checks: &mut Option<Checks>, op: &linker::LinkedToken, popped: usize) {
+    if let Some(checks) = checks
+        && popped > 0
+    {
+        asm.push(Asm::Lea(Rax, Mem::base_disp(Rsp, popped as i32 * 8)));
+        asm.push(Asm::AluLoad(AluOp::Cmp, Rax, Mem::symbol("stack_base")));
+        asm.push(Asm::Jcc(Cond::A, checks.fail(op, RuntimeCheck::StackUnderflow)));
+    }
+}
+
+fn popped_by_system_intrinsic(intrinsic: Intrinsic) -> usize {
+    match intrinsic {
+        Intrinsic::Dump | Intrinsic::Exit => 1,
+        Intrinsic::Puts | Intrinsic::EPuts => 2,
+        Intrinsic::Argc | Intrinsic::Argv | Intrinsic::Envp => 0,
+        _ => tokenizer::get_syscall_arg_count(&intrinsic) + 1,
+    }
+}
+
+fn write_alu(asm: &mut Vec<Asm>, op: AluOp, dst: Reg, src: Slot) {
+    match src {
+        Slot::Reg(reg) => asm.push(Asm::Alu(op, dst, reg)),
+        Slot::Imm(val) => asm.push(Asm::AluImm(op, dst, val)),
+    }
+}
+
+/// Intrinsics which depend on the operating system: output, exiting, program arguments and syscalls.
+fn write_system_intrinsic(os: Os, asm: &mut Vec<Asm>, op: &linker::LinkedToken, intrinsic: Intrinsic, linkage: Linkage) {
+    match (os, intrinsic) {
+        (Os::Linux, Intrinsic::Dump) => {
+            asm.push(Asm::Pop(Rdi));
+            asm.push(Asm::Call("print".to_string()));
+        }
+        (Os::Linux, Intrinsic::Puts) => {
+            asm.push(Asm::Pop(Rsi));
+            asm.push(Asm::Pop(Rdx));
+            asm.push(Asm::Call("out_write".to_string()));
+        }
+        (Os::Linux, Intrinsic::EPuts) => {
+            asm.push(Asm::Call("out_flush".to_string()));
+            asm.push(Asm::MovImm(Rax, 1));
+            asm.push(Asm::MovImm(Rdi, 2));
+            asm.push(Asm::Pop(Rsi));
+            asm.push(Asm::Pop(Rdx));
+            asm.push(Asm::Syscall);
+        }
+        (Os::Linux, Intrinsic::Exit) if linkage != Linkage::Static => {
+            asm.push(Asm::Call("out_flush".to_string()));
+            asm.push(Asm::Pop(Rdi));
+            asm.push(Asm::AluImm(AluOp::And, Rsp, -16));
+            asm.push(Asm::Call("exit".to_string()));
+        }
+        (Os::Linux, Intrinsic::Exit) => {
+            asm.push(Asm::Call("out_flush".to_string()));
+            asm.push(Asm::MovImm(Rax, 60));
+            asm.push(Asm::Pop(Rdi));
+            asm.push(Asm::Syscall);
+        }
+        (Os::Linux, Intrinsic::Argc | Intrinsic::Argv | Intrinsic::Envp) if linkage == Linkage::Library => {
+            eprintln!(
+                "{}: ERROR: '{}' is not available in libraries, they are not started with program arguments",
+                op.word, op.word.txt
+            );
+            std::process::exit(1);
+        }
+        (Os::Linux, Intrinsic::Argc) => {
+            asm.push(Asm::Load(Size::Qword, Rax, Mem::symbol("args_ptr")));
+            asm.push(Asm::Load(Size::Qword, Rax, Mem::base(Rax)));
+            asm.push(Asm::Push(Rax));
+        }
+        (Os::Linux, Intrinsic::Argv) => {
+            asm.push(Asm::Load(Size::Qword, Rax, Mem::symbol("args_ptr")));
+            asm.push(Asm::AluImm(AluOp::Add, Rax, 8));
+            asm.push(Asm::Push(Rax));
+        }
+        (Os::Linux, Intrinsic::Envp) => {
+            asm.push(Asm::Load(Size::Qword, Rax, Mem::symbol("args_ptr")));
+            asm.push(Asm::Load(Size::Qword, Rbx, Mem::base(Rax)));
+            asm.push(Asm::Lea(Rax, Mem::base_index(Rax, Rbx, 8, 16)));
+            asm.push(Asm::Push(Rax));
+        }
+        (Os::Linux, Intrinsic::Syscall0 | Intrinsic::Syscall1 | Intrinsic::Syscall2 | Intrinsic::Syscall3 | Intrinsic::Syscall4 | Intrinsic::Syscall5 | Intrinsic::Syscall6) => {
+            asm.push(Asm::Call("out_flush".to_string()));
+            asm.push(Asm::Pop(Rax));
+            for reg in SYSCALL_ARG_REGISTERS.iter().take(tokenizer::get_syscall_arg_count(&intrinsic)) {
+                asm.push(Asm::Pop(*reg));
+            }
+            asm.push(Asm::Syscall);
+            asm.push(Asm::Push(Rax));
+        }
+        (Os::Windows, Intrinsic::Dump) => {
+            asm.push(Asm::Pop(Rcx));
+            asm.push(Asm::Call("print".to_string()));
+        }
+        (Os::Windows, Intrinsic::Puts) => {
+            asm.push(Asm::Pop(Rdx));
+            asm.push(Asm::Pop(R8));
+            asm.push(Asm::Call("out_write".to_string()));
+        }
+        (Os::Windows, Intrinsic::EPuts) => {
+            asm.push(Asm::Call("out_flush".to_string()));
+            asm.push(Asm::MovImm(Rcx, -12));
+            asm.push(Asm::Pop(Rdx));
+            asm.push(Asm::Pop(R8));
+            asm.push(Asm::Call("write_string".to_string()));
+        }
+        (Os::Windows, Intrinsic::Exit) => {
+            asm.push(Asm::Call("out_flush".to_string()));
+            asm.push(Asm::Pop(Rcx));
+            asm.push(Asm::AluImm(AluOp::And, Rsp, -16));
+            asm.push(Asm::AluImm(AluOp::Sub, Rsp, 32));
+            asm.push(Asm::Call("ExitProcess".to_string()));
+        }
+        (Os::Windows, Intrinsic::Argc | Intrinsic::Argv | Intrinsic::Envp) => {
+            eprintln!("{}: ERROR: '{}' is not supported by asm-win64", op.word, op.word.txt);
+            std::process::exit(1);
+        }
+        (Os::Windows, Intrinsic::Syscall0 | Intrinsic::Syscall1 | Intrinsic::Syscall2 | Intrinsic::Syscall3 | Intrinsic::Syscall4 | Intrinsic::Syscall5 | Intrinsic::Syscall6) => {
+            eprintln!("{}: ERROR: '{}' is not supported by asm-win64, syscalls are only available on linux", op.word, op.word.txt);
+            std::process::exit(1);
+        }
+        _ => panic!(),
+    }
+}
+
+fn access_size(intrinsic: Intrinsic) -> Size {
+    match intrinsic {
+        Intrinsic::Store8 | Intrinsic::Load8 => Size::Byte,
+        Intrinsic::Store16 | Intrinsic::Load16 => Size::Word,
+        Intrinsic::Store32 | Intrinsic::Load32 => Size::Dword,
+        _ => Size::Qword,
+    }
+}
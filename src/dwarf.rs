@@ -0,0 +1,265 @@
+//Minimal DWARF 4 writer: a single compile unit with one subprogram per function, and a line table
+//mapping the addresses of the code back to the rows and columns of the source files.
+
+const DW_TAG_COMPILE_UNIT: u8 = 0x11;
+const DW_TAG_SUBPROGRAM: u8 = 0x2e;
+
+const DW_AT_NAME: u8 = 0x03;
+const DW_AT_STMT_LIST: u8 = 0x10;
+const DW_AT_LOW_PC: u8 = 0x11;
+const DW_AT_HIGH_PC: u8 = 0x12;
+const DW_AT_LANGUAGE: u8 = 0x13;
+const DW_AT_COMP_DIR: u8 = 0x1b;
+const DW_AT_PRODUCER: u8 = 0x25;
+const DW_AT_DECL_FILE: u8 = 0x3a;
+const DW_AT_DECL_LINE: u8 = 0x3b;
+const DW_AT_EXTERNAL: u8 = 0x3f;
+
+const DW_FORM_ADDR: u8 = 0x01;
+const DW_FORM_DATA2: u8 = 0x05;
+const DW_FORM_DATA8: u8 = 0x07;
+const DW_FORM_STRING: u8 = 0x08;
+const DW_FORM_UDATA: u8 = 0x0f;
+const DW_FORM_SEC_OFFSET: u8 = 0x17;
+const DW_FORM_FLAG_PRESENT: u8 = 0x19;
+
+//There is no language code for Feylon, so the code is described as assembly
+const DW_LANG_MIPS_ASSEMBLER: u16 = 0x8001;
+
+const DW_LNS_COPY: u8 = 1;
+const DW_LNS_ADVANCE_PC: u8 = 2;
+const DW_LNS_ADVANCE_LINE: u8 = 3;
+const DW_LNS_SET_FILE: u8 = 4;
+const DW_LNS_SET_COLUMN: u8 = 5;
+const DW_LNE_END_SEQUENCE: u8 = 1;
+const DW_LNE_SET_ADDRESS: u8 = 2;
+const OPCODE_BASE: u8 = 13;
+
+/// A row of the line table: the code starting at `addr` belongs to the given source location.
+pub struct Line {
+    pub addr: u64,
+    pub file: String,
+    pub row: u32,
+    pub col: u32,
+}
+
+/// A function spanning the code from `low_pc` up to, but not including, `high_pc`.
+pub struct Subprogram {
+    pub name: String,
+    pub low_pc: u64,
+    pub high_pc: u64,
+    pub file: String,
+    pub row: u32,
+}
+
+/// Everything described by the debug information of a program, with all addresses already resolved.
+pub struct DebugInfo {
+    pub name: String,
+    pub comp_dir: String,
+    pub low_pc: u64,
+    pub high_pc: u64,
+    pub lines: Vec<Line>,
+    pub subprograms: Vec<Subprogram>,
+}
+
+/// The contents of the `.debug_abbrev`, `.debug_info` and `.debug_line` sections.
+pub struct Sections {
+    pub abbrev: Vec<u8>,
+    pub info: Vec<u8>,
+    pub line: Vec<u8>,
+}
+
+pub fn write_sections(debug: &DebugInfo) -> Sections {
+    //Files are numbered from 1 in the line table, in order of their first use
+    let mut files: Vec<&str> = vec![];
+    for file in debug.lines.iter().map(|x| x.file.as_str()).chain(debug.subprograms.iter().map(|x| x.file.as_str())) {
+        if !files.contains(&file) {
+            files.push(file);
+        }
+    }
+    let file_index = |file: &str| files.iter().position(|x| *x == file).unwrap() as u64 + 1;
+    Sections {
+        abbrev: write_abbrev(!debug.subprograms.is_empty()),
+        info: write_info(debug, &file_index),
+        line: write_line(debug, &files, &file_index),
+    }
+}
+
+fn write_abbrev(has_subprograms: bool) -> Vec<u8> {
+    let mut out: Vec<u8> = vec![];
+    write_abbrev_entry(
+        &mut out,
+        1,
+        DW_TAG_COMPILE_UNIT,
+        has_subprograms,
+        &[
+            (DW_AT_PRODUCER, DW_FORM_STRING),
+            (DW_AT_LANGUAGE, DW_FORM_DATA2),
+            (DW_AT_NAME, DW_FORM_STRING),
+            (DW_AT_COMP_DIR, DW_FORM_STRING),
+            (DW_AT_LOW_PC, DW_FORM_ADDR),
+            (DW_AT_HIGH_PC, DW_FORM_DATA8),
+            (DW_AT_STMT_LIST, DW_FORM_SEC_OFFSET),
+        ],
+    );
+    write_abbrev_entry(
+        &mut out,
+        2,
+        DW_TAG_SUBPROGRAM,
+        false,
+        &[
+            (DW_AT_NAME, DW_FORM_STRING),
+            (DW_AT_EXTERNAL, DW_FORM_FLAG_PRESENT),
+            (DW_AT_DECL_FILE, DW_FORM_UDATA),
+            (DW_AT_DECL_LINE, DW_FORM_UDATA),
+            (DW_AT_LOW_PC, DW_FORM_ADDR),
+            (DW_AT_HIGH_PC, DW_FORM_DATA8),
+        ],
+    );
+    out.push(0);
+    out
+}
+
+fn write_abbrev_entry(out: &mut Vec<u8>, code: u64, tag: u8, children: bool, attributes: &[(u8, u8)]) {
+    write_uleb(out, code);
+    out.push(tag);
+    out.push(children as u8);
+    for (attribute, form) in attributes {
+        out.push(*attribute);
+        out.push(*form);
+    }
+    out.extend_from_slice(&[0, 0]);
+}
+
+fn write_info(debug: &DebugInfo, file_index: &dyn Fn(&str) -> u64) -> Vec<u8> {
+    let mut out: Vec<u8> = vec![];
+    out.extend_from_slice(&0u32.to_le_bytes()); //Unit length, filled in below
+    out.extend_from_slice(&4u16.to_le_bytes()); //DWARF version
+    out.extend_from_slice(&0u32.to_le_bytes()); //Offset of the abbreviations
+    out.push(8); //Address size
+
+    write_uleb(&mut out, 1);
+    write_string(&mut out, &format!("feylon {}", env!("CARGO_PKG_VERSION")));
+    out.extend_from_slice(&DW_LANG_MIPS_ASSEMBLER.to_le_bytes());
+    write_string(&mut out, &debug.name);
+    write_string(&mut out, &debug.comp_dir);
+    out.extend_from_slice(&debug.low_pc.to_le_bytes());
+    out.extend_from_slice(&(debug.high_pc - debug.low_pc).to_le_bytes());
+    out.extend_from_slice(&0u32.to_le_bytes()); //Offset of the line table
+
+    for subprogram in &debug.subprograms {
+        write_uleb(&mut out, 2);
+        write_string(&mut out, &subprogram.name);
+        write_uleb(&mut out, file_index(&subprogram.file));
+        write_uleb(&mut out, subprogram.row as u64);
+        out.extend_from_slice(&subprogram.low_pc.to_le_bytes());
+        out.extend_from_slice(&(subprogram.high_pc - subprogram.low_pc).to_le_bytes());
+    }
+    if !debug.subprograms.is_empty() {
+        out.push(0); //End of the children of the compile unit
+    }
+
+    let unit_length = (out.len() - 4) as u32;
+    out[0..4].copy_from_slice(&unit_length.to_le_bytes());
+    out
+}
+
+fn write_line(debug: &DebugInfo, files: &[&str], file_index: &dyn Fn(&str) -> u64) -> Vec<u8> {
+    let mut out: Vec<u8> = vec![];
+    out.extend_from_slice(&0u32.to_le_bytes()); //Unit length, filled in below
+    out.extend_from_slice(&4u16.to_le_bytes()); //DWARF version
+    out.extend_from_slice(&0u32.to_le_bytes()); //Header length, filled in below
+    let header_start = out.len();
+    out.push(1); //Minimum instruction length
+    out.push(1); //Maximum operations per instruction
+    out.push(1); //Rows are statements by default
+    out.push(-5i8 as u8); //Line base
+    out.push(14); //Line range
+    out.push(OPCODE_BASE);
+    out.extend_from_slice(&[0, 1, 1, 1, 1, 0, 0, 0, 1, 0, 0, 1]); //Operand counts of the standard opcodes
+    out.push(0); //No include directories, file names are relative to the compilation directory
+    for file in files {
+        write_string(&mut out, file);
+        out.extend_from_slice(&[0, 0, 0]); //Directory, modification time and length
+    }
+    out.push(0);
+    let header_length = (out.len() - header_start) as u32;
+    out[6..10].copy_from_slice(&header_length.to_le_bytes());
+
+    //Instructions without code share an address with the next one, only the last of them is kept
+    let mut rows: Vec<&Line> = vec![];
+    for line in &debug.lines {
+        if rows.last().is_some_and(|x| x.addr == line.addr) {
+            rows.pop();
+        }
+        if !rows.last().is_some_and(|x| x.file == line.file && x.row == line.row) {
+            rows.push(line);
+        }
+    }
+
+    out.push(0);
+    write_uleb(&mut out, 9);
+    out.push(DW_LNE_SET_ADDRESS);
+    out.extend_from_slice(&debug.low_pc.to_le_bytes());
+    let mut addr = debug.low_pc;
+    let mut file: u64 = 1;
+    let mut row: i64 = 1;
+    for line in rows {
+        if line.addr != addr {
+            out.push(DW_LNS_ADVANCE_PC);
+            write_uleb(&mut out, line.addr - addr);
+            addr = line.addr;
+        }
+        if file_index(&line.file) != file {
+            file = file_index(&line.file);
+            out.push(DW_LNS_SET_FILE);
+            write_uleb(&mut out, file);
+        }
+        if line.row as i64 != row {
+            out.push(DW_LNS_ADVANCE_LINE);
+            write_sleb(&mut out, line.row as i64 - row);
+            row = line.row as i64;
+        }
+        out.push(DW_LNS_SET_COLUMN);
+        write_uleb(&mut out, line.col as u64 + 1);
+        out.push(DW_LNS_COPY);
+    }
+    if debug.high_pc != addr {
+        out.push(DW_LNS_ADVANCE_PC);
+        write_uleb(&mut out, debug.high_pc - addr);
+    }
+    out.extend_from_slice(&[0, 1, DW_LNE_END_SEQUENCE]);
+
+    let unit_length = (out.len() - 4) as u32;
+    out[0..4].copy_from_slice(&unit_length.to_le_bytes());
+    out
+}
+
+fn write_string(out: &mut Vec<u8>, val: &str) {
+    out.extend_from_slice(val.as_bytes());
+    out.push(0);
+}
+
+fn write_uleb(out: &mut Vec<u8>, mut val: u64) {
+    loop {
+        let byte = (val & 0x7f) as u8;
+        val >>= 7;
+        if val == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn write_sleb(out: &mut Vec<u8>, mut val: i64) {
+    loop {
+        let byte = (val & 0x7f) as u8;
+        val >>= 7;
+        if (val == 0 && byte & 0x40 == 0) || (val == -1 && byte & 0x40 != 0) {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
@@ -0,0 +1,424 @@
+//Minimal writer for static x86-64 Linux executables: one read-only executable segment holding
+//the headers, code and read-only data, followed by one zero initialised writable segment.
+//The symbol table and the debug information are appended as sections which are not loaded.
+//Programs calling C code are written as relocatable objects instead, which are linked by the system linker.
+
+pub const BASE_ADDR: u64 = 0x400000;
+const PAGE_SIZE: u64 = 0x1000;
+const ELF_HEADER_SIZE: u64 = 64;
+const PROGRAM_HEADER_SIZE: u64 = 56;
+const PROGRAM_HEADER_COUNT: u64 = 2;
+const SECTION_HEADER_SIZE: u64 = 64;
+const SYMBOL_SIZE: u64 = 24;
+
+pub const TEXT_ADDR: u64 = BASE_ADDR + ELF_HEADER_SIZE + PROGRAM_HEADER_SIZE * PROGRAM_HEADER_COUNT;
+
+const PT_LOAD: u32 = 1;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+const PF_R: u32 = 4;
+
+const SHT_PROGBITS: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const SHT_STRTAB: u32 = 3;
+const SHT_RELA: u32 = 4;
+const SHT_NOBITS: u32 = 8;
+const SHF_WRITE: u64 = 1;
+const SHF_ALLOC: u64 = 2;
+const SHF_EXECINSTR: u64 = 4;
+const SHF_INFO_LINK: u64 = 0x40;
+const RELA_SIZE: u64 = 24;
+
+//Section indices of the loaded sections, in the order they are written
+const TEXT_SECTION: u16 = 1;
+const RODATA_SECTION: u16 = 2;
+const BSS_SECTION: u16 = 3;
+
+const STB_LOCAL: u8 = 0;
+const STB_GLOBAL: u8 = 1;
+const STT_NOTYPE: u8 = 0;
+const STT_OBJECT: u8 = 1;
+const STT_FUNC: u8 = 2;
+const STT_SECTION: u8 = 3;
+
+const R_X86_64_PC32: u32 = 2;
+const R_X86_64_PLT32: u32 = 4;
+
+/// Addresses of the sections of an executable, as returned by `layout`.
+pub struct Layout {
+    pub text_addr: u64,
+    pub rodata_addr: u64,
+    pub bss_addr: u64,
+}
+
+/// Places the read-only data right after the code and the zero initialised data on the next page.
+pub fn layout(text_size: usize, rodata_size: usize) -> Layout {
+    let rodata_addr = (TEXT_ADDR + text_size as u64).next_multiple_of(8);
+    let bss_addr = (rodata_addr + rodata_size as u64).next_multiple_of(PAGE_SIZE);
+    Layout {
+        text_addr: TEXT_ADDR,
+        rodata_addr,
+        bss_addr,
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum SymbolKind {
+    Function,
+    Object,
+}
+
+/// A named address in the code or in the zero initialised data, listed in the symbol table.
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    pub addr: u64,
+    pub size: u64,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum RelocationKind {
+    /// A 32 bit displacement relative to the relocated field.
+    Pc32,
+    /// A 32 bit displacement to a function, which the linker may direct through the procedure linkage table.
+    Plt32,
+}
+
+/// What a relocation refers to: one of the sections of the object or a symbol defined by another file.
+#[derive(Clone)]
+pub enum RelocationTarget {
+    Text,
+    Rodata,
+    Bss,
+    External(String),
+}
+
+/// A field in the code of an object which the linker fills in with the address of the target plus the addend.
+pub struct Relocation {
+    pub offset: u64,
+    pub kind: RelocationKind,
+    pub target: RelocationTarget,
+    pub addend: i64,
+}
+
+/// Header of a section, sections only describe the file for tools like debuggers and are not used for loading it.
+struct SectionHeader<'a> {
+    name: &'a str,
+    typ: u32,
+    flags: u64,
+    addr: u64,
+    offset: u64,
+    size: u64,
+    link: u32,
+    info: u32,
+    align: u64,
+    entry_size: u64,
+}
+
+impl<'a> SectionHeader<'a> {
+    fn new(name: &'a str, typ: u32, offset: u64, size: u64) -> SectionHeader<'a> {
+        SectionHeader {
+            name,
+            typ,
+            flags: 0,
+            addr: 0,
+            offset,
+            size,
+            link: 0,
+            info: 0,
+            align: 1,
+            entry_size: 0,
+        }
+    }
+
+    fn loaded(name: &'a str, typ: u32, flags: u64, addr: u64, size: u64) -> SectionHeader<'a> {
+        SectionHeader {
+            flags: SHF_ALLOC | flags,
+            addr,
+            align: 8,
+            ..SectionHeader::new(name, typ, addr - BASE_ADDR, size)
+        }
+    }
+}
+
+/// Writes the executable, `sections` are appended after the loaded data as is, like the debug information.
+pub fn write_executable(layout: &Layout, entry: u64, text: &[u8], rodata: &[u8], bss_size: usize, symbols: &[Symbol], sections: &[(&str, Vec<u8>)]) -> Vec<u8> {
+    let mut out: Vec<u8> = vec![];
+    let file_size = layout.rodata_addr - BASE_ADDR + rodata.len() as u64;
+
+    let mut symtab: Vec<u8> = vec![0; SYMBOL_SIZE as usize];
+    let mut strtab: Vec<u8> = vec![0];
+    for symbol in symbols {
+        write_symbol(&mut symtab, &mut strtab, symbol, STB_GLOBAL);
+    }
+
+    let mut headers: Vec<SectionHeader> = vec![
+        SectionHeader::new("", 0, 0, 0),
+        SectionHeader::loaded(".text", SHT_PROGBITS, SHF_EXECINSTR, layout.text_addr, text.len() as u64),
+        SectionHeader::loaded(".rodata", SHT_PROGBITS, 0, layout.rodata_addr, rodata.len() as u64),
+        SectionHeader {
+            offset: file_size,
+            ..SectionHeader::loaded(".bss", SHT_NOBITS, SHF_WRITE, layout.bss_addr, bss_size as u64)
+        },
+    ];
+    let mut unloaded: Vec<(&str, &[u8])> = sections.iter().map(|(name, contents)| (*name, contents.as_slice())).collect();
+    unloaded.push((".symtab", &symtab));
+    unloaded.push((".strtab", &strtab));
+    let mut shstrtab: Vec<u8> = vec![0];
+    let mut offset = file_size;
+    for (name, contents) in &unloaded {
+        offset = offset.next_multiple_of(8);
+        headers.push(SectionHeader::new(name, SHT_PROGBITS, offset, contents.len() as u64));
+        offset += contents.len() as u64;
+    }
+    let strtab_index = headers.len() as u32 - 1;
+    let symtab_header = &mut headers[strtab_index as usize - 1];
+    symtab_header.typ = SHT_SYMTAB;
+    symtab_header.link = strtab_index;
+    symtab_header.info = 1; //Index of the first global symbol
+    symtab_header.align = 8;
+    symtab_header.entry_size = SYMBOL_SIZE;
+    headers[strtab_index as usize].typ = SHT_STRTAB;
+    //The section names are the last section, so its own name is added before its header
+    let mut name_offsets: Vec<u32> = vec![0];
+    for name in headers.iter().skip(1).map(|x| x.name).chain([".shstrtab"]) {
+        name_offsets.push(shstrtab.len() as u32);
+        shstrtab.extend_from_slice(name.as_bytes());
+        shstrtab.push(0);
+    }
+    headers.push(SectionHeader::new(".shstrtab", SHT_STRTAB, offset, shstrtab.len() as u64));
+    unloaded.push((".shstrtab", &shstrtab));
+    let section_headers_offset = (offset + shstrtab.len() as u64).next_multiple_of(8);
+
+    out.extend_from_slice(&[0x7f, b'E', b'L', b'F']);
+    out.push(2); //64 bit
+    out.push(1); //Little endian
+    out.push(1); //ELF version
+    out.push(0); //System V ABI
+    out.extend_from_slice(&[0; 8]);
+    out.extend_from_slice(&2u16.to_le_bytes()); //Executable
+    out.extend_from_slice(&0x3eu16.to_le_bytes()); //x86-64
+    out.extend_from_slice(&1u32.to_le_bytes());
+    out.extend_from_slice(&entry.to_le_bytes());
+    out.extend_from_slice(&ELF_HEADER_SIZE.to_le_bytes()); //Program headers
+    out.extend_from_slice(&section_headers_offset.to_le_bytes());
+    out.extend_from_slice(&0u32.to_le_bytes());
+    out.extend_from_slice(&(ELF_HEADER_SIZE as u16).to_le_bytes());
+    out.extend_from_slice(&(PROGRAM_HEADER_SIZE as u16).to_le_bytes());
+    out.extend_from_slice(&(PROGRAM_HEADER_COUNT as u16).to_le_bytes());
+    out.extend_from_slice(&(SECTION_HEADER_SIZE as u16).to_le_bytes());
+    out.extend_from_slice(&(headers.len() as u16).to_le_bytes());
+    out.extend_from_slice(&(headers.len() as u16 - 1).to_le_bytes()); //Section names
+
+    write_program_header(&mut out, PF_R | PF_X, 0, BASE_ADDR, file_size, file_size);
+    write_program_header(&mut out, PF_R | PF_W, 0, layout.bss_addr, 0, bss_size as u64);
+
+    out.extend_from_slice(text);
+    out.resize((layout.rodata_addr - BASE_ADDR) as usize, 0);
+    out.extend_from_slice(rodata);
+    for (header, (_, contents)) in headers[4..].iter().zip(&unloaded) {
+        out.resize(header.offset as usize, 0);
+        out.extend_from_slice(contents);
+    }
+    out.resize(section_headers_offset as usize, 0);
+
+    for (header, name) in headers.iter().zip(name_offsets) {
+        write_section_header(&mut out, header, name);
+    }
+    out
+}
+
+/// Writes a relocatable object for the system linker. Unlike in an executable the addresses of the symbols are offsets
+/// in their sections. `symbols` are local to the object and `globals` are visible to the other files being linked.
+pub fn write_object(text: &[u8], rodata: &[u8], bss_size: usize, symbols: &[Symbol], globals: &[Symbol], relocations: &[Relocation]) -> Vec<u8> {
+    let mut out: Vec<u8> = vec![];
+
+    //The sections are referred to by symbols of their own, after the null symbol
+    let mut symtab: Vec<u8> = vec![0; SYMBOL_SIZE as usize];
+    let mut strtab: Vec<u8> = vec![0];
+    for section in [TEXT_SECTION, RODATA_SECTION, BSS_SECTION] {
+        symtab.extend_from_slice(&0u32.to_le_bytes());
+        symtab.push(STB_LOCAL << 4 | STT_SECTION);
+        symtab.push(0);
+        symtab.extend_from_slice(&section.to_le_bytes());
+        symtab.extend_from_slice(&[0; 16]);
+    }
+    for symbol in symbols {
+        write_symbol(&mut symtab, &mut strtab, symbol, STB_LOCAL);
+    }
+    //Local symbols have to come before the global ones
+    let first_global = symtab.len() as u64 / SYMBOL_SIZE;
+    for symbol in globals {
+        write_symbol(&mut symtab, &mut strtab, symbol, STB_GLOBAL);
+    }
+    let mut externals: Vec<&str> = vec![];
+    for relocation in relocations {
+        if let RelocationTarget::External(name) = &relocation.target
+            && !externals.contains(&name.as_str())
+        {
+            externals.push(name);
+            symtab.extend_from_slice(&(strtab.len() as u32).to_le_bytes());
+            strtab.extend_from_slice(name.as_bytes());
+            strtab.push(0);
+            symtab.push(STB_GLOBAL << 4 | STT_NOTYPE);
+            symtab.push(0);
+            symtab.extend_from_slice(&[0; 18]); //Undefined section, no value and no size
+        }
+    }
+    let external_index = |name: &str| first_global + globals.len() as u64 + externals.iter().position(|x| *x == name).unwrap() as u64;
+
+    let mut rela: Vec<u8> = vec![];
+    for relocation in relocations {
+        let symbol = match &relocation.target {
+            RelocationTarget::Text => TEXT_SECTION as u64,
+            RelocationTarget::Rodata => RODATA_SECTION as u64,
+            RelocationTarget::Bss => BSS_SECTION as u64,
+            RelocationTarget::External(name) => external_index(name),
+        };
+        let typ = match relocation.kind {
+            RelocationKind::Pc32 => R_X86_64_PC32,
+            RelocationKind::Plt32 => R_X86_64_PLT32,
+        };
+        rela.extend_from_slice(&relocation.offset.to_le_bytes());
+        rela.extend_from_slice(&(symbol << 32 | typ as u64).to_le_bytes());
+        rela.extend_from_slice(&relocation.addend.to_le_bytes());
+    }
+
+    let mut contents: Vec<(SectionHeader, &[u8])> = vec![
+        (SectionHeader::new("", 0, 0, 0), &[]),
+        (
+            SectionHeader {
+                flags: SHF_ALLOC | SHF_EXECINSTR,
+                align: 16,
+                ..SectionHeader::new(".text", SHT_PROGBITS, 0, text.len() as u64)
+            },
+            text,
+        ),
+        (
+            SectionHeader {
+                flags: SHF_ALLOC,
+                align: 8,
+                ..SectionHeader::new(".rodata", SHT_PROGBITS, 0, rodata.len() as u64)
+            },
+            rodata,
+        ),
+        (
+            SectionHeader {
+                flags: SHF_ALLOC | SHF_WRITE,
+                align: 8,
+                ..SectionHeader::new(".bss", SHT_NOBITS, 0, bss_size as u64)
+            },
+            &[],
+        ),
+        (
+            SectionHeader {
+                flags: SHF_INFO_LINK,
+                link: 6, //.symtab
+                info: TEXT_SECTION as u32,
+                align: 8,
+                entry_size: RELA_SIZE,
+                ..SectionHeader::new(".rela.text", SHT_RELA, 0, rela.len() as u64)
+            },
+            &rela,
+        ),
+        //Marks the stack as not executable, the linker assumes it is otherwise
+        (SectionHeader::new(".note.GNU-stack", SHT_PROGBITS, 0, 0), &[]),
+        (
+            SectionHeader {
+                link: 7, //.strtab
+                info: first_global as u32,
+                align: 8,
+                entry_size: SYMBOL_SIZE,
+                ..SectionHeader::new(".symtab", SHT_SYMTAB, 0, symtab.len() as u64)
+            },
+            &symtab,
+        ),
+        (SectionHeader::new(".strtab", SHT_STRTAB, 0, strtab.len() as u64), &strtab),
+    ];
+    let mut shstrtab: Vec<u8> = vec![0];
+    let mut name_offsets: Vec<u32> = vec![0];
+    for name in contents.iter().skip(1).map(|(x, _)| x.name).chain([".shstrtab"]) {
+        name_offsets.push(shstrtab.len() as u32);
+        shstrtab.extend_from_slice(name.as_bytes());
+        shstrtab.push(0);
+    }
+    contents.push((SectionHeader::new(".shstrtab", SHT_STRTAB, 0, shstrtab.len() as u64), &shstrtab));
+    let mut offset = ELF_HEADER_SIZE;
+    for (header, data) in contents.iter_mut().skip(1) {
+        offset = offset.next_multiple_of(header.align);
+        header.offset = offset;
+        offset += data.len() as u64;
+    }
+    let section_headers_offset = offset.next_multiple_of(8);
+
+    out.extend_from_slice(&[0x7f, b'E', b'L', b'F']);
+    out.push(2); //64 bit
+    out.push(1); //Little endian
+    out.push(1); //ELF version
+    out.push(0); //System V ABI
+    out.extend_from_slice(&[0; 8]);
+    out.extend_from_slice(&1u16.to_le_bytes()); //Relocatable
+    out.extend_from_slice(&0x3eu16.to_le_bytes()); //x86-64
+    out.extend_from_slice(&1u32.to_le_bytes());
+    out.extend_from_slice(&0u64.to_le_bytes()); //No entry point
+    out.extend_from_slice(&0u64.to_le_bytes()); //No program headers
+    out.extend_from_slice(&section_headers_offset.to_le_bytes());
+    out.extend_from_slice(&0u32.to_le_bytes());
+    out.extend_from_slice(&(ELF_HEADER_SIZE as u16).to_le_bytes());
+    out.extend_from_slice(&(PROGRAM_HEADER_SIZE as u16).to_le_bytes());
+    out.extend_from_slice(&0u16.to_le_bytes());
+    out.extend_from_slice(&(SECTION_HEADER_SIZE as u16).to_le_bytes());
+    out.extend_from_slice(&(contents.len() as u16).to_le_bytes());
+    out.extend_from_slice(&(contents.len() as u16 - 1).to_le_bytes()); //Section names
+
+    for (header, data) in contents.iter().skip(1) {
+        if header.typ != SHT_NOBITS {
+            out.resize(header.offset as usize, 0);
+            out.extend_from_slice(data);
+        }
+    }
+    out.resize(section_headers_offset as usize, 0);
+    for ((header, _), name) in contents.iter().zip(name_offsets) {
+        write_section_header(&mut out, header, name);
+    }
+    out
+}
+
+fn write_symbol(symtab: &mut Vec<u8>, strtab: &mut Vec<u8>, symbol: &Symbol, binding: u8) {
+    symtab.extend_from_slice(&(strtab.len() as u32).to_le_bytes());
+    strtab.extend_from_slice(symbol.name.as_bytes());
+    strtab.push(0);
+    let (typ, section) = match symbol.kind {
+        SymbolKind::Function => (STT_FUNC, TEXT_SECTION),
+        SymbolKind::Object => (STT_OBJECT, BSS_SECTION),
+    };
+    symtab.push(binding << 4 | typ);
+    symtab.push(0);
+    symtab.extend_from_slice(&section.to_le_bytes());
+    symtab.extend_from_slice(&symbol.addr.to_le_bytes());
+    symtab.extend_from_slice(&symbol.size.to_le_bytes());
+}
+
+fn write_section_header(out: &mut Vec<u8>, header: &SectionHeader, name: u32) {
+    out.extend_from_slice(&name.to_le_bytes());
+    out.extend_from_slice(&header.typ.to_le_bytes());
+    out.extend_from_slice(&header.flags.to_le_bytes());
+    out.extend_from_slice(&header.addr.to_le_bytes());
+    out.extend_from_slice(&header.offset.to_le_bytes());
+    out.extend_from_slice(&header.size.to_le_bytes());
+    out.extend_from_slice(&header.link.to_le_bytes());
+    out.extend_from_slice(&header.info.to_le_bytes());
+    out.extend_from_slice(&header.align.to_le_bytes());
+    out.extend_from_slice(&header.entry_size.to_le_bytes());
+}
+
+fn write_program_header(out: &mut Vec<u8>, flags: u32, offset: u64, addr: u64, file_size: u64, mem_size: u64) {
+    out.extend_from_slice(&PT_LOAD.to_le_bytes());
+    out.extend_from_slice(&flags.to_le_bytes());
+    out.extend_from_slice(&offset.to_le_bytes());
+    out.extend_from_slice(&addr.to_le_bytes());
+    out.extend_from_slice(&addr.to_le_bytes());
+    out.extend_from_slice(&file_size.to_le_bytes());
+    out.extend_from_slice(&mem_size.to_le_bytes());
+    out.extend_from_slice(&PAGE_SIZE.to_le_bytes());
+}
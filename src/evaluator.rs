@@ -37,6 +37,10 @@ fn evaluate_constant(const_token: &tokenizer::Token, ctx: &mut tokenizer::Parser
         match token.op {
             tokenizer::Op::End => break,
             tokenizer::Op::PushInt(val) => stack.push(tokenizer::ConstDef { typ: checker::DataType::INT, val }),
+            tokenizer::Op::PushSInt(val) => stack.push(tokenizer::ConstDef {
+                typ: checker::DataType::SINT,
+                val: val as u64,
+            }),
             tokenizer::Op::ConstRef(const_ref_name) => match ctx.constants.get(const_ref_name.as_str()) {
                 Some(ref_def) => stack.push(tokenizer::ConstDef {
                     typ: ref_def.typ,
@@ -53,26 +57,26 @@ fn evaluate_constant(const_token: &tokenizer::Token, ctx: &mut tokenizer::Parser
             tokenizer::Op::Intrinsic(intrinsic) => {
                 let a = stack.pop().unwrap();
                 let b = stack.pop().unwrap();
-                if a.typ != checker::DataType::INT {
+                if a.typ != checker::DataType::INT && a.typ != checker::DataType::SINT {
                     eprintln!("{}: ERROR: Encountered illegal data type '{}' when evaluating constant", token.word, a.typ);
                     std::process::exit(1);
                 }
-                if b.typ != checker::DataType::INT {
-                    eprintln!("{}: ERROR: Encountered illegal data type '{}' when evaluating constant", token.word, b.typ);
+                if b.typ != a.typ {
+                    eprintln!("{}: ERROR: Cannot mix data types '{}' and '{}' when evaluating constant", token.word, b.typ, a.typ);
                     std::process::exit(1);
                 }
                 match intrinsic {
                     tokenizer::Intrinsic::Add => stack.push(tokenizer::ConstDef {
-                        typ: checker::DataType::INT,
-                        val: a.val + b.val,
+                        typ: a.typ,
+                        val: a.val.wrapping_add(b.val),
                     }),
                     tokenizer::Intrinsic::Subtract => stack.push(tokenizer::ConstDef {
-                        typ: checker::DataType::INT,
-                        val: b.val - a.val,
+                        typ: a.typ,
+                        val: b.val.wrapping_sub(a.val),
                     }),
                     tokenizer::Intrinsic::Multiply => stack.push(tokenizer::ConstDef {
-                        typ: checker::DataType::INT,
-                        val: a.val * b.val,
+                        typ: a.typ,
+                        val: a.val.wrapping_mul(b.val),
                     }),
                     _ => {
                         eprintln!("{}: ERROR: Encountered illegal intrinsic '{}' when evaluating constant", token.word, token.word.txt);
@@ -60,7 +60,7 @@ pub fn parse_lines_into_words(file: String, source_lines: Vec<String>) -> Vec<Wo
     while !ctx.lines.is_empty() {
         ctx.line = ctx.lines.pop().unwrap();
         ctx.row += 1;
-        ctx.pos = find_char(&ctx.line, 0, |x| !x.is_whitespace());
+        ctx.pos = find_char(ctx.line, 0, |x| !x.is_whitespace());
         if ctx.line.starts_with("#!") {
             eprintln!(
                 "{}:{}:{}: ERROR: Shebangs/Hashbangs are only allowed to be the first line of the file",
@@ -83,7 +83,7 @@ pub fn parse_lines_into_words(file: String, source_lines: Vec<String>) -> Vec<Wo
                 break;
             }
             ctx.line = splitted.first().unwrap();
-            let end_pos = find_char(&ctx.line, ctx.pos + 1, |x| x.is_whitespace());
+            let end_pos = find_char(ctx.line, ctx.pos + 1, |x| x.is_whitespace());
             if end_pos > ctx.line.len() {
                 break;
             }
@@ -97,14 +97,14 @@ pub fn parse_lines_into_words(file: String, source_lines: Vec<String>) -> Vec<Wo
                 col: ctx.pos as u32,
                 txt: token_text,
             });
-            ctx.pos = find_char(&ctx.line, end_pos + 1, |x| !x.is_whitespace());
+            ctx.pos = find_char(ctx.line, end_pos + 1, |x| !x.is_whitespace());
         }
     }
     ctx.result
 }
 
 fn parse_char(ctx: &mut LexerContext) {
-    let end_pos = find_char(&ctx.line, ctx.pos + 2, |x| x == '\'');
+    let end_pos = find_char(ctx.line, ctx.pos + 2, |x| x == '\'');
     if end_pos >= ctx.line.len()
         || (ctx.line.chars().nth(ctx.pos + 1).unwrap() != '\\' && end_pos - ctx.pos >= 3)
         || (ctx.line.chars().nth(ctx.pos + 1).unwrap() == '\\' && end_pos - ctx.pos >= 4)
@@ -112,18 +112,18 @@ fn parse_char(ctx: &mut LexerContext) {
         eprintln!("{}:{}:{}: ERROR: Encountered invalid character literal", ctx.file, ctx.row, ctx.pos + 1);
         std::process::exit(1);
     }
-    let val = ctx.line[ctx.pos..end_pos].chars().nth(0).unwrap();
+    let val = ctx.line[ctx.pos..end_pos].chars().next().unwrap();
     ctx.result.push(Word {
         file: ctx.file.clone(),
         row: ctx.row as u32,
         col: ctx.pos as u32,
         txt: (val as u32).to_string(),
     });
-    ctx.pos = find_char(&ctx.line, end_pos + 1, |x| !x.is_whitespace());
+    ctx.pos = find_char(ctx.line, end_pos + 1, |x| !x.is_whitespace());
 }
 
 fn parse_string(ctx: &mut LexerContext) {
-    let end_pos = find_char(&ctx.line, ctx.pos + 1, |x| x == '"');
+    let end_pos = find_char(ctx.line, ctx.pos + 1, |x| x == '"');
     if end_pos >= ctx.line.len() {
         let start_row = ctx.row;
         let start_col = ctx.pos;
@@ -132,7 +132,7 @@ fn parse_string(ctx: &mut LexerContext) {
         while !ctx.lines.is_empty() {
             ctx.line = ctx.lines.pop().unwrap();
             ctx.row += 1;
-            let end_pos = find_char(&ctx.line, ctx.pos + 1, |x| x == '"');
+            let end_pos = find_char(ctx.line, 0, |x| x == '"');
             if end_pos >= ctx.line.len() {
                 string_buffer.push(ctx.line);
             } else {
@@ -143,7 +143,7 @@ fn parse_string(ctx: &mut LexerContext) {
                     col: start_col as u32,
                     txt: string_buffer.join("\n"),
                 });
-                ctx.pos = find_char(&ctx.line, end_pos + 2, |x| !x.is_whitespace());
+                ctx.pos = find_char(ctx.line, end_pos + 2, |x| !x.is_whitespace());
                 break;
             }
         }
@@ -154,6 +154,6 @@ fn parse_string(ctx: &mut LexerContext) {
             col: ctx.pos as u32,
             txt: ctx.line[ctx.pos..end_pos + 1].to_string(),
         });
-        ctx.pos = find_char(&ctx.line, end_pos + 2, |x| !x.is_whitespace());
+        ctx.pos = find_char(ctx.line, end_pos + 2, |x| !x.is_whitespace());
     }
 }
@@ -2,7 +2,7 @@ use crate::checker::TypedPos;
 use crate::tokenizer;
 use crate::tokenizer::{Intrinsic, Op};
 use crate::{checker, lexer};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt::{Display, Formatter};
 
 #[derive(Copy, Clone)]
@@ -16,6 +16,7 @@ pub enum LinkedTokenData {
 
 pub enum Instruction {
     PushInt(u64),
+    PushSInt(i64),
     PushPtr(usize),
     PushMem(usize),
     PushBool(bool),
@@ -25,6 +26,7 @@ pub enum Instruction {
 
     Function,
     Call,
+    CallExtern,
     Return,
     PushVars,
     PopVars,
@@ -39,6 +41,7 @@ impl Display for Instruction {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         let txt = match self {
             Instruction::PushInt(_) => "PUSH_INT",
+            Instruction::PushSInt(_) => "PUSH_SINT",
             Instruction::PushPtr(_) => "PUSH_POINTER",
             Instruction::PushMem(_) => "PUSH_MEMORY",
             Instruction::PushBool(_) => "PUSH_BOOL",
@@ -48,6 +51,7 @@ impl Display for Instruction {
 
             Instruction::Function => "FUNCTION",
             Instruction::Call => "CALL",
+            Instruction::CallExtern => "CALL_EXTERN",
             Instruction::Return => "RETURN",
             Instruction::PushVars => "PUSH_VARS",
             Instruction::PopVars => "POP_VARS",
@@ -71,13 +75,30 @@ pub struct LinkedToken {
 pub struct FunctionRef {
     pub ins: Vec<TypedPos>,
     pub outs: Vec<TypedPos>,
-    ptr: usize,
+    pub ptr: usize,
+    pub export: bool,
+}
+
+/// A function defined outside of the program, called with the C calling convention of the target.
+#[derive(Clone)]
+pub struct ExternRef {
+    pub name: String,
+    pub ins: Vec<TypedPos>,
+    pub outs: Vec<TypedPos>,
 }
 
 pub struct LinkerContext {
     tokens: Vec<tokenizer::Token>,
+    //The jumps over functions point right after them, so a function at the end of the program is jumped over to `result.len()`
     pub result: Vec<LinkedToken>,
     pub functions: HashMap<String, FunctionRef>,
+    pub externs: Vec<ExternRef>,
+    //The native and C backends follow every string with a zero byte which is not part of its length,
+    //so strings can be passed to C functions and syscalls as they are
+    pub strings: Vec<String>,
+    pub memories: HashMap<String, tokenizer::MemoryDef>,
+    //Pointers of the arithmetic instructions the typechecker found to operate on signed values
+    pub signed_ops: HashSet<usize>,
     call_stack: Vec<usize>,
     var_stack: Vec<String>,
     pub mem_size: usize,
@@ -85,11 +106,15 @@ pub struct LinkerContext {
 }
 
 impl LinkerContext {
-    pub fn new(tokens: Vec<tokenizer::Token>, mem_size: usize) -> LinkerContext {
+    pub fn new(tokens: Vec<tokenizer::Token>, memories: HashMap<String, tokenizer::MemoryDef>, mem_size: usize) -> LinkerContext {
         LinkerContext {
             tokens,
             result: vec![],
             functions: HashMap::new(),
+            externs: vec![],
+            strings: vec![],
+            memories,
+            signed_ops: HashSet::new(),
             call_stack: vec![],
             var_stack: vec![],
             mem_size,
@@ -102,6 +127,16 @@ impl LinkerContext {
         self.pointer += 1;
         result
     }
+
+    fn intern_string(&mut self, val: &str) -> usize {
+        match self.strings.iter().position(|x| x == val) {
+            Some(index) => index,
+            None => {
+                self.strings.push(val.to_string());
+                self.strings.len() - 1
+            }
+        }
+    }
 }
 
 impl LinkedToken {
@@ -119,15 +154,18 @@ impl LinkedToken {
 }
 
 pub fn link_tokens(parser_context: tokenizer::ParserContext) -> LinkerContext {
-    let mut ctx = LinkerContext::new(parser_context.result, parser_context.total_memory_size);
+    let mut ctx = LinkerContext::new(parser_context.result, parser_context.memories, parser_context.total_memory_size);
     ctx.tokens.reverse();
-    while !ctx.tokens.is_empty() {
-        let token = ctx.tokens.pop().unwrap();
+    while let Some(token) = ctx.tokens.pop() {
         match &token.op {
             Op::PushInt(val) => {
                 let new_token = LinkedToken::new(token.word, ctx.incr_ptr(), Instruction::PushInt(*val));
                 ctx.result.push(new_token);
             }
+            Op::PushSInt(val) => {
+                let new_token = LinkedToken::new(token.word, ctx.incr_ptr(), Instruction::PushSInt(*val));
+                ctx.result.push(new_token);
+            }
             Op::PushPtr(val) => {
                 let new_token = LinkedToken::new(token.word, ctx.incr_ptr(), Instruction::PushPtr(*val));
                 ctx.result.push(new_token);
@@ -137,7 +175,8 @@ pub fn link_tokens(parser_context: tokenizer::ParserContext) -> LinkerContext {
                 ctx.result.push(new_token);
             }
             Op::PushString(val) => {
-                let new_token = LinkedToken::new(token.word, ctx.incr_ptr(), Instruction::PushString(val.clone()));
+                let string_index = ctx.intern_string(val);
+                let new_token = LinkedToken::new_with_data(token.word, ctx.incr_ptr(), Instruction::PushString(val.clone()), LinkedTokenData::Index(string_index));
                 ctx.result.push(new_token);
             }
             Op::Intrinsic(val) => {
@@ -153,19 +192,21 @@ pub fn link_tokens(parser_context: tokenizer::ParserContext) -> LinkerContext {
                 });
                 let new_token = match def.typ {
                     checker::DataType::INT => LinkedToken::new(token.word, ctx.incr_ptr(), Instruction::PushInt(def.val)),
+                    checker::DataType::SINT => LinkedToken::new(token.word, ctx.incr_ptr(), Instruction::PushSInt(def.val as i64)),
                     _ => panic!("Encountered unimplemented datatype '{}' of constant '{}'. This is a evaluation error.", def.typ, name),
                 };
                 ctx.result.push(new_token);
             }
             Op::MemRef(name) => {
-                let def = parser_context.memories.get(name).unwrap_or_else(|| {
+                let def = ctx.memories.get(name).unwrap_or_else(|| {
                     eprintln!(
                         "{}: ERROR: Encountered a reference to a nonexistent memory '{}'. This is a evaluation error.",
                         token.word, name
                     );
                     std::process::exit(1);
                 });
-                let new_token = LinkedToken::new(token.word, ctx.incr_ptr(), Instruction::PushMem(def.ptr));
+                let mem_ptr = def.ptr;
+                let new_token = LinkedToken::new(token.word, ctx.incr_ptr(), Instruction::PushMem(mem_ptr));
                 ctx.result.push(new_token);
             }
             Op::Function(function_name) => {
@@ -181,10 +222,24 @@ pub fn link_tokens(parser_context: tokenizer::ParserContext) -> LinkerContext {
                         ins: func_def.ins.clone(),
                         outs: func_def.outs.clone(),
                         ptr: ctx.pointer - 1,
+                        export: func_def.export,
                     },
                 );
             }
+            Op::Extern(function_name) => {
+                let func_def = parser_context.functions.get(function_name).unwrap();
+                ctx.externs.push(ExternRef {
+                    name: function_name.clone(),
+                    ins: func_def.ins.clone(),
+                    outs: func_def.outs.clone(),
+                });
+            }
             Op::FunctionRef(function_name) => {
+                if let Some(extern_index) = ctx.externs.iter().position(|x| x.name == *function_name) {
+                    let new_token = LinkedToken::new_with_data(token.word, ctx.incr_ptr(), Instruction::CallExtern, LinkedTokenData::Index(extern_index));
+                    ctx.result.push(new_token);
+                    continue;
+                }
                 let func_ref = ctx.functions.get(function_name).unwrap_or_else(|| {
                     eprintln!(
                         "{}: ERROR: Encountered a reference to a nonexistent function '{}'. This is a tokenizing error.",
@@ -5,20 +5,36 @@ use std::path::Path;
 
 mod checker;
 mod compiler;
+mod compiler_asm_aarch64;
 mod compiler_asm_elf64;
 mod compiler_asm_win64;
+mod compiler_c;
+mod compiler_elf64;
+mod compiler_gas_elf64;
+mod compiler_llvm;
 mod compiler_string;
+mod compiler_wasm;
+mod compiler_x86_64;
+mod dwarf;
+mod elf64;
 mod evaluator;
 mod lexer;
 mod linker;
 mod simulator;
 mod test;
 mod tokenizer;
+mod wasm;
+mod x86_64;
 
 fn main() {
     let mut args: Vec<String> = std::env::args().collect();
     let self_path = args.remove(0);
-    if args.len() == 0 {
+    //Everything after `--` is passed on to the simulated program
+    let program_args: Vec<String> = match args.iter().position(|x| x == "--") {
+        Some(pos) => args.split_off(pos).into_iter().skip(1).collect(),
+        None => vec![],
+    };
+    if args.is_empty() {
         usage(&self_path);
         std::process::exit(0);
     }
@@ -32,21 +48,29 @@ fn main() {
     match command {
         "simulate" => {
             let skip_typecheck = args.contains(&"--unsafe".to_string());
-            simulate_program(&last_arg, skip_typecheck)
+            let checked_arith = args.contains(&"--checked-arith".to_string());
+            //Whether an operation is signed is only known after typechecking
+            if skip_typecheck && checked_arith {
+                eprintln!("ERROR: --checked-arith can not be combined with --unsafe");
+                std::process::exit(1);
+            }
+            simulate_program(&last_arg, skip_typecheck, checked_arith, program_args)
         }
         "compile" => match read_file_contents(&last_arg, None) {
             Ok(lines) => {
                 let skip_typecheck = args.contains(&"--unsafe".to_string());
-                let compiler_id = args
+                let backend = args
                     .iter()
                     .find_map(|x| {
                         if x.starts_with("--use=") {
                             let id = x.strip_prefix("--use=").unwrap();
-                            if !compiler::KNOWN_COMPILERS.contains(&id) {
-                                eprintln!("ERROR: Unknown compiler: {}", id);
-                                std::process::exit(1);
+                            match compiler::find_backend(id) {
+                                Some(backend) => Some(backend),
+                                None => {
+                                    eprintln!("ERROR: Unknown compiler: {}", id);
+                                    std::process::exit(1);
+                                }
                             }
-                            Some(id)
                         } else {
                             None
                         }
@@ -55,8 +79,29 @@ fn main() {
                         eprintln!("ERROR: Compiler to use was not defined. Use the --use=<id> option");
                         std::process::exit(1);
                     });
+                let options = compiler::CompileOptions {
+                    emit_asm: args.contains(&"--emit=asm".to_string()),
+                    checked: args.contains(&"--checked".to_string()),
+                    link: args.iter().filter_map(|x| x.strip_prefix("--link=")).map(|x| x.to_string()).collect(),
+                    lib: args.contains(&"--lib".to_string()) || args.contains(&"--emit=obj".to_string()),
+                };
+                if options.checked && !backend.checkable() {
+                    eprintln!("ERROR: The {} compiler does not support --checked", backend.id());
+                    std::process::exit(1);
+                }
+                if options.lib && !backend.linkable() {
+                    eprintln!("ERROR: The {} compiler does not support --lib", backend.id());
+                    std::process::exit(1);
+                }
+                if options.lib && options.checked {
+                    eprintln!("ERROR: Libraries can not be compiled with --checked");
+                    std::process::exit(1);
+                }
                 let program = parse_program(last_arg.clone(), lines, skip_typecheck);
-                compiler::compile(compiler_id, &last_arg, &program);
+                if options.lib {
+                    compiler::check_library(&program);
+                }
+                backend.compile(&last_arg, &program, &options);
                 std::process::exit(0);
             }
             Err(err) => {
@@ -67,12 +112,13 @@ fn main() {
         "test" => {
             let skip_typecheck = args.contains(&"--unsafe".to_string());
             let print_output = args.contains(&"--print".to_string());
+            let checked = args.contains(&"--checked".to_string());
             let compiler_id = args
                 .iter()
                 .find_map(|x| {
                     if x.starts_with("--use=") {
                         let id = x.strip_prefix("--use=").unwrap();
-                        if id != "asm-win64" && id != "asm-elf64" && id != "simulate" {
+                        if id != "simulate" && !compiler::find_backend(id).is_some_and(|x| x.testable()) {
                             eprintln!("ERROR: Unknown compiler: {}", id);
                             std::process::exit(1);
                         }
@@ -85,10 +131,31 @@ fn main() {
                     eprintln!("ERROR: Compiler to use was not defined. Use the --use=<id> option");
                     std::process::exit(1);
                 });
+            if checked && !compiler::find_backend(compiler_id).is_some_and(|x| x.checkable()) {
+                eprintln!("ERROR: The {} compiler does not support --checked", compiler_id);
+                std::process::exit(1);
+            }
+            let checked_arith = args.contains(&"--checked-arith".to_string());
+            if checked_arith && compiler_id != "simulate" {
+                eprintln!("ERROR: --checked-arith is only available with --use=simulate");
+                std::process::exit(1);
+            }
+            if checked_arith && skip_typecheck {
+                eprintln!("ERROR: --checked-arith can not be combined with --unsafe");
+                std::process::exit(1);
+            }
+            let options = test::TestOptions {
+                compiler: compiler_id,
+                print: print_output,
+                skip_typecheck,
+                checked,
+                checked_arith,
+                allow_skip: args.contains(&"--allow-skip".to_string()),
+            };
             if args.contains(&"--all".to_string()) {
-                test::run_all_tests(self_path, last_arg, print_output, skip_typecheck, compiler_id)
+                test::run_all_tests(self_path, last_arg, &options)
             } else {
-                test::test_program(self_path, last_arg, print_output, skip_typecheck, compiler_id);
+                test::test_program(self_path, last_arg, &options);
             }
             std::process::exit(0);
         }
@@ -99,12 +166,19 @@ fn main() {
     };
 }
 
-fn simulate_program(path: &String, skip_typecheck: bool) {
-    match read_file_contents(&path, None) {
+fn simulate_program(path: &String, skip_typecheck: bool, checked_arith: bool, program_args: Vec<String>) {
+    match read_file_contents(path, None) {
         Ok(lines) => {
             let program = parse_program(path.clone(), lines, skip_typecheck);
-            simulator::simulate_tokens(program);
-            std::process::exit(0);
+            let mut args = vec![path.clone()];
+            args.extend(program_args);
+            match simulator::simulate_tokens(program, checked_arith, args) {
+                Ok(exit_code) => std::process::exit(exit_code),
+                Err(err) => {
+                    eprintln!("{}", err);
+                    std::process::exit(simulator::RUNTIME_ERROR_EXIT_CODE);
+                }
+            }
         }
         Err(err) => {
             eprintln!("ERROR: Could not load program {}!", path);
@@ -115,20 +189,34 @@ fn simulate_program(path: &String, skip_typecheck: bool) {
 }
 
 fn usage(self_path: &str) {
-    println!("Usage: {} <COMMAND> [OPTIONS] <file_path>", self_path);
+    println!("Usage: {} <COMMAND> [OPTIONS] <file_path> [-- <program_args>]", self_path);
     println!("Available commands:");
     println!("  simulate        Interpret and simulate the given program");
     println!("    Available options:");
+    println!("      --unsafe          Skip typechecking");
+    println!("      --checked-arith   Report arithmetic overflow instead of wrapping around, not with --unsafe");
     println!("  compile         Compile the given program and write it to disk");
     println!("    Available options:");
-    println!("      --use=<?>   Which compiler to use. Can be one of: {}", compiler::KNOWN_COMPILERS.join(", "));
+    println!("      --use=<?>   Which compiler to use. Can be one of: {}", backend_ids(false).join(", "));
     println!("      --unsafe    Skip typechecking");
+    println!("      --emit=asm  Also write the generated assembly when using elf64");
+    println!("      --checked   Report runtime errors with their location, only for the x86-64 compilers");
+    println!("      --link=<?>  Object file or library to link with programs calling external functions, like --link=-lm");
+    println!("      --lib       Write an object and a C header exporting the 'export function' definitions, only for the x86-64 linux compilers");
+    println!("      --emit=obj  Same as --lib");
     println!("  test            Interpret and test the given program");
     println!("    Available options:");
     println!("      --all       Run all tests in the given directory; file_path must be a directory");
-    println!("      --use=<?>   Which compiler to use. Can be one of: simulate, asm-win64, asm-elf64");
+    println!("      --use=<?>   Which compiler to use. Can be one of: simulate, {}", backend_ids(true).join(", "));
     println!("      --unsafe    Skip typechecking");
     println!("      --print     Print the program STDOUT and STDERR");
+    println!("      --checked   Compile the tested programs with runtime checks");
+    println!("      --checked-arith  Simulate the tested programs reporting arithmetic overflow, only with --use=simulate");
+    println!("      --allow-skip  Pass tests which can not be run on this machine, like without clang or qemu-aarch64");
+}
+
+fn backend_ids(testable_only: bool) -> Vec<&'static str> {
+    compiler::BACKENDS.iter().filter(|x| !testable_only || x.testable()).map(|x| x.id()).collect()
 }
 
 pub fn read_file_contents(path: &str, relative_parent: Option<&str>) -> io::Result<Vec<String>> {
@@ -147,9 +235,9 @@ fn parse_program(file: String, lines: Vec<String>, skip_typecheck: bool) -> Link
     let words = lexer::parse_lines_into_words(file, lines);
     let mut tokens = tokenizer::parse_words_into_tokens(words);
     evaluator::evaluate_tokens(&mut tokens);
-    let linked = linker::link_tokens(tokens);
+    let mut linked = linker::link_tokens(tokens);
     if !skip_typecheck {
-        checker::check_types(&linked, 0);
+        checker::check_types(&mut linked, 0);
     }
     linked
 }
@@ -164,13 +252,13 @@ pub fn add_or_replace_extension(file_path: &str, extension: &str) -> String {
         })
         .to_string();
     if !file_name.contains('.') {
-        if extension.len() > 0 {
-            file_name = String::from(file_name) + "." + extension;
+        if !extension.is_empty() {
+            file_name = file_name + "." + extension;
         }
     } else {
         let mut file_name_parts: Vec<&str> = file_name.split('.').collect();
         file_name_parts.pop();
-        if extension.len() > 0 {
+        if !extension.is_empty() {
             file_name_parts.push(extension);
         }
         file_name = file_name_parts.join(".");
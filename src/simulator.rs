@@ -1,242 +1,685 @@
-use crate::linker;
 use crate::linker::LinkedTokenData;
 use crate::tokenizer::Intrinsic;
+use crate::{compiler_string, lexer, linker, tokenizer};
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::io::{Read, Write};
 
-pub fn simulate_tokens(linker_context: linker::LinkerContext) {
-    let mut stack: Vec<u64> = vec![];
-    let mut vars: Vec<u64> = vec![];
-    let mut call_stack: Vec<usize> = vec![];
-    let mut mem: Vec<u64> = vec![0; linker_context.mem_size];
-    let mut string_pool = [0; 65536];
-    let mut string_ptr = 0;
-    let mut program_counter: usize = 0;
-
-    while program_counter < linker_context.result.len() {
-        let op = &linker_context.result[program_counter];
+/// Addresses below this value are never handed out, so a pointer of 0 can't alias any data.
+const NULL_REGION_SIZE: usize = 4096;
+
+/// Exit code used when the simulated program is aborted because of a runtime error.
+pub const RUNTIME_ERROR_EXIT_CODE: i32 = 70;
+
+//Linux x86-64 syscalls emulated by the simulator, failures are returned as negated errno values like the kernel does
+const SYS_READ: u64 = 0;
+const SYS_WRITE: u64 = 1;
+const SYS_OPEN: u64 = 2;
+const SYS_CLOSE: u64 = 3;
+const SYS_MMAP: u64 = 9;
+const SYS_EXIT: u64 = 60;
+const EBADF: i64 = 9;
+const EFAULT: i64 = 14;
+const EINVAL: i64 = 22;
+const O_ACCMODE: u64 = 0o3;
+const O_CREAT: u64 = 0o100;
+const O_TRUNC: u64 = 0o1000;
+const O_APPEND: u64 = 0o2000;
+const MAP_ANONYMOUS: u64 = 0x20;
+const PAGE_SIZE: usize = 4096;
+
+pub enum RuntimeErrorKind {
+    StackUnderflow,
+    VarStackUnderflow,
+    DivisionByZero(u64),
+    MemoryOutOfBounds(u64, usize),
+    InvalidReturn,
+    ArithmeticOverflow(String),
+    UnsupportedSyscall(String),
+    UnsupportedExtern(String),
+}
+
+pub struct RuntimeError {
+    pub kind: RuntimeErrorKind,
+    pub word: lexer::Word,
+    pub instruction: String,
+    pub stack: Vec<u64>,
+    pub call_stack: Vec<lexer::Word>,
+}
+
+impl Display for RuntimeErrorKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RuntimeErrorKind::StackUnderflow => write!(f, "Stack underflow"),
+            RuntimeErrorKind::VarStackUnderflow => write!(f, "Variable stack underflow"),
+            RuntimeErrorKind::DivisionByZero(dividend) => write!(f, "Division by zero while dividing {}", dividend),
+            RuntimeErrorKind::MemoryOutOfBounds(ptr, width) => write!(f, "Memory access of {} byte(s) at address {} is out of bounds", width, ptr),
+            RuntimeErrorKind::InvalidReturn => write!(f, "Return without a matching function call"),
+            RuntimeErrorKind::ArithmeticOverflow(operands) => write!(f, "Arithmetic overflow with operands {}", operands),
+            RuntimeErrorKind::UnsupportedSyscall(reason) => write!(f, "Unsupported syscall: {}", reason),
+            RuntimeErrorKind::UnsupportedExtern(name) => write!(f, "External function '{}' can not be simulated", name),
+        }
+    }
+}
+
+impl Display for RuntimeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{}: ERROR: {} in '{}'", self.word, self.kind, self.word.txt)?;
+        writeln!(f, "{}: INFO: Instruction: {}", self.word, self.instruction)?;
+        write!(f, "{}: INFO: Data stack (top last): {:?}", self.word, self.stack)?;
+        for call_word in self.call_stack.iter().rev() {
+            write!(f, "\n{}: INFO: In function '{}' called from here", call_word, call_word.txt)?;
+        }
+        Ok(())
+    }
+}
+
+struct Simulation<'a> {
+    ctx: &'a linker::LinkerContext,
+    checked_arith: bool,
+    stack: Vec<u64>,
+    //Values popped by the current instruction, so a runtime error can report the stack as it was before it
+    popped: Vec<u64>,
+    vars: Vec<u64>,
+    call_stack: Vec<usize>,
+    mem: Vec<u8>,
+    //The strings, the memories, the program arguments and every mapping, an access has to lie within one of them
+    regions: Vec<std::ops::Range<usize>>,
+    string_ptrs: Vec<usize>,
+    mem_start: usize,
+    program_counter: usize,
+    exit_code: Option<i32>,
+    files: HashMap<u64, std::fs::File>,
+    argc: usize,
+    argv_ptr: usize,
+    envp_ptr: usize,
+}
+
+/// Places all data of the program in a single address space, mirroring the native layouts:
+/// the null region, followed by the string literals (`.rodata`) and the memories (`.bss`).
+/// Returns the memory, the address of every interned string and the start address of the memories.
+fn layout_memory(linker_context: &linker::LinkerContext) -> (Vec<u8>, Vec<usize>, usize) {
+    let mut mem: Vec<u8> = vec![0; NULL_REGION_SIZE];
+    let mut string_ptrs: Vec<usize> = vec![];
+    for val in &linker_context.strings {
+        string_ptrs.push(mem.len());
+        mem.extend_from_slice(val.as_bytes());
+        mem.push(0);
+    }
+    skip_guard_page(&mut mem);
+    let mem_start = mem.len();
+    mem.resize(mem_start + linker_context.mem_size, 0);
+    skip_guard_page(&mut mem);
+    (mem, string_ptrs, mem_start)
+}
+
+/// Leaves a page outside of any region after the last one, like the gaps between the segments of a native program,
+/// so accesses running past the end of a region are reported instead of reading the next one.
+fn skip_guard_page(mem: &mut Vec<u8>) {
+    mem.resize(mem.len().next_multiple_of(PAGE_SIZE) + PAGE_SIZE, 0);
+}
+
+/// Appends the null terminated argument and environment strings, followed by the null terminated
+/// `argv` and `envp` pointer arrays, like the kernel does on the initial stack of a process.
+/// Returns the addresses of both pointer arrays.
+fn layout_args(mem: &mut Vec<u8>, args: &[String], env: &[String]) -> (usize, usize) {
+    let mut ptrs: Vec<Vec<u64>> = vec![];
+    for strings in [args, env] {
+        let mut string_ptrs: Vec<u64> = vec![];
+        for val in strings {
+            string_ptrs.push(mem.len() as u64);
+            mem.extend_from_slice(val.as_bytes());
+            mem.push(0);
+        }
+        string_ptrs.push(0);
+        ptrs.push(string_ptrs);
+    }
+    mem.resize(mem.len().next_multiple_of(8), 0);
+    let argv_ptr = mem.len();
+    for ptr in ptrs.concat() {
+        mem.extend_from_slice(&ptr.to_le_bytes());
+    }
+    (argv_ptr, argv_ptr + (args.len() + 1) * 8)
+}
+
+pub fn simulate_tokens(linker_context: linker::LinkerContext, checked_arith: bool, args: Vec<String>) -> Result<i32, Box<RuntimeError>> {
+    let (mut mem, string_ptrs, mem_start) = layout_memory(&linker_context);
+    let string_end = NULL_REGION_SIZE + linker_context.strings.iter().map(|x| x.len() + 1).sum::<usize>();
+    let env: Vec<String> = std::env::vars_os()
+        .map(|(key, val)| format!("{}={}", key.to_string_lossy(), val.to_string_lossy()))
+        .collect();
+    let args_start = mem.len();
+    let (argv_ptr, envp_ptr) = layout_args(&mut mem, &args, &env);
+    let regions = vec![NULL_REGION_SIZE..string_end, mem_start..mem_start + linker_context.mem_size, args_start..mem.len()];
+    let mut sim = Simulation {
+        ctx: &linker_context,
+        checked_arith,
+        stack: vec![],
+        popped: vec![],
+        vars: vec![],
+        call_stack: vec![],
+        mem,
+        regions,
+        string_ptrs,
+        mem_start,
+        program_counter: 0,
+        exit_code: None,
+        files: HashMap::new(),
+        argc: args.len(),
+        argv_ptr,
+        envp_ptr,
+    };
+
+    while sim.exit_code.is_none() && sim.program_counter < linker_context.result.len() {
+        let op = &linker_context.result[sim.program_counter];
+        sim.popped.clear();
+        if let Err(kind) = sim.execute(op) {
+            //Instructions fail before pushing their results, so the popped values go back on top in reverse
+            sim.stack.extend(sim.popped.iter().rev());
+            return Err(Box::new(RuntimeError {
+                kind,
+                word: op.word.clone(),
+                instruction: compiler_string::stringify_op(op),
+                stack: sim.stack,
+                call_stack: sim.call_stack.iter().map(|x| linker_context.result[x - 1].word.clone()).collect(),
+            }));
+        }
+    }
+
+    if let Some(exit_code) = sim.exit_code {
+        return Ok(exit_code);
+    }
+    if !sim.stack.is_empty() {
+        eprintln!("ERROR: Simulation exited with leftover data on the stack");
+        eprintln!("ERROR: This may be a false positive if allowed_overflow has been used.");
+        eprintln!("{:?}", sim.stack);
+    }
+    Ok(0)
+}
+
+impl Simulation<'_> {
+    fn pop(&mut self) -> Result<u64, RuntimeErrorKind> {
+        let val = self.stack.pop().ok_or(RuntimeErrorKind::StackUnderflow)?;
+        self.popped.push(val);
+        Ok(val)
+    }
+
+    fn peek(&self, depth: usize) -> Result<u64, RuntimeErrorKind> {
+        if depth >= self.stack.len() {
+            return Err(RuntimeErrorKind::StackUnderflow);
+        }
+        Ok(self.stack[self.stack.len() - 1 - depth])
+    }
+
+    fn execute(&mut self, op: &linker::LinkedToken) -> Result<(), RuntimeErrorKind> {
         match &op.instruction {
             linker::Instruction::PushInt(x) => {
-                stack.push(*x);
-                program_counter += 1;
+                self.stack.push(*x);
+                self.program_counter += 1;
+            }
+            linker::Instruction::PushSInt(x) => {
+                self.stack.push(*x as u64);
+                self.program_counter += 1;
+            }
+            linker::Instruction::PushPtr(x) => {
+                self.stack.push(*x as u64);
+                self.program_counter += 1;
             }
-            linker::Instruction::PushPtr(x) | linker::Instruction::PushMem(x) => {
-                stack.push(*x as u64);
-                program_counter += 1;
+            linker::Instruction::PushMem(x) => {
+                self.stack.push((self.mem_start + *x) as u64);
+                self.program_counter += 1;
             }
             linker::Instruction::PushBool(x) => {
-                stack.push(if *x { 1 } else { 0 });
-                program_counter += 1;
-            }
-            linker::Instruction::PushString(x) => {
-                let str_as_chars = x.as_bytes();
-                let strlen = str_as_chars.len();
-                string_pool[string_ptr..string_ptr + strlen].copy_from_slice(str_as_chars);
-                stack.push(strlen as u64);
-                stack.push(string_ptr as u64);
-                string_ptr += strlen;
-                program_counter += 1;
+                self.stack.push(if *x { 1 } else { 0 });
+                self.program_counter += 1;
             }
-            linker::Instruction::Intrinsic(intrinsic) => {
-                match intrinsic {
-                    Intrinsic::Dump => {
-                        println!("{}", stack.pop().unwrap());
-                    }
-                    Intrinsic::Drop => {
-                        stack.pop();
-                    }
-                    Intrinsic::Dup => {
-                        let a = stack.last().unwrap().clone();
-                        stack.push(a);
-                    }
-                    Intrinsic::Over => {
-                        let a = stack[stack.len() - 2];
-                        stack.push(a);
-                    }
-                    Intrinsic::Swap => {
-                        let a = stack.pop().unwrap();
-                        let b = stack.pop().unwrap();
-                        stack.push(a);
-                        stack.push(b);
-                    }
-                    Intrinsic::Rot => {
-                        let a = stack.pop().unwrap();
-                        let b = stack.pop().unwrap();
-                        let c = stack.pop().unwrap();
-                        stack.push(b);
-                        stack.push(c);
-                        stack.push(a);
-                    }
-                    Intrinsic::Add => {
-                        let a = stack.pop().unwrap();
-                        let b = stack.pop().unwrap();
-                        stack.push(a + b);
-                    }
-                    Intrinsic::Subtract => {
-                        let a = stack.pop().unwrap();
-                        let b = stack.pop().unwrap();
-                        stack.push(b - a);
-                    }
-                    Intrinsic::Multiply => {
-                        let a = stack.pop().unwrap();
-                        let b = stack.pop().unwrap();
-                        stack.push(a * b);
-                    }
-                    Intrinsic::Divide => {
-                        let a = stack.pop().unwrap();
-                        let b = stack.pop().unwrap();
-                        stack.push(b / a);
-                    }
-                    Intrinsic::Modulo => {
-                        let a = stack.pop().unwrap();
-                        let b = stack.pop().unwrap();
-                        stack.push(b % a);
-                    }
-                    Intrinsic::ShiftLeft => {
-                        let a = stack.pop().unwrap();
-                        let b = stack.pop().unwrap();
-                        stack.push(b << a);
-                    }
-                    Intrinsic::ShiftRight => {
-                        let a = stack.pop().unwrap();
-                        let b = stack.pop().unwrap();
-                        stack.push(b >> a);
-                    }
-                    Intrinsic::BitAnd => {
-                        let a = stack.pop().unwrap();
-                        let b = stack.pop().unwrap();
-                        stack.push(b & a);
-                    }
-                    Intrinsic::BitOr => {
-                        let a = stack.pop().unwrap();
-                        let b = stack.pop().unwrap();
-                        stack.push(b | a);
-                    }
-                    Intrinsic::BitXor => {
-                        let a = stack.pop().unwrap();
-                        let b = stack.pop().unwrap();
-                        stack.push(b ^ a);
-                    }
-                    Intrinsic::Equals => {
-                        let a = stack.pop().unwrap();
-                        let b = stack.pop().unwrap();
-                        stack.push(if a == b { 1 } else { 0 });
-                    }
-                    Intrinsic::NotEquals => {
-                        let a = stack.pop().unwrap();
-                        let b = stack.pop().unwrap();
-                        stack.push(if a != b { 1 } else { 0 });
-                    }
-                    Intrinsic::Less => {
-                        let a = stack.pop().unwrap();
-                        let b = stack.pop().unwrap();
-                        stack.push(if b < a { 1 } else { 0 });
-                    }
-                    Intrinsic::Greater => {
-                        let a = stack.pop().unwrap();
-                        let b = stack.pop().unwrap();
-                        stack.push(if b > a { 1 } else { 0 });
-                    }
-                    Intrinsic::LessOrEqual => {
-                        let a = stack.pop().unwrap();
-                        let b = stack.pop().unwrap();
-                        stack.push(if b <= a { 1 } else { 0 });
-                    }
-                    Intrinsic::GreaterOrEqual => {
-                        let a = stack.pop().unwrap();
-                        let b = stack.pop().unwrap();
-                        stack.push(if b >= a { 1 } else { 0 });
-                    }
-                    Intrinsic::Store8 | Intrinsic::Store16 | Intrinsic::Store32 | Intrinsic::Store64 => {
-                        let ptr = stack.pop().unwrap() as usize;
-                        let a = stack.pop().unwrap();
-                        mem[ptr] = a;
-                    }
-                    Intrinsic::Load8 | Intrinsic::Load16 | Intrinsic::Load32 | Intrinsic::Load64 => {
-                        let ptr = stack.pop().unwrap() as usize;
-                        let x = mem[ptr];
-                        stack.push(x);
-                    }
+            linker::Instruction::PushString(x) => match op.data {
+                LinkedTokenData::Index(string_index) => {
+                    self.stack.push(x.len() as u64);
+                    self.stack.push(self.string_ptrs[string_index] as u64);
+                    self.program_counter += 1;
                 }
-                program_counter += 1;
+                _ => panic!(),
+            },
+            linker::Instruction::Intrinsic(intrinsic) => {
+                self.execute_intrinsic(op, intrinsic)?;
+                self.program_counter += 1;
             }
             linker::Instruction::Call => match op.data {
                 LinkedTokenData::JumpAddr(ptr) => {
-                    call_stack.push(program_counter + 1);
-                    program_counter = ptr;
+                    self.call_stack.push(self.program_counter + 1);
+                    self.program_counter = ptr;
                 }
                 _ => panic!(),
             },
+            linker::Instruction::CallExtern => return Err(RuntimeErrorKind::UnsupportedExtern(op.word.txt.clone())),
             linker::Instruction::Return => {
-                let return_ptr = call_stack.pop().unwrap();
-                program_counter = return_ptr;
+                let return_ptr = self.call_stack.pop().ok_or(RuntimeErrorKind::InvalidReturn)?;
+                if return_ptr > self.ctx.result.len() {
+                    return Err(RuntimeErrorKind::InvalidReturn);
+                }
+                self.program_counter = return_ptr;
             }
             linker::Instruction::PushVars => match op.data {
                 LinkedTokenData::Count(count) => {
                     for _ in 0..count {
-                        let x = stack.pop().unwrap();
-                        vars.push(x);
+                        let x = self.pop()?;
+                        self.vars.push(x);
                     }
-                    program_counter += 1;
+                    self.program_counter += 1;
                 }
                 _ => panic!(),
             },
             linker::Instruction::ApplyVar => match op.data {
                 LinkedTokenData::Index(var_index) => {
-                    let x = vars[vars.len() - 1 - var_index];
-                    stack.push(x);
-                    program_counter += 1;
+                    if var_index >= self.vars.len() {
+                        return Err(RuntimeErrorKind::VarStackUnderflow);
+                    }
+                    let x = self.vars[self.vars.len() - 1 - var_index];
+                    self.stack.push(x);
+                    self.program_counter += 1;
                 }
                 _ => panic!(),
             },
             linker::Instruction::PopVars => match op.data {
                 LinkedTokenData::Count(count) => {
                     for _ in 0..count {
-                        vars.pop();
+                        self.vars.pop().ok_or(RuntimeErrorKind::VarStackUnderflow)?;
                     }
-                    program_counter += 1;
+                    self.program_counter += 1;
                 }
                 _ => panic!(),
             },
             linker::Instruction::Function => {
-                program_counter += 1;
+                self.program_counter += 1;
             }
             linker::Instruction::Jump => match op.data {
                 LinkedTokenData::JumpAddr(ptr) => {
-                    program_counter = ptr;
+                    self.program_counter = ptr;
                 }
                 _ => panic!(),
             },
-            linker::Instruction::JumpNeq => {
-                let flag = stack.pop().unwrap();
+            linker::Instruction::JumpNeq | linker::Instruction::Do => {
+                let flag = self.pop()?;
                 if flag == 0 {
                     match op.data {
                         LinkedTokenData::JumpAddr(ptr) => {
-                            program_counter = ptr;
+                            self.program_counter = ptr;
                         }
                         _ => panic!(),
                     }
-                    continue;
+                } else {
+                    self.program_counter += 1;
                 }
-                program_counter += 1;
             }
-            linker::Instruction::Do => {
-                let flag = stack.pop().unwrap();
-                if flag == 0 {
-                    match op.data {
-                        LinkedTokenData::JumpAddr(ptr) => {
-                            program_counter = ptr;
-                        }
-                        _ => panic!(),
+        }
+        Ok(())
+    }
+
+    fn execute_intrinsic(&mut self, op: &linker::LinkedToken, intrinsic: &Intrinsic) -> Result<(), RuntimeErrorKind> {
+        match intrinsic {
+            Intrinsic::Dump => {
+                println!("{}", self.pop()?);
+            }
+            Intrinsic::Puts | Intrinsic::EPuts => {
+                let ptr = self.pop()?;
+                let len = self.pop()? as usize;
+                if len > 0 {
+                    let range = self.mem_range(ptr, len)?;
+                    if matches!(intrinsic, Intrinsic::Puts) {
+                        std::io::stdout().write_all(&self.mem[range]).unwrap();
+                        std::io::stdout().flush().unwrap();
+                    } else {
+                        std::io::stderr().write_all(&self.mem[range]).unwrap();
                     }
-                    continue;
                 }
-                program_counter += 1;
+            }
+            Intrinsic::Drop => {
+                self.pop()?;
+            }
+            Intrinsic::Dup => {
+                let a = self.peek(0)?;
+                self.stack.push(a);
+            }
+            Intrinsic::Over => {
+                let a = self.peek(1)?;
+                self.stack.push(a);
+            }
+            Intrinsic::Swap => {
+                let a = self.pop()?;
+                let b = self.pop()?;
+                self.stack.push(a);
+                self.stack.push(b);
+            }
+            Intrinsic::Rot => {
+                let a = self.pop()?;
+                let b = self.pop()?;
+                let c = self.pop()?;
+                self.stack.push(b);
+                self.stack.push(a);
+                self.stack.push(c);
+            }
+            Intrinsic::Add => {
+                let a = self.pop()?;
+                let b = self.pop()?;
+                self.check_overflow(intrinsic, self.ctx.signed_ops.contains(&op.self_ptr), a, b)?;
+                self.stack.push(b.wrapping_add(a));
+            }
+            Intrinsic::Subtract => {
+                let a = self.pop()?;
+                let b = self.pop()?;
+                self.check_overflow(intrinsic, self.ctx.signed_ops.contains(&op.self_ptr), a, b)?;
+                self.stack.push(b.wrapping_sub(a));
+            }
+            Intrinsic::Multiply => {
+                let a = self.pop()?;
+                let b = self.pop()?;
+                self.check_overflow(intrinsic, self.ctx.signed_ops.contains(&op.self_ptr), a, b)?;
+                self.stack.push(b.wrapping_mul(a));
+            }
+            Intrinsic::Divide => {
+                let a = self.pop()?;
+                let b = self.pop()?;
+                if a == 0 {
+                    return Err(RuntimeErrorKind::DivisionByZero(b));
+                }
+                self.stack.push(b / a);
+            }
+            Intrinsic::Modulo => {
+                let a = self.pop()?;
+                let b = self.pop()?;
+                if a == 0 {
+                    return Err(RuntimeErrorKind::DivisionByZero(b));
+                }
+                self.stack.push(b % a);
+            }
+            Intrinsic::DivideSigned => {
+                let a = self.pop()?;
+                let b = self.pop()?;
+                if a == 0 {
+                    return Err(RuntimeErrorKind::DivisionByZero(b));
+                }
+                self.check_overflow(intrinsic, true, a, b)?;
+                self.stack.push((b as i64).wrapping_div(a as i64) as u64);
+            }
+            Intrinsic::ModuloSigned => {
+                let a = self.pop()?;
+                let b = self.pop()?;
+                if a == 0 {
+                    return Err(RuntimeErrorKind::DivisionByZero(b));
+                }
+                self.check_overflow(intrinsic, true, a, b)?;
+                self.stack.push((b as i64).wrapping_rem(a as i64) as u64);
+            }
+            Intrinsic::ShiftLeft => {
+                let a = self.pop()?;
+                let b = self.pop()?;
+                self.check_overflow(intrinsic, self.ctx.signed_ops.contains(&op.self_ptr), a, b)?;
+                self.stack.push(b.wrapping_shl(a as u32));
+            }
+            Intrinsic::ShiftRight => {
+                let a = self.pop()?;
+                let b = self.pop()?;
+                self.check_overflow(intrinsic, false, a, b)?;
+                self.stack.push(b.wrapping_shr(a as u32));
+            }
+            Intrinsic::ShiftRightSigned => {
+                let a = self.pop()?;
+                let b = self.pop()?;
+                self.check_overflow(intrinsic, true, a, b)?;
+                self.stack.push((b as i64).wrapping_shr(a as u32) as u64);
+            }
+            Intrinsic::BitAnd => {
+                let a = self.pop()?;
+                let b = self.pop()?;
+                self.stack.push(b & a);
+            }
+            Intrinsic::BitOr => {
+                let a = self.pop()?;
+                let b = self.pop()?;
+                self.stack.push(b | a);
+            }
+            Intrinsic::BitXor => {
+                let a = self.pop()?;
+                let b = self.pop()?;
+                self.stack.push(b ^ a);
+            }
+            Intrinsic::Equals => {
+                let a = self.pop()?;
+                let b = self.pop()?;
+                self.stack.push(if a == b { 1 } else { 0 });
+            }
+            Intrinsic::NotEquals => {
+                let a = self.pop()?;
+                let b = self.pop()?;
+                self.stack.push(if a != b { 1 } else { 0 });
+            }
+            Intrinsic::Less => {
+                let a = self.pop()?;
+                let b = self.pop()?;
+                self.stack.push(if b < a { 1 } else { 0 });
+            }
+            Intrinsic::Greater => {
+                let a = self.pop()?;
+                let b = self.pop()?;
+                self.stack.push(if b > a { 1 } else { 0 });
+            }
+            Intrinsic::LessOrEqual => {
+                let a = self.pop()?;
+                let b = self.pop()?;
+                self.stack.push(if b <= a { 1 } else { 0 });
+            }
+            Intrinsic::GreaterOrEqual => {
+                let a = self.pop()?;
+                let b = self.pop()?;
+                self.stack.push(if b >= a { 1 } else { 0 });
+            }
+            Intrinsic::LessSigned => {
+                let a = self.pop()? as i64;
+                let b = self.pop()? as i64;
+                self.stack.push(if b < a { 1 } else { 0 });
+            }
+            Intrinsic::GreaterSigned => {
+                let a = self.pop()? as i64;
+                let b = self.pop()? as i64;
+                self.stack.push(if b > a { 1 } else { 0 });
+            }
+            Intrinsic::LessOrEqualSigned => {
+                let a = self.pop()? as i64;
+                let b = self.pop()? as i64;
+                self.stack.push(if b <= a { 1 } else { 0 });
+            }
+            Intrinsic::GreaterOrEqualSigned => {
+                let a = self.pop()? as i64;
+                let b = self.pop()? as i64;
+                self.stack.push(if b >= a { 1 } else { 0 });
+            }
+            Intrinsic::CastInt | Intrinsic::CastSInt | Intrinsic::CastPtr => {
+                self.peek(0)?;
+            }
+            Intrinsic::Store8 | Intrinsic::Store16 | Intrinsic::Store32 | Intrinsic::Store64 => {
+                let ptr = self.pop()?;
+                let a = self.pop()?;
+                let range = self.mem_range(ptr, access_width(intrinsic))?;
+                self.mem[range].copy_from_slice(&a.to_le_bytes()[..access_width(intrinsic)]);
+            }
+            Intrinsic::Load8 | Intrinsic::Load16 | Intrinsic::Load32 | Intrinsic::Load64 => {
+                let ptr = self.pop()?;
+                let range = self.mem_range(ptr, access_width(intrinsic))?;
+                let mut bytes = [0u8; 8];
+                bytes[..access_width(intrinsic)].copy_from_slice(&self.mem[range]);
+                self.stack.push(u64::from_le_bytes(bytes));
+            }
+            Intrinsic::Exit => {
+                self.exit_code = Some(self.pop()? as i32);
+            }
+            Intrinsic::Argc => {
+                self.stack.push(self.argc as u64);
+            }
+            Intrinsic::Argv => {
+                self.stack.push(self.argv_ptr as u64);
+            }
+            Intrinsic::Envp => {
+                self.stack.push(self.envp_ptr as u64);
+            }
+            Intrinsic::Syscall0 | Intrinsic::Syscall1 | Intrinsic::Syscall2 | Intrinsic::Syscall3 | Intrinsic::Syscall4 | Intrinsic::Syscall5 | Intrinsic::Syscall6 => {
+                let number = self.pop()?;
+                let mut args = [0u64; 6];
+                for arg in args.iter_mut().take(tokenizer::get_syscall_arg_count(intrinsic)) {
+                    *arg = self.pop()?;
+                }
+                let result = self.execute_syscall(number, args)?;
+                self.stack.push(result);
             }
         }
+        Ok(())
     }
 
-    if !stack.is_empty() {
-        eprintln!("ERROR: Simulation exited with leftover data on the stack");
-        eprintln!("ERROR: This may be a false positive if allowed_overflow has been used.");
-        eprintln!("{:?}", stack);
+    fn execute_syscall(&mut self, number: u64, args: [u64; 6]) -> Result<u64, RuntimeErrorKind> {
+        let result = match number {
+            SYS_READ => {
+                let (fd, ptr, count) = (args[0], args[1], args[2] as usize);
+                if count == 0 {
+                    return Ok(0);
+                }
+                let Ok(range) = self.mem_range(ptr, count) else {
+                    return Ok(errno(EFAULT));
+                };
+                let buf = &mut self.mem[range];
+                match fd {
+                    0 => std::io::stdin().read(buf),
+                    _ => match self.files.get_mut(&fd) {
+                        Some(file) => file.read(buf),
+                        None => return Ok(errno(EBADF)),
+                    },
+                }
+            }
+            SYS_WRITE => {
+                let (fd, ptr, count) = (args[0], args[1], args[2] as usize);
+                if count == 0 {
+                    return Ok(0);
+                }
+                let Ok(range) = self.mem_range(ptr, count) else {
+                    return Ok(errno(EFAULT));
+                };
+                let buf = &self.mem[range];
+                let written = match fd {
+                    1 => std::io::stdout().write_all(buf).and_then(|_| std::io::stdout().flush()),
+                    2 => std::io::stderr().write_all(buf),
+                    _ => match self.files.get_mut(&fd) {
+                        Some(file) => file.write_all(buf),
+                        None => return Ok(errno(EBADF)),
+                    },
+                };
+                written.map(|_| count)
+            }
+            SYS_OPEN => {
+                let Some(path) = self.read_c_string(args[0]) else {
+                    return Ok(errno(EFAULT));
+                };
+                let flags = args[1];
+                let mut options = std::fs::OpenOptions::new();
+                match flags & O_ACCMODE {
+                    0 => options.read(true),
+                    1 => options.write(true),
+                    _ => options.read(true).write(true),
+                };
+                options.create(flags & O_CREAT != 0).truncate(flags & O_TRUNC != 0).append(flags & O_APPEND != 0);
+                options.open(path).map(|file| {
+                    let fd = (3..).find(|x| !self.files.contains_key(x)).unwrap();
+                    self.files.insert(fd, file);
+                    fd as usize
+                })
+            }
+            SYS_CLOSE => {
+                let fd = args[0];
+                if fd > 2 && self.files.remove(&fd).is_none() {
+                    return Ok(errno(EBADF));
+                }
+                Ok(0)
+            }
+            SYS_MMAP => {
+                let (length, flags) = (args[1] as usize, args[3]);
+                if flags & MAP_ANONYMOUS == 0 {
+                    return Err(RuntimeErrorKind::UnsupportedSyscall("mmap only supports anonymous mappings".to_string()));
+                }
+                if length == 0 {
+                    return Ok(errno(EINVAL));
+                }
+                //Mappings are placed at the end of the address space, aligned to whole pages
+                let start = self.mem.len().next_multiple_of(PAGE_SIZE);
+                self.mem.resize(start + length.next_multiple_of(PAGE_SIZE), 0);
+                self.regions.push(start..self.mem.len());
+                Ok(start)
+            }
+            SYS_EXIT => {
+                self.exit_code = Some(args[0] as i32);
+                Ok(0)
+            }
+            _ => return Err(RuntimeErrorKind::UnsupportedSyscall(format!("number {}", number))),
+        };
+        Ok(match result {
+            Ok(x) => x as u64,
+            Err(err) => errno(err.raw_os_error().map(|x| x as i64).unwrap_or(EINVAL)),
+        })
+    }
+
+    fn read_c_string(&self, ptr: u64) -> Option<String> {
+        let start = ptr as usize;
+        let region = self.regions.iter().find(|x| x.contains(&start))?;
+        let len = self.mem[start..region.end].iter().position(|x| *x == 0)?;
+        Some(String::from_utf8_lossy(&self.mem[start..start + len]).to_string())
+    }
+
+    /// The bytes accessed at `ptr`, which have to lie within the region `ptr` points into.
+    fn mem_range(&self, ptr: u64, width: usize) -> Result<std::ops::Range<usize>, RuntimeErrorKind> {
+        let start = ptr as usize;
+        if width == 0 {
+            return Ok(0..0);
+        }
+        match (self.regions.iter().find(|x| x.contains(&start)), start.checked_add(width)) {
+            (Some(region), Some(end)) if end <= region.end => Ok(start..end),
+            _ => Err(RuntimeErrorKind::MemoryOutOfBounds(ptr, width)),
+        }
+    }
+
+    //Shift counts are masked to 6 bits and all other arithmetic wraps around, just like on x86-64.
+    //In checked mode these cases are reported instead, signed values are detected by the typechecker.
+    fn check_overflow(&self, intrinsic: &Intrinsic, signed: bool, a: u64, b: u64) -> Result<(), RuntimeErrorKind> {
+        if !self.checked_arith {
+            return Ok(());
+        }
+        let overflow = match intrinsic {
+            Intrinsic::Add if signed => (b as i64).checked_add(a as i64).is_none(),
+            Intrinsic::Add => b.checked_add(a).is_none(),
+            Intrinsic::Subtract if signed => (b as i64).checked_sub(a as i64).is_none(),
+            Intrinsic::Subtract => b.checked_sub(a).is_none(),
+            Intrinsic::Multiply if signed => (b as i64).checked_mul(a as i64).is_none(),
+            Intrinsic::Multiply => b.checked_mul(a).is_none(),
+            Intrinsic::DivideSigned | Intrinsic::ModuloSigned => b as i64 == i64::MIN && a as i64 == -1,
+            Intrinsic::ShiftLeft if signed => a >= 64 || ((b as i64) << a) >> a != b as i64,
+            Intrinsic::ShiftLeft => a >= 64 || (b << a) >> a != b,
+            Intrinsic::ShiftRight | Intrinsic::ShiftRightSigned => a >= 64,
+            _ => false,
+        };
+        if !overflow {
+            return Ok(());
+        }
+        let operands = if signed { format!("{} and {}", b as i64, a as i64) } else { format!("{} and {}", b, a) };
+        Err(RuntimeErrorKind::ArithmeticOverflow(operands))
+    }
+}
+
+fn errno(code: i64) -> u64 {
+    (-code) as u64
+}
+
+fn access_width(intrinsic: &Intrinsic) -> usize {
+    match intrinsic {
+        Intrinsic::Store8 | Intrinsic::Load8 => 1,
+        Intrinsic::Store16 | Intrinsic::Load16 => 2,
+        Intrinsic::Store32 | Intrinsic::Load32 => 4,
+        Intrinsic::Store64 | Intrinsic::Load64 => 8,
+        _ => panic!("Intrinsic '{}' does not access memory", intrinsic),
     }
 }
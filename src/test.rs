@@ -1,42 +1,81 @@
-use crate::{add_or_replace_extension, read_file_contents};
+use crate::{add_or_replace_extension, compiler, read_file_contents};
 use std::path::Path;
 
+//Exit code of a test which could not be run, like the automake test drivers use
+const SKIPPED_EXIT_CODE: i32 = 77;
+
+pub struct TestOptions<'a> {
+    /// The id of the compiler, or `simulate`
+    pub compiler: &'a str,
+    /// Print the output of failing tests
+    pub print: bool,
+    /// Skip typechecking the tested programs
+    pub skip_typecheck: bool,
+    /// Compile the tested programs with runtime checks
+    pub checked: bool,
+    /// Simulate the tested programs reporting arithmetic overflow
+    pub checked_arith: bool,
+    /// Pass when a test can not be run on this machine, instead of failing
+    pub allow_skip: bool,
+}
+
 struct TestFile {
     exit_code: i32,
     stdout: Vec<String>,
     stderr: Vec<String>,
 }
 
-pub fn test_program(self_path: String, mut file_path: String, print: bool, skip_typecheck: bool, compiler: &str) {
+pub fn test_program(self_path: String, mut file_path: String, options: &TestOptions) {
     let absolute_file_path = Path::new(file_path.as_str()).canonicalize().unwrap();
     file_path = absolute_file_path.to_str().unwrap().to_string();
-    if compiler != "simulate" {
-        compile_test_program(self_path.clone(), file_path.clone(), skip_typecheck, compiler);
+    if options.compiler != "simulate" {
+        compile_test_program(self_path.clone(), file_path.clone(), options);
     }
     let test_file = parse_test_file(&(file_path.clone() + ".txt"));
-    if compiler == "simulate" {
-        let mut cmd = std::process::Command::new(self_path);
-        cmd.arg("simulate").arg(file_path.clone());
-        if skip_typecheck {
+    if options.compiler == "simulate" {
+        //Simulated from its own directory like compiled tests, see compile_test_program
+        let path = Path::new(&file_path);
+        let mut cmd = std::process::Command::new(std::env::current_exe().unwrap_or_else(|_| self_path.into()));
+        cmd.current_dir(path.parent().unwrap()).arg("simulate");
+        if options.skip_typecheck {
             cmd.arg("--unsafe");
         }
-        validate_tested_program(&mut cmd, test_file, file_path, print);
+        if options.checked_arith {
+            cmd.arg("--checked-arith");
+        }
+        cmd.arg(path.file_name().unwrap());
+        validate_tested_program(&mut cmd, test_file, file_path, options.print);
     } else {
-        let extension = if compiler == "asm-win64" { "exe" } else { "" };
-        let test_exe_path = add_or_replace_extension(&file_path, extension);
-        let mut cmd = std::process::Command::new(test_exe_path);
-        validate_tested_program(&mut cmd, test_file, file_path, print);
+        let backend = compiler::find_backend(options.compiler).unwrap();
+        let mut cmd = backend.run_command(&file_path).unwrap_or_else(|reason| {
+            println!("INFO: Skipped running {} as {}", file_path, reason);
+            std::process::exit(if options.allow_skip { 0 } else { SKIPPED_EXIT_CODE });
+        });
+        validate_tested_program(&mut cmd, test_file, file_path, options.print);
     }
 }
 
-fn compile_test_program(self_path: String, file_path: String, skip_typecheck: bool, compiler: &str) {
-    let mut cmd = std::process::Command::new(self_path);
-    cmd.arg("compile").arg(format!("--use={}", compiler));
-    if skip_typecheck {
+fn compile_test_program(self_path: String, file_path: String, options: &TestOptions) {
+    //The compiler runs in the directory of the test, where a relative path to itself would not be found
+    let mut cmd = std::process::Command::new(std::env::current_exe().unwrap_or_else(|_| self_path.into()));
+    cmd.arg("compile").arg(format!("--use={}", options.compiler));
+    if options.skip_typecheck {
         cmd.arg("--unsafe");
     }
-    cmd.arg(file_path.clone()).stdout(std::process::Stdio::inherit()).stderr(std::process::Stdio::inherit());
+    if options.checked {
+        cmd.arg("--checked");
+    }
+    //Compiled from its own directory, the locations in runtime errors do not depend on where the tests are checked out
+    let path = Path::new(&file_path);
+    cmd.current_dir(path.parent().unwrap()).arg(path.file_name().unwrap());
+    cmd.stdout(std::process::Stdio::inherit()).stderr(std::process::Stdio::inherit());
+    //Most compilers write the same executable, one left behind by another compiler must not be tested instead
+    let _ = std::fs::remove_file(add_or_replace_extension(&file_path, ""));
     match cmd.output() {
+        Ok(output) if !output.status.success() => {
+            eprintln!("ERROR: Could not compile test program {}!", file_path);
+            std::process::exit(1);
+        }
         Ok(_) => {
             eprintln!("SUCCESS: Successfully compiled test program {}!", file_path);
             eprintln!("SUCCESS: Binary is located at {}!", file_path);
@@ -136,8 +175,7 @@ fn parse_test_file(file: &str) -> TestFile {
             let mut out: Vec<String> = vec![];
             let mut err: Vec<String> = vec![];
             let mut is_stdout = true;
-            while !contents.is_empty() {
-                let token = contents.pop().unwrap();
+            while let Some(token) = contents.pop() {
                 if "out:".eq(token.as_str()) {
                     is_stdout = true;
                 } else if "err:".eq(token.as_str()) {
@@ -168,12 +206,12 @@ fn test_text_output(expected: Vec<String>, actual: Vec<u8>) -> Option<(bool, Vec
     }
     if let Ok(actual_string) = String::from_utf8(actual.clone()) {
         let splitted: Vec<String> = actual_string.lines().map(|x| x.to_string()).collect();
-        return Some((&expected == &splitted, splitted));
+        return Some((expected == splitted, splitted));
     }
     None
 }
 
-pub fn run_all_tests(self_path: String, file_path: String, print: bool, skip_typecheck: bool, compiler: &str) {
+pub fn run_all_tests(self_path: String, file_path: String, options: &TestOptions) {
     let path = std::path::Path::new(file_path.as_str());
     if !path.exists() {
         eprintln!("ERROR: Directory does not exist: {}", file_path);
@@ -199,18 +237,25 @@ pub fn run_all_tests(self_path: String, file_path: String, print: bool, skip_typ
         })
         .collect();
     let mut failed: Vec<String> = vec![];
+    let mut skipped: Vec<String> = vec![];
     for test_path in paths {
         let test_path_string = test_path.to_string_lossy().to_string();
         eprintln!("INFO: Running test: {}", test_path_string);
         let mut cmd_builder = std::process::Command::new(self_path.clone());
         cmd_builder.arg("test");
-        if print {
+        if options.print {
             cmd_builder.arg("--print");
         }
-        if skip_typecheck {
+        if options.skip_typecheck {
             cmd_builder.arg("--unsafe");
         }
-        cmd_builder.arg(format!("--use={}", compiler));
+        if options.checked {
+            cmd_builder.arg("--checked");
+        }
+        if options.checked_arith {
+            cmd_builder.arg("--checked-arith");
+        }
+        cmd_builder.arg(format!("--use={}", options.compiler));
         let cmd = cmd_builder
             .arg(test_path_string.clone())
             .stdout(std::process::Stdio::piped())
@@ -218,10 +263,11 @@ pub fn run_all_tests(self_path: String, file_path: String, print: bool, skip_typ
             .output();
         match cmd {
             Ok(output) => match output.status.code() {
+                Some(SKIPPED_EXIT_CODE) => skipped.push(test_path_string.to_string()),
                 Some(code) => {
                     if code != 0 {
                         failed.push(test_path_string.to_string());
-                        if print {
+                        if options.print {
                             println!("======== STDOUT ========");
                             println!("{}", String::from_utf8(output.stdout).unwrap());
                             println!("======== STDERR ========");
@@ -242,14 +288,24 @@ pub fn run_all_tests(self_path: String, file_path: String, print: bool, skip_typ
             }
         }
     }
+    if !skipped.is_empty() {
+        eprintln!("INFO: Some tests were skipped:");
+        for skipped_path in &skipped {
+            eprintln!("{}", skipped_path);
+        }
+    }
     if !failed.is_empty() {
         eprintln!("ERROR: Some tests failed:");
         for failed_path in failed {
             eprintln!("{}", failed_path);
         }
-        if !print {
+        if !options.print {
             eprintln!("Add the --print option to print the test outputs");
         }
         std::process::exit(1);
     }
+    if !skipped.is_empty() && !options.allow_skip {
+        eprintln!("ERROR: Skipped tests count as failed, add the --allow-skip option to allow them");
+        std::process::exit(1);
+    }
 }
@@ -3,6 +3,9 @@ use crate::{checker, lexer, read_file_contents};
 use std::collections::HashMap;
 use std::fmt::{Display, Formatter};
 
+/// External and exported functions follow the System V calling convention, which passes up to six integer arguments in registers.
+const C_MAX_INS: usize = 6;
+
 pub struct ConstDef {
     pub typ: checker::DataType,
     pub val: u64,
@@ -16,6 +19,8 @@ pub struct MemoryDef {
 pub struct FunctionDef {
     pub ins: Vec<TypedPos>,
     pub outs: Vec<TypedPos>,
+    //Exported functions can be called from C when the program is compiled as a library
+    pub export: bool,
 }
 
 pub struct ParserContext {
@@ -39,6 +44,8 @@ pub struct Token {
 
 pub enum Op {
     PushInt(u64),
+    PushSInt(i64),
+    #[allow(dead_code)]
     PushPtr(usize),
     PushBool(bool),
     PushString(String),
@@ -50,6 +57,7 @@ pub enum Op {
     MemRef(String),
     Function(String),
     FunctionRef(String),
+    Extern(String),
     Var(Vec<String>),
     VarRef(String),
 
@@ -63,18 +71,24 @@ pub enum Op {
 #[derive(Copy, Clone)]
 pub enum Intrinsic {
     Dump,
+    Puts,
+    EPuts,
     Drop,
     Dup,
     Over,
     Swap,
     Rot,
+    //Binary operators take their right hand side from the top of the stack, so `5 3 -` is 2 and `5 3 <` is false
     Add,
     Subtract,
     Multiply,
     Divide,
+    DivideSigned,
     Modulo,
+    ModuloSigned,
     ShiftLeft,
     ShiftRight,
+    ShiftRightSigned,
     BitAnd,
     BitOr,
     BitXor,
@@ -84,6 +98,14 @@ pub enum Intrinsic {
     Greater,
     LessOrEqual,
     GreaterOrEqual,
+    LessSigned,
+    GreaterSigned,
+    LessOrEqualSigned,
+    GreaterOrEqualSigned,
+    //Casts only change how the value is typechecked, they do not compile to any code
+    CastInt,
+    CastSInt,
+    CastPtr,
     Store8,
     Store16,
     Store32,
@@ -92,12 +114,24 @@ pub enum Intrinsic {
     Load16,
     Load32,
     Load64,
+    Exit,
+    Argc,
+    Argv,
+    Envp,
+    Syscall0,
+    Syscall1,
+    Syscall2,
+    Syscall3,
+    Syscall4,
+    Syscall5,
+    Syscall6,
 }
 
 impl Display for Op {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         let txt = match self {
             Op::PushInt(_) => "PUSH_INT",
+            Op::PushSInt(_) => "PUSH_SINT",
             Op::PushPtr(_) => "PUSH_PTR",
             Op::PushBool(_) => "PUSH_BOOL",
             Op::PushString(_) => "PUSH_STRING",
@@ -109,6 +143,7 @@ impl Display for Op {
             Op::MemRef(_) => "MEM_REF",
             Op::Function(_) => "FUNCTION",
             Op::FunctionRef(_) => "FUNCTION_REF",
+            Op::Extern(_) => "EXTERN",
             Op::Var(_) => "VAR",
             Op::VarRef(_) => "VAR_REF",
 
@@ -126,6 +161,8 @@ impl Display for Intrinsic {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         let txt = match self {
             Intrinsic::Dump => "DUMP",
+            Intrinsic::Puts => "PUTS",
+            Intrinsic::EPuts => "EPUTS",
             Intrinsic::Drop => "DROP",
             Intrinsic::Dup => "DUP",
             Intrinsic::Over => "OVER",
@@ -135,9 +172,12 @@ impl Display for Intrinsic {
             Intrinsic::Subtract => "SUBTRACT",
             Intrinsic::Multiply => "MULTIPLY",
             Intrinsic::Divide => "DIVIDE",
+            Intrinsic::DivideSigned => "DIVIDE_SIGNED",
             Intrinsic::Modulo => "MODULO",
+            Intrinsic::ModuloSigned => "MODULO_SIGNED",
             Intrinsic::ShiftLeft => "SHIFT_LEFT",
             Intrinsic::ShiftRight => "SHIFT_RIGHT",
+            Intrinsic::ShiftRightSigned => "SHIFT_RIGHT_SIGNED",
             Intrinsic::BitAnd => "BIT_AND",
             Intrinsic::BitOr => "BIT_OR",
             Intrinsic::BitXor => "BIT_XOR",
@@ -147,6 +187,13 @@ impl Display for Intrinsic {
             Intrinsic::Greater => "GREATER",
             Intrinsic::LessOrEqual => "LESS_OR_EQUAL",
             Intrinsic::GreaterOrEqual => "GREATER_OR_EQUAL",
+            Intrinsic::LessSigned => "LESS_SIGNED",
+            Intrinsic::GreaterSigned => "GREATER_SIGNED",
+            Intrinsic::LessOrEqualSigned => "LESS_OR_EQUAL_SIGNED",
+            Intrinsic::GreaterOrEqualSigned => "GREATER_OR_EQUAL_SIGNED",
+            Intrinsic::CastInt => "CAST_INT",
+            Intrinsic::CastSInt => "CAST_SINT",
+            Intrinsic::CastPtr => "CAST_PTR",
             Intrinsic::Store8 => "STORE_8",
             Intrinsic::Store16 => "STORE_16",
             Intrinsic::Store32 => "STORE_32",
@@ -155,6 +202,17 @@ impl Display for Intrinsic {
             Intrinsic::Load16 => "LOAD_16",
             Intrinsic::Load32 => "LOAD_32",
             Intrinsic::Load64 => "LOAD_64",
+            Intrinsic::Exit => "EXIT",
+            Intrinsic::Argc => "ARGC",
+            Intrinsic::Argv => "ARGV",
+            Intrinsic::Envp => "ENVP",
+            Intrinsic::Syscall0 => "SYSCALL_0",
+            Intrinsic::Syscall1 => "SYSCALL_1",
+            Intrinsic::Syscall2 => "SYSCALL_2",
+            Intrinsic::Syscall3 => "SYSCALL_3",
+            Intrinsic::Syscall4 => "SYSCALL_4",
+            Intrinsic::Syscall5 => "SYSCALL_5",
+            Intrinsic::Syscall6 => "SYSCALL_6",
         };
         write!(f, "{}", txt)
     }
@@ -175,22 +233,22 @@ pub fn parse_words_into_tokens(mut words: Vec<lexer::Word>) -> ParserContext {
         var_stack: vec![],
         current_block_id: 0,
     };
-    while !words.is_empty() {
-        let word = words.pop().unwrap();
-        match parse_word_into_token(&mut ctx, &mut words, word) {
-            Some(token) => ctx.result.push(token),
-            _ => {}
-        };
+    while let Some(word) = words.pop() {
+        if let Some(token) = parse_word_into_token(&mut ctx, &mut words, word) {
+            ctx.result.push(token);
+        }
     }
     ctx
 }
 
 fn parse_word_into_token(ctx: &mut ParserContext, words: &mut Vec<lexer::Word>, word: lexer::Word) -> Option<Token> {
-    match word.txt.parse::<u64>() {
-        Ok(x) => {
-            return Some(Token { word, op: Op::PushInt(x) });
-        }
-        Err(_) => {}
+    if let Ok(x) = word.txt.parse::<u64>() {
+        return Some(Token { word, op: Op::PushInt(x) });
+    }
+    if word.txt.starts_with('-')
+        && let Ok(x) = word.txt.parse::<i64>()
+    {
+        return Some(Token { word, op: Op::PushSInt(x) });
     }
     if word.txt.starts_with('"') && word.txt.ends_with('"') {
         let content = word.txt.clone();
@@ -221,17 +279,14 @@ fn parse_word_into_token(ctx: &mut ParserContext, words: &mut Vec<lexer::Word>,
         let value = "true" == word.txt;
         return Some(Token { word, op: Op::PushBool(value) });
     }
-    match get_intrinsic_by_word(&word.txt) {
-        Some(intrinsic) => {
-            return Some(Token {
-                word,
-                op: Op::Intrinsic(intrinsic),
-            });
-        }
-        None => {}
+    if let Some(intrinsic) = get_intrinsic_by_word(&word.txt) {
+        return Some(Token {
+            word,
+            op: Op::Intrinsic(intrinsic),
+        });
     }
-    match get_operation_by_word(&word.txt) {
-        Some(op) => match op {
+    if let Some(op) = get_operation_by_word(&word.txt) {
+        match op {
             Op::End => {
                 let last_block_id = ctx.block_stack.pop().unwrap();
                 if ctx.var_stack.contains(&last_block_id) {
@@ -275,8 +330,7 @@ fn parse_word_into_token(ctx: &mut ParserContext, words: &mut Vec<lexer::Word>,
                 return Some(Token { word, op });
             }
             _ => panic!("Encountered unhandled operation: {}", op),
-        },
-        None => {}
+        }
     }
     if "const" == word.txt {
         if words.is_empty() {
@@ -317,6 +371,37 @@ fn parse_word_into_token(ctx: &mut ParserContext, words: &mut Vec<lexer::Word>,
         ctx.current_block_id += 1;
         return Some(parse_function(ctx, words, &word));
     }
+    if "extern" == word.txt {
+        if words.pop().is_none_or(|x| x.txt != "function") || words.is_empty() {
+            eprintln!("{}: ERROR: Encountered incomplete external function signature", word);
+            std::process::exit(1);
+        }
+        //External functions have no body, so unlike functions they do not open a block
+        let token = parse_function(ctx, words, &word);
+        check_c_signature(ctx, &token.word, "External");
+        let name = token.word.txt.clone();
+        return Some(Token {
+            word: token.word,
+            op: Op::Extern(name),
+        });
+    }
+    if "export" == word.txt {
+        if words.pop().is_none_or(|x| x.txt != "function") || words.is_empty() {
+            eprintln!("{}: ERROR: Encountered incomplete exported function signature", word);
+            std::process::exit(1);
+        }
+        ctx.block_stack.push(ctx.current_block_id);
+        ctx.current_block_id += 1;
+        let token = parse_function(ctx, words, &word);
+        check_c_signature(ctx, &token.word, "Exported");
+        let name = &token.word.txt;
+        if name.starts_with(|c: char| c.is_ascii_digit()) || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            eprintln!("{}: ERROR: Exported function name '{}' is not a valid C identifier", token.word, name);
+            std::process::exit(1);
+        }
+        ctx.functions.get_mut(name).unwrap().export = true;
+        return Some(token);
+    }
     if "var" == word.txt {
         if words.is_empty() {
             eprintln!("{}: ERROR: Encountered incomplete function signature", word);
@@ -340,15 +425,14 @@ fn parse_word_into_token(ctx: &mut ParserContext, words: &mut Vec<lexer::Word>,
     }
     if !ctx.var_stack.is_empty() {
         let mut next = ctx.var_stack.clone();
-        while !next.is_empty() {
-            let current_id = next.pop().unwrap();
-            if let Some(vars) = ctx.vars.get(&current_id) {
-                if let Some(found) = vars.iter().find(|x| word.txt.eq(*x)) {
-                    return Some(Token {
-                        word,
-                        op: Op::VarRef(found.clone()),
-                    });
-                }
+        while let Some(current_id) = next.pop() {
+            if let Some(vars) = ctx.vars.get(&current_id)
+                && let Some(found) = vars.iter().find(|x| word.txt.eq(*x))
+            {
+                return Some(Token {
+                    word,
+                    op: Op::VarRef(found.clone()),
+                });
             }
         }
     }
@@ -359,6 +443,8 @@ fn parse_word_into_token(ctx: &mut ParserContext, words: &mut Vec<lexer::Word>,
 fn get_intrinsic_by_word(word: &str) -> Option<Intrinsic> {
     match word {
         "dump" => Some(Intrinsic::Dump),
+        "puts" => Some(Intrinsic::Puts),
+        "eputs" => Some(Intrinsic::EPuts),
         "drop" => Some(Intrinsic::Drop),
         "dup" => Some(Intrinsic::Dup),
         "over" => Some(Intrinsic::Over),
@@ -368,9 +454,12 @@ fn get_intrinsic_by_word(word: &str) -> Option<Intrinsic> {
         "-" => Some(Intrinsic::Subtract),
         "*" => Some(Intrinsic::Multiply),
         "/" => Some(Intrinsic::Divide),
+        "s/" => Some(Intrinsic::DivideSigned),
         "%" => Some(Intrinsic::Modulo),
+        "s%" => Some(Intrinsic::ModuloSigned),
         "<<" => Some(Intrinsic::ShiftLeft),
         ">>" => Some(Intrinsic::ShiftRight),
+        "s>>" => Some(Intrinsic::ShiftRightSigned),
         "&" => Some(Intrinsic::BitAnd),
         "|" => Some(Intrinsic::BitOr),
         "^" => Some(Intrinsic::BitXor),
@@ -380,6 +469,13 @@ fn get_intrinsic_by_word(word: &str) -> Option<Intrinsic> {
         ">" => Some(Intrinsic::Greater),
         "<=" => Some(Intrinsic::LessOrEqual),
         ">=" => Some(Intrinsic::GreaterOrEqual),
+        "s<" => Some(Intrinsic::LessSigned),
+        "s>" => Some(Intrinsic::GreaterSigned),
+        "s<=" => Some(Intrinsic::LessOrEqualSigned),
+        "s>=" => Some(Intrinsic::GreaterOrEqualSigned),
+        "cast(int)" => Some(Intrinsic::CastInt),
+        "cast(sint)" => Some(Intrinsic::CastSInt),
+        "cast(ptr)" => Some(Intrinsic::CastPtr),
         "store8" | "store" => Some(Intrinsic::Store8),
         "store16" => Some(Intrinsic::Store16),
         "store32" => Some(Intrinsic::Store32),
@@ -388,10 +484,33 @@ fn get_intrinsic_by_word(word: &str) -> Option<Intrinsic> {
         "load16" => Some(Intrinsic::Load16),
         "load32" => Some(Intrinsic::Load32),
         "load64" => Some(Intrinsic::Load64),
+        "exit" => Some(Intrinsic::Exit),
+        "argc" => Some(Intrinsic::Argc),
+        "argv" => Some(Intrinsic::Argv),
+        "envp" => Some(Intrinsic::Envp),
+        "syscall0" => Some(Intrinsic::Syscall0),
+        "syscall1" => Some(Intrinsic::Syscall1),
+        "syscall2" => Some(Intrinsic::Syscall2),
+        "syscall3" => Some(Intrinsic::Syscall3),
+        "syscall4" => Some(Intrinsic::Syscall4),
+        "syscall5" => Some(Intrinsic::Syscall5),
+        "syscall6" => Some(Intrinsic::Syscall6),
         _ => None,
     }
 }
 
+pub fn get_syscall_arg_count(intrinsic: &Intrinsic) -> usize {
+    match intrinsic {
+        Intrinsic::Syscall1 => 1,
+        Intrinsic::Syscall2 => 2,
+        Intrinsic::Syscall3 => 3,
+        Intrinsic::Syscall4 => 4,
+        Intrinsic::Syscall5 => 5,
+        Intrinsic::Syscall6 => 6,
+        _ => 0,
+    }
+}
+
 fn get_operation_by_word(word: &str) -> Option<Op> {
     match word {
         "end" => Some(Op::End),
@@ -412,7 +531,7 @@ fn parse_function(ctx: &mut ParserContext, words: &mut Vec<lexer::Word>, functio
     let mut parts: Vec<lexer::Word> = vec![];
     let mut buffer: String = String::from("");
     let mut ptr: usize = 0;
-    'MainLoop: while !words.is_empty() {
+    'MainLoop: loop {
         let txt = next_word.txt.clone();
         let mut i = 0;
         while i < txt.len() {
@@ -454,12 +573,16 @@ fn parse_function(ctx: &mut ParserContext, words: &mut Vec<lexer::Word>, functio
                 file: next_word.file.clone(),
                 row: next_word.row,
                 col: next_word.col,
-                txt: String::from(buffer),
+                txt: buffer,
             });
             buffer = String::from("");
             ptr = 0;
         }
-        next_word = words.pop().unwrap();
+        //External functions may be declared last, so the closing parenthesis can be in the very last word
+        next_word = words.pop().unwrap_or_else(|| {
+            eprintln!("{}: ERROR: Encountered incomplete function signature", function_word);
+            std::process::exit(1);
+        });
     }
     let func_name_word = parts.first().unwrap().clone();
     parts.remove(0);
@@ -474,9 +597,9 @@ fn parse_function(ctx: &mut ParserContext, words: &mut Vec<lexer::Word>, functio
             match data_type {
                 Some(typ) => {
                     if input {
-                        ins.push(TypedPos { word: part, typ: typ.clone() });
+                        ins.push(TypedPos { word: part, typ });
                     } else {
-                        outs.push(TypedPos { word: part, typ: typ.clone() });
+                        outs.push(TypedPos { word: part, typ });
                     }
                 }
                 None => {
@@ -491,7 +614,7 @@ fn parse_function(ctx: &mut ParserContext, words: &mut Vec<lexer::Word>, functio
             }
         }
     }
-    ctx.functions.insert(func_name_word.txt.clone(), FunctionDef { ins, outs });
+    ctx.functions.insert(func_name_word.txt.clone(), FunctionDef { ins, outs, export: false });
     Token {
         word: lexer::Word {
             file: function_word.file.clone(),
@@ -503,6 +626,17 @@ fn parse_function(ctx: &mut ParserContext, words: &mut Vec<lexer::Word>, functio
     }
 }
 
+fn check_c_signature(ctx: &ParserContext, function_word: &lexer::Word, kind: &str) {
+    let def = ctx.functions.get(&function_word.txt).unwrap();
+    if def.ins.len() > C_MAX_INS || def.outs.len() > 1 {
+        eprintln!(
+            "{}: ERROR: {} functions can take at most {} inputs and return at most 1 output",
+            function_word, kind, C_MAX_INS
+        );
+        std::process::exit(1);
+    }
+}
+
 fn parse_vars(ctx: &mut ParserContext, words: &mut Vec<lexer::Word>, var_word: &lexer::Word) -> Token {
     let mut next_word = words.pop().unwrap();
     if next_word.txt.contains("\"") || next_word.txt.contains("\'") {
@@ -535,7 +669,7 @@ fn parse_vars(ctx: &mut ParserContext, words: &mut Vec<lexer::Word>, var_word: &
             i += 1;
         }
         if ptr > 0 {
-            parts.push(String::from(buffer));
+            parts.push(buffer);
             buffer = String::from("");
             ptr = 0;
         }
@@ -0,0 +1,704 @@
+//Minimal WebAssembly module representation used by the wasm backend: it is written as text, encoded
+//into the binary format and validated structurally before anything is written to disk.
+
+use std::collections::HashSet;
+use std::fmt::{Display, Formatter};
+
+pub const PAGE_SIZE: usize = 65536;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ValType {
+    I32,
+    I64,
+}
+
+/// Operators without immediates.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Op {
+    I32Add,
+    I32Sub,
+    I32Eqz,
+    I32WrapI64,
+    I64ExtendI32U,
+    I64Eqz,
+    I64Eq,
+    I64Ne,
+    I64LtS,
+    I64LtU,
+    I64GtS,
+    I64GtU,
+    I64LeS,
+    I64LeU,
+    I64GeS,
+    I64GeU,
+    I64Add,
+    I64Sub,
+    I64Mul,
+    I64DivS,
+    I64DivU,
+    I64RemS,
+    I64RemU,
+    I64And,
+    I64Or,
+    I64Xor,
+    I64Shl,
+    I64ShrS,
+    I64ShrU,
+}
+
+/// Memory access operators, all of them take a memory offset.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MemOp {
+    I64Load,
+    I64Load8U,
+    I64Load16U,
+    I64Load32U,
+    I64Store,
+    I64Store8,
+    I64Store16,
+    I64Store32,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Instr {
+    Comment(String),
+    Block,
+    Loop,
+    If(Option<ValType>),
+    Else,
+    End,
+    Br(u32),
+    BrTable(Vec<u32>, u32),
+    Return,
+    Call(u32),
+    LocalGet(u32),
+    LocalSet(u32),
+    LocalTee(u32),
+    Mem(MemOp, u32),
+    I32Const(i32),
+    I64Const(i64),
+    Op(Op),
+}
+
+pub struct FuncType {
+    pub params: Vec<ValType>,
+    pub results: Vec<ValType>,
+}
+
+pub struct Import {
+    pub module: String,
+    pub name: String,
+    pub typ: u32,
+}
+
+pub struct Func {
+    pub typ: u32,
+    pub locals: Vec<ValType>,
+    pub body: Vec<Instr>,
+}
+
+/// A module with a single memory, exported as `memory`, and exported functions.
+pub struct Module {
+    pub types: Vec<FuncType>,
+    pub imports: Vec<Import>,
+    pub funcs: Vec<Func>,
+    pub memory_pages: u32,
+    pub exports: Vec<(String, u32)>,
+    pub data: Vec<(u32, Vec<u8>)>,
+}
+
+impl ValType {
+    fn code(&self) -> u8 {
+        match self {
+            ValType::I32 => 0x7f,
+            ValType::I64 => 0x7e,
+        }
+    }
+}
+
+impl Display for ValType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let txt = match self {
+            ValType::I32 => "i32",
+            ValType::I64 => "i64",
+        };
+        write!(f, "{}", txt)
+    }
+}
+
+const OPS: [(Op, u8, &str); 29] = [
+    (Op::I32Add, 0x6a, "i32.add"),
+    (Op::I32Sub, 0x6b, "i32.sub"),
+    (Op::I32Eqz, 0x45, "i32.eqz"),
+    (Op::I32WrapI64, 0xa7, "i32.wrap_i64"),
+    (Op::I64ExtendI32U, 0xad, "i64.extend_i32_u"),
+    (Op::I64Eqz, 0x50, "i64.eqz"),
+    (Op::I64Eq, 0x51, "i64.eq"),
+    (Op::I64Ne, 0x52, "i64.ne"),
+    (Op::I64LtS, 0x53, "i64.lt_s"),
+    (Op::I64LtU, 0x54, "i64.lt_u"),
+    (Op::I64GtS, 0x55, "i64.gt_s"),
+    (Op::I64GtU, 0x56, "i64.gt_u"),
+    (Op::I64LeS, 0x57, "i64.le_s"),
+    (Op::I64LeU, 0x58, "i64.le_u"),
+    (Op::I64GeS, 0x59, "i64.ge_s"),
+    (Op::I64GeU, 0x5a, "i64.ge_u"),
+    (Op::I64Add, 0x7c, "i64.add"),
+    (Op::I64Sub, 0x7d, "i64.sub"),
+    (Op::I64Mul, 0x7e, "i64.mul"),
+    (Op::I64DivS, 0x7f, "i64.div_s"),
+    (Op::I64DivU, 0x80, "i64.div_u"),
+    (Op::I64RemS, 0x81, "i64.rem_s"),
+    (Op::I64RemU, 0x82, "i64.rem_u"),
+    (Op::I64And, 0x83, "i64.and"),
+    (Op::I64Or, 0x84, "i64.or"),
+    (Op::I64Xor, 0x85, "i64.xor"),
+    (Op::I64Shl, 0x86, "i64.shl"),
+    (Op::I64ShrS, 0x87, "i64.shr_s"),
+    (Op::I64ShrU, 0x88, "i64.shr_u"),
+];
+
+//Opcode, name and natural alignment as a power of two
+const MEM_OPS: [(MemOp, u8, &str, u32); 8] = [
+    (MemOp::I64Load, 0x29, "i64.load", 3),
+    (MemOp::I64Load8U, 0x31, "i64.load8_u", 0),
+    (MemOp::I64Load16U, 0x33, "i64.load16_u", 1),
+    (MemOp::I64Load32U, 0x35, "i64.load32_u", 2),
+    (MemOp::I64Store, 0x37, "i64.store", 3),
+    (MemOp::I64Store8, 0x3c, "i64.store8", 0),
+    (MemOp::I64Store16, 0x3d, "i64.store16", 1),
+    (MemOp::I64Store32, 0x3e, "i64.store32", 2),
+];
+
+impl Op {
+    fn info(&self) -> (u8, &'static str) {
+        let (_, code, name) = OPS.iter().find(|(op, _, _)| op == self).unwrap();
+        (*code, name)
+    }
+}
+
+impl MemOp {
+    fn info(&self) -> (u8, &'static str, u32) {
+        let (_, code, name, align) = MEM_OPS.iter().find(|(op, _, _, _)| op == self).unwrap();
+        (*code, name, *align)
+    }
+}
+
+impl Display for Instr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Instr::Comment(txt) => write!(f, ";; {}", txt),
+            Instr::Block => write!(f, "block"),
+            Instr::Loop => write!(f, "loop"),
+            Instr::If(None) => write!(f, "if"),
+            Instr::If(Some(typ)) => write!(f, "if (result {})", typ),
+            Instr::Else => write!(f, "else"),
+            Instr::End => write!(f, "end"),
+            Instr::Br(depth) => write!(f, "br {}", depth),
+            Instr::BrTable(depths, default) => {
+                let depths: Vec<String> = depths.iter().map(|x| x.to_string()).collect();
+                write!(f, "br_table {} {}", depths.join(" "), default)
+            }
+            Instr::Return => write!(f, "return"),
+            Instr::Call(index) => write!(f, "call {}", index),
+            Instr::LocalGet(index) => write!(f, "local.get {}", index),
+            Instr::LocalSet(index) => write!(f, "local.set {}", index),
+            Instr::LocalTee(index) => write!(f, "local.tee {}", index),
+            Instr::Mem(op, 0) => write!(f, "{}", op.info().1),
+            Instr::Mem(op, offset) => write!(f, "{} offset={}", op.info().1, offset),
+            Instr::I32Const(val) => write!(f, "i32.const {}", val),
+            Instr::I64Const(val) => write!(f, "i64.const {}", val),
+            Instr::Op(op) => write!(f, "{}", op.info().1),
+        }
+    }
+}
+
+impl Module {
+    /// Formats the module in the WebAssembly text format.
+    pub fn to_wat(&self) -> String {
+        let mut out: Vec<String> = vec!["(module".to_string()];
+        let sig = |typ: &FuncType| {
+            let mut txt = String::new();
+            for param in &typ.params {
+                txt.push_str(&format!(" (param {})", param));
+            }
+            for result in &typ.results {
+                txt.push_str(&format!(" (result {})", result));
+            }
+            txt
+        };
+        for typ in &self.types {
+            out.push(format!("  (type (func{}))", sig(typ)));
+        }
+        for import in &self.imports {
+            out.push(format!("  (import {:?} {:?} (func (type {})))", import.module, import.name, import.typ));
+        }
+        out.push(format!("  (memory {})", self.memory_pages));
+        out.push("  (export \"memory\" (memory 0))".to_string());
+        for (name, index) in &self.exports {
+            out.push(format!("  (export {:?} (func {}))", name, index));
+        }
+        for func in &self.funcs {
+            out.push(format!("  (func (type {})", func.typ));
+            for local in &func.locals {
+                out.push(format!("    (local {})", local));
+            }
+            let mut indent = 2;
+            for instr in &func.body {
+                if matches!(instr, Instr::End | Instr::Else) {
+                    indent -= 1;
+                }
+                out.push(format!("{}{}", "  ".repeat(indent), instr));
+                if matches!(instr, Instr::Block | Instr::Loop | Instr::If(_) | Instr::Else) {
+                    indent += 1;
+                }
+            }
+            out.push("  )".to_string());
+        }
+        for (offset, bytes) in &self.data {
+            let txt: String = bytes.iter().map(|x| format!("\\{:02x}", x)).collect();
+            out.push(format!("  (data (i32.const {}) \"{}\")", offset, txt));
+        }
+        out.push(")".to_string());
+        out.push(String::new());
+        out.join("\n")
+    }
+
+    /// Encodes the module in the WebAssembly binary format.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out: Vec<u8> = vec![0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00];
+
+        let mut section: Vec<u8> = vec![];
+        write_u32(&mut section, self.types.len() as u32);
+        for typ in &self.types {
+            section.push(0x60);
+            write_u32(&mut section, typ.params.len() as u32);
+            section.extend(typ.params.iter().map(|x| x.code()));
+            write_u32(&mut section, typ.results.len() as u32);
+            section.extend(typ.results.iter().map(|x| x.code()));
+        }
+        write_section(&mut out, 1, &section);
+
+        let mut section: Vec<u8> = vec![];
+        write_u32(&mut section, self.imports.len() as u32);
+        for import in &self.imports {
+            write_name(&mut section, &import.module);
+            write_name(&mut section, &import.name);
+            section.push(0x00);
+            write_u32(&mut section, import.typ);
+        }
+        write_section(&mut out, 2, &section);
+
+        let mut section: Vec<u8> = vec![];
+        write_u32(&mut section, self.funcs.len() as u32);
+        for func in &self.funcs {
+            write_u32(&mut section, func.typ);
+        }
+        write_section(&mut out, 3, &section);
+
+        let mut section: Vec<u8> = vec![1, 0x00];
+        write_u32(&mut section, self.memory_pages);
+        write_section(&mut out, 5, &section);
+
+        let mut section: Vec<u8> = vec![];
+        write_u32(&mut section, self.exports.len() as u32 + 1);
+        write_name(&mut section, "memory");
+        section.extend_from_slice(&[0x02, 0x00]);
+        for (name, index) in &self.exports {
+            write_name(&mut section, name);
+            section.push(0x00);
+            write_u32(&mut section, *index);
+        }
+        write_section(&mut out, 7, &section);
+
+        let mut section: Vec<u8> = vec![];
+        write_u32(&mut section, self.funcs.len() as u32);
+        for func in &self.funcs {
+            let mut body: Vec<u8> = vec![];
+            write_u32(&mut body, func.locals.len() as u32);
+            for local in &func.locals {
+                write_u32(&mut body, 1);
+                body.push(local.code());
+            }
+            for instr in &func.body {
+                encode_instr(&mut body, instr);
+            }
+            body.push(0x0b);
+            write_u32(&mut section, body.len() as u32);
+            section.extend_from_slice(&body);
+        }
+        write_section(&mut out, 10, &section);
+
+        let mut section: Vec<u8> = vec![];
+        write_u32(&mut section, self.data.len() as u32);
+        for (offset, bytes) in &self.data {
+            section.push(0x00);
+            section.push(0x41);
+            write_i64(&mut section, *offset as i64);
+            section.push(0x0b);
+            write_u32(&mut section, bytes.len() as u32);
+            section.extend_from_slice(bytes);
+        }
+        write_section(&mut out, 11, &section);
+        out
+    }
+}
+
+fn encode_instr(out: &mut Vec<u8>, instr: &Instr) {
+    match instr {
+        Instr::Comment(_) => {}
+        Instr::Block => out.extend_from_slice(&[0x02, 0x40]),
+        Instr::Loop => out.extend_from_slice(&[0x03, 0x40]),
+        Instr::If(None) => out.extend_from_slice(&[0x04, 0x40]),
+        Instr::If(Some(typ)) => out.extend_from_slice(&[0x04, typ.code()]),
+        Instr::Else => out.push(0x05),
+        Instr::End => out.push(0x0b),
+        Instr::Br(depth) => {
+            out.push(0x0c);
+            write_u32(out, *depth);
+        }
+        Instr::BrTable(depths, default) => {
+            out.push(0x0e);
+            write_u32(out, depths.len() as u32);
+            for depth in depths {
+                write_u32(out, *depth);
+            }
+            write_u32(out, *default);
+        }
+        Instr::Return => out.push(0x0f),
+        Instr::Call(index) => {
+            out.push(0x10);
+            write_u32(out, *index);
+        }
+        Instr::LocalGet(index) | Instr::LocalSet(index) | Instr::LocalTee(index) => {
+            out.push(match instr {
+                Instr::LocalGet(_) => 0x20,
+                Instr::LocalSet(_) => 0x21,
+                _ => 0x22,
+            });
+            write_u32(out, *index);
+        }
+        Instr::Mem(op, offset) => {
+            let (code, _, align) = op.info();
+            out.push(code);
+            write_u32(out, align);
+            write_u32(out, *offset);
+        }
+        Instr::I32Const(val) => {
+            out.push(0x41);
+            write_i64(out, *val as i64);
+        }
+        Instr::I64Const(val) => {
+            out.push(0x42);
+            write_i64(out, *val);
+        }
+        Instr::Op(op) => out.push(op.info().0),
+    }
+}
+
+fn write_u32(out: &mut Vec<u8>, mut val: u32) {
+    loop {
+        let byte = (val & 0x7f) as u8;
+        val >>= 7;
+        if val == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn write_i64(out: &mut Vec<u8>, mut val: i64) {
+    loop {
+        let byte = (val & 0x7f) as u8;
+        val >>= 7;
+        if (val == 0 && byte & 0x40 == 0) || (val == -1 && byte & 0x40 != 0) {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn write_name(out: &mut Vec<u8>, name: &str) {
+    write_u32(out, name.len() as u32);
+    out.extend_from_slice(name.as_bytes());
+}
+
+fn write_section(out: &mut Vec<u8>, id: u8, content: &[u8]) {
+    out.push(id);
+    write_u32(out, content.len() as u32);
+    out.extend_from_slice(content);
+}
+
+/// Reads a binary module, checking its structure: section order and sizes, index spaces,
+/// block nesting and branch depths, memory accesses and data segments staying inside the memory.
+pub fn validate(bytes: &[u8]) -> Result<(), String> {
+    let mut reader = Reader { bytes, pos: 0 };
+    if reader.take(8)? != [0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00] {
+        return Err("Invalid magic number or version".to_string());
+    }
+    let mut last_id = 0;
+    let mut types: Vec<(usize, usize)> = vec![];
+    let mut func_types: Vec<u32> = vec![];
+    let mut import_count = 0;
+    let mut memory_pages: Option<u32> = None;
+    let mut code_count = 0;
+    while !reader.done() {
+        let id = reader.byte()?;
+        let size = reader.u32()? as usize;
+        let end = reader.pos + size;
+        if end > bytes.len() {
+            return Err(format!("Section {} is out of bounds", id));
+        }
+        if id != 0 && id <= last_id {
+            return Err(format!("Section {} is out of order", id));
+        }
+        if id != 0 {
+            last_id = id;
+        }
+        match id {
+            0 => reader.pos = end,
+            1 => {
+                for _ in 0..reader.u32()? {
+                    if reader.byte()? != 0x60 {
+                        return Err("Invalid function type".to_string());
+                    }
+                    let params = reader.val_types()?;
+                    let results = reader.val_types()?;
+                    types.push((params, results));
+                }
+            }
+            2 => {
+                for _ in 0..reader.u32()? {
+                    reader.name()?;
+                    reader.name()?;
+                    if reader.byte()? != 0x00 {
+                        return Err("Only function imports are supported".to_string());
+                    }
+                    let typ = reader.u32()?;
+                    if typ as usize >= types.len() {
+                        return Err(format!("Import uses unknown type {}", typ));
+                    }
+                    func_types.push(typ);
+                    import_count += 1;
+                }
+            }
+            3 => {
+                for _ in 0..reader.u32()? {
+                    let typ = reader.u32()?;
+                    if typ as usize >= types.len() {
+                        return Err(format!("Function uses unknown type {}", typ));
+                    }
+                    func_types.push(typ);
+                }
+            }
+            5 => {
+                if reader.u32()? != 1 {
+                    return Err("Exactly one memory is required".to_string());
+                }
+                if reader.byte()? != 0x00 {
+                    return Err("Memories with a maximum are not supported".to_string());
+                }
+                memory_pages = Some(reader.u32()?);
+            }
+            7 => {
+                let mut names: HashSet<String> = HashSet::new();
+                for _ in 0..reader.u32()? {
+                    let name = reader.name()?;
+                    if !names.insert(name.clone()) {
+                        return Err(format!("Duplicate export '{}'", name));
+                    }
+                    let kind = reader.byte()?;
+                    let index = reader.u32()?;
+                    let valid = match kind {
+                        0x00 => (index as usize) < func_types.len(),
+                        0x02 => index == 0 && memory_pages.is_some(),
+                        _ => false,
+                    };
+                    if !valid {
+                        return Err(format!("Export '{}' refers to an unknown item", name));
+                    }
+                }
+            }
+            10 => {
+                code_count = reader.u32()? as usize;
+                if code_count != func_types.len() - import_count {
+                    return Err("Function and code section sizes differ".to_string());
+                }
+                for index in 0..code_count {
+                    let body_size = reader.u32()? as usize;
+                    let body_end = reader.pos + body_size;
+                    let (params, _) = types[func_types[import_count + index] as usize];
+                    let mut local_count = params;
+                    for _ in 0..reader.u32()? {
+                        let count = reader.u32()? as usize;
+                        reader.val_type()?;
+                        local_count += count;
+                    }
+                    reader.validate_body(local_count, func_types.len(), memory_pages.is_some())?;
+                    if reader.pos != body_end {
+                        return Err(format!("Function {} does not match its size", import_count + index));
+                    }
+                }
+            }
+            11 => {
+                let pages = memory_pages.ok_or("Data segments require a memory")?;
+                for _ in 0..reader.u32()? {
+                    if reader.byte()? != 0x00 || reader.byte()? != 0x41 {
+                        return Err("Only active data segments with a constant offset are supported".to_string());
+                    }
+                    let offset = reader.i64()?;
+                    if reader.byte()? != 0x0b {
+                        return Err("Invalid data segment offset".to_string());
+                    }
+                    let len = reader.u32()? as usize;
+                    reader.take(len)?;
+                    if offset < 0 || offset as usize + len > pages as usize * PAGE_SIZE {
+                        return Err("Data segment does not fit in the memory".to_string());
+                    }
+                }
+            }
+            _ => return Err(format!("Unsupported section {}", id)),
+        }
+        if reader.pos != end {
+            return Err(format!("Section {} does not match its size", id));
+        }
+    }
+    if code_count != func_types.len() - import_count {
+        return Err("Missing code section".to_string());
+    }
+    Ok(())
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn done(&self) -> bool {
+        self.pos >= self.bytes.len()
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        let byte = *self.bytes.get(self.pos).ok_or("Unexpected end of module")?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    fn take(&mut self, len: usize) -> Result<&[u8], String> {
+        if self.pos + len > self.bytes.len() {
+            return Err("Unexpected end of module".to_string());
+        }
+        self.pos += len;
+        Ok(&self.bytes[self.pos - len..self.pos])
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        let mut val: u64 = 0;
+        for shift in (0..35).step_by(7) {
+            let byte = self.byte()?;
+            val |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return u32::try_from(val).map_err(|_| "Integer is too large".to_string());
+            }
+        }
+        Err("Integer is too long".to_string())
+    }
+
+    fn i64(&mut self) -> Result<i64, String> {
+        let mut val: i64 = 0;
+        for shift in (0..70).step_by(7) {
+            let byte = self.byte()?;
+            val |= ((byte & 0x7f) as i64) << shift;
+            if byte & 0x80 == 0 {
+                if shift < 57 && byte & 0x40 != 0 {
+                    val |= -1 << (shift + 7);
+                }
+                return Ok(val);
+            }
+        }
+        Err("Integer is too long".to_string())
+    }
+
+    fn name(&mut self) -> Result<String, String> {
+        let len = self.u32()? as usize;
+        let bytes = self.take(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| "Invalid name".to_string())
+    }
+
+    fn val_type(&mut self) -> Result<(), String> {
+        match self.byte()? {
+            0x7f | 0x7e => Ok(()),
+            x => Err(format!("Unsupported value type {:#x}", x)),
+        }
+    }
+
+    fn val_types(&mut self) -> Result<usize, String> {
+        let count = self.u32()? as usize;
+        for _ in 0..count {
+            self.val_type()?;
+        }
+        Ok(count)
+    }
+
+    fn validate_body(&mut self, local_count: usize, func_count: usize, has_memory: bool) -> Result<(), String> {
+        //The function body itself is the outermost block
+        let mut depth: u32 = 1;
+        while depth > 0 {
+            let opcode = self.byte()?;
+            match opcode {
+                0x02..=0x04 => match self.byte()? {
+                    0x40 | 0x7f | 0x7e => depth += 1,
+                    x => return Err(format!("Unsupported block type {:#x}", x)),
+                },
+                0x05 => {
+                    if depth < 2 {
+                        return Err("Unexpected else".to_string());
+                    }
+                }
+                0x0b => depth -= 1,
+                0x0c | 0x0d => {
+                    if self.u32()? >= depth {
+                        return Err("Branch depth is out of range".to_string());
+                    }
+                }
+                0x0e => {
+                    for _ in 0..self.u32()? + 1 {
+                        if self.u32()? >= depth {
+                            return Err("Branch table depth is out of range".to_string());
+                        }
+                    }
+                }
+                0x10 => {
+                    if self.u32()? as usize >= func_count {
+                        return Err("Call to an unknown function".to_string());
+                    }
+                }
+                0x20..=0x22 => {
+                    if self.u32()? as usize >= local_count {
+                        return Err("Access to an unknown local".to_string());
+                    }
+                }
+                _ if MEM_OPS.iter().any(|(_, code, _, _)| *code == opcode) => {
+                    if !has_memory {
+                        return Err("Memory access without a memory".to_string());
+                    }
+                    let (_, _, _, natural) = MEM_OPS.iter().find(|(_, code, _, _)| *code == opcode).unwrap();
+                    if self.u32()? > *natural {
+                        return Err("Alignment is larger than the natural alignment".to_string());
+                    }
+                    self.u32()?;
+                }
+                0x41 | 0x42 => {
+                    self.i64()?;
+                }
+                0x00 | 0x0f | 0x1a => {}
+                _ if OPS.iter().any(|(_, code, _)| *code == opcode) => {}
+                _ => return Err(format!("Unsupported opcode {:#x}", opcode)),
+            }
+        }
+        Ok(())
+    }
+}
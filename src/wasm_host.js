@@ -0,0 +1,67 @@
+// Host interface for Feylon programs compiled with --use=wasm.
+// In node, run `node program.js` next to `program.wasm`. In browsers, call `runFeylon(bytes, stdout, stderr)`
+// where stdout and stderr receive strings, it resolves to the exit code of the program.
+
+class FeylonExit {
+    constructor(code) {
+        this.code = code;
+    }
+}
+
+async function runFeylon(bytes, stdout, stderr) {
+    const decoder = new TextDecoder();
+    let memory = null;
+    let out = "";
+    const flush = () => {
+        if (out.length > 0) {
+            stdout(out);
+            out = "";
+        }
+    };
+    const env = {
+        dump: (value) => {
+            out += BigInt.asUintN(64, value).toString() + "\n";
+        },
+        write: (fd, ptr, len) => {
+            const text = decoder.decode(new Uint8Array(memory.buffer, ptr, len));
+            if (fd === 1) {
+                out += text;
+            } else {
+                flush();
+                stderr(text);
+            }
+        },
+        exit: (code) => {
+            throw new FeylonExit(code);
+        },
+    };
+    const { instance } = await WebAssembly.instantiate(bytes, { env });
+    memory = instance.exports.memory;
+    try {
+        instance.exports.main();
+        return 0;
+    } catch (err) {
+        if (err instanceof FeylonExit) {
+            return err.code;
+        }
+        throw err;
+    } finally {
+        flush();
+    }
+}
+
+if (typeof module !== "undefined" && require.main === module) {
+    const fs = require("fs");
+    const bytes = fs.readFileSync(__filename.replace(/\.js$/, ".wasm"));
+    runFeylon(
+        bytes,
+        (text) => fs.writeSync(1, text),
+        (text) => fs.writeSync(2, text),
+    ).then(
+        (code) => process.exit(code),
+        (err) => {
+            fs.writeSync(2, `ERROR: ${err}\n`);
+            process.exit(1);
+        },
+    );
+}
@@ -0,0 +1,729 @@
+use crate::compiler_x86_64::Os;
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+
+//Registers are listed in the order of their encoding
+#[allow(dead_code)]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Reg {
+    Rax,
+    Rcx,
+    Rdx,
+    Rbx,
+    Rsp,
+    Rbp,
+    Rsi,
+    Rdi,
+    R8,
+    R9,
+    R10,
+    R11,
+    R12,
+    R13,
+    R14,
+    R15,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Size {
+    Byte,
+    Word,
+    Dword,
+    Qword,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum AluOp {
+    Add,
+    Or,
+    And,
+    Sub,
+    Xor,
+    Cmp,
+    Test,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ShiftOp {
+    Shl,
+    Shr,
+    Sar,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum UnaryOp {
+    Mul,
+    Div,
+    IDiv,
+    Neg,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Cond {
+    B,
+    AE,
+    E,
+    NE,
+    BE,
+    A,
+    L,
+    GE,
+    LE,
+    G,
+}
+
+/// A memory operand `[base + index * scale + disp]` or `[symbol + disp]`.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Mem {
+    pub base: Option<Reg>,
+    pub index: Option<(Reg, u8)>,
+    pub disp: i32,
+    pub symbol: Option<String>,
+}
+
+/// The subset of x86-64 used by the native backends, it can be written as NASM source or encoded into machine code.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Asm {
+    Label(String),
+    Comment(String),
+    Mov(Reg, Reg),
+    MovImm(Reg, i64),
+    LeaSymbol(Reg, String),
+    Load(Size, Reg, Mem),
+    Store(Size, Mem, Reg),
+    StoreImm(Size, Mem, i32),
+    Lea(Reg, Mem),
+    Push(Reg),
+    PushImm(i32),
+    Pop(Reg),
+    Alu(AluOp, Reg, Reg),
+    AluImm(AluOp, Reg, i32),
+    AluLoad(AluOp, Reg, Mem),
+    AluStore(AluOp, Mem, Reg),
+    Shift(ShiftOp, Reg),
+    ShiftImm(ShiftOp, Reg, u8),
+    Unary(UnaryOp, Reg),
+    /// Unsigned division of `edx:eax` by the low 32 bits of the register, which is much faster than the 64 bit form.
+    Div32(Reg),
+    Cmov(Cond, Reg, Reg),
+    Cqo,
+    Jmp(String),
+    Jcc(Cond, String),
+    Call(String),
+    Ret,
+    Syscall,
+    RepMovsb,
+}
+
+/// A program for the native backends: exported and imported symbols, zero initialised data, read-only data and code.
+/// Libraries have no entry point, only the functions they export.
+pub struct Program {
+    pub entry: Option<String>,
+    pub exports: Vec<String>,
+    pub externs: Vec<String>,
+    pub bss: Vec<(String, usize)>,
+    pub rodata: Vec<(String, Vec<u8>)>,
+    pub text: Vec<Asm>,
+}
+
+impl Mem {
+    pub fn base(base: Reg) -> Self {
+        Mem::base_disp(base, 0)
+    }
+
+    pub fn base_disp(base: Reg, disp: i32) -> Self {
+        Mem {
+            base: Some(base),
+            index: None,
+            disp,
+            symbol: None,
+        }
+    }
+
+    pub fn base_index(base: Reg, index: Reg, scale: u8, disp: i32) -> Self {
+        Mem {
+            base: Some(base),
+            index: Some((index, scale)),
+            disp,
+            symbol: None,
+        }
+    }
+
+    pub fn symbol(symbol: &str) -> Self {
+        Mem {
+            base: None,
+            index: None,
+            disp: 0,
+            symbol: Some(symbol.to_string()),
+        }
+    }
+
+    /// Formats the operand for GNU as, which needs symbols to be addressed relative to rip explicitly.
+    fn to_gas(&self) -> String {
+        match self.symbol {
+            Some(_) => format!("[rip + {}", &self.to_string()[1..]),
+            None => self.to_string(),
+        }
+    }
+}
+
+impl Reg {
+    fn code(&self) -> u8 {
+        *self as u8
+    }
+
+    fn name(&self, size: Size) -> String {
+        const NAMES: [&str; 8] = ["ax", "cx", "dx", "bx", "sp", "bp", "si", "di"];
+        let code = self.code() as usize;
+        if code >= 8 {
+            let suffix = match size {
+                Size::Byte => "b",
+                Size::Word => "w",
+                Size::Dword => "d",
+                Size::Qword => "",
+            };
+            return format!("r{}{}", code, suffix);
+        }
+        match size {
+            Size::Byte if code < 4 => format!("{}l", &NAMES[code][..1]),
+            Size::Byte => format!("{}l", NAMES[code]),
+            Size::Word => NAMES[code].to_string(),
+            Size::Dword => format!("e{}", NAMES[code]),
+            Size::Qword => format!("r{}", NAMES[code]),
+        }
+    }
+}
+
+impl Display for Reg {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name(Size::Qword))
+    }
+}
+
+impl Display for Size {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let txt = match self {
+            Size::Byte => "byte",
+            Size::Word => "word",
+            Size::Dword => "dword",
+            Size::Qword => "qword",
+        };
+        write!(f, "{}", txt)
+    }
+}
+
+impl Display for AluOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", format!("{:?}", self).to_lowercase())
+    }
+}
+
+impl Display for ShiftOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", format!("{:?}", self).to_lowercase())
+    }
+}
+
+impl Display for UnaryOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", format!("{:?}", self).to_lowercase())
+    }
+}
+
+impl Display for Cond {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", format!("{:?}", self).to_lowercase())
+    }
+}
+
+impl Display for Mem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let mut parts: Vec<String> = vec![];
+        if let Some(symbol) = &self.symbol {
+            parts.push(symbol.clone());
+        }
+        if let Some(base) = self.base {
+            parts.push(base.to_string());
+        }
+        if let Some((index, scale)) = self.index {
+            parts.push(format!("{} * {}", index, scale));
+        }
+        let mut txt = parts.join(" + ");
+        if self.disp > 0 || parts.is_empty() {
+            txt = if parts.is_empty() { self.disp.to_string() } else { format!("{} + {}", txt, self.disp) };
+        } else if self.disp < 0 {
+            txt = format!("{} - {}", txt, -(self.disp as i64));
+        }
+        write!(f, "[{}]", txt)
+    }
+}
+
+/// Formats the instruction using NASM syntax.
+impl Display for Asm {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Asm::Label(name) => write!(f, "{}:", name),
+            Asm::Comment(txt) => write!(f, "    ;{}", txt),
+            Asm::Mov(dst, src) => write!(f, "    mov {}, {}", dst, src),
+            Asm::MovImm(dst, val) => write!(f, "    mov {}, {}", dst, val),
+            Asm::LeaSymbol(dst, symbol) => write!(f, "    lea {}, [{}]", dst, symbol),
+            Asm::Load(Size::Qword, dst, mem) => write!(f, "    mov {}, {}", dst, mem),
+            Asm::Load(Size::Dword, dst, mem) => write!(f, "    mov {}, {}", dst.name(Size::Dword), mem),
+            Asm::Load(size, dst, mem) => write!(f, "    movzx {}, {} {}", dst, size, mem),
+            Asm::Store(size, mem, src) => write!(f, "    mov {} {}, {}", size, mem, src.name(*size)),
+            Asm::StoreImm(size, mem, val) => write!(f, "    mov {} {}, {}", size, mem, val),
+            Asm::Lea(dst, mem) => write!(f, "    lea {}, {}", dst, mem),
+            Asm::Push(reg) => write!(f, "    push {}", reg),
+            Asm::PushImm(val) => write!(f, "    push qword {}", val),
+            Asm::Pop(reg) => write!(f, "    pop {}", reg),
+            Asm::Alu(op, dst, src) => write!(f, "    {} {}, {}", op, dst, src),
+            Asm::AluImm(op, dst, val) => write!(f, "    {} {}, {}", op, dst, val),
+            Asm::AluLoad(op, dst, mem) => write!(f, "    {} {}, {}", op, dst, mem),
+            Asm::AluStore(op, mem, src) => write!(f, "    {} {}, {}", op, mem, src),
+            Asm::Shift(op, dst) => write!(f, "    {} {}, cl", op, dst),
+            Asm::ShiftImm(op, dst, count) => write!(f, "    {} {}, {}", op, dst, count),
+            Asm::Unary(op, reg) => write!(f, "    {} {}", op, reg),
+            Asm::Div32(reg) => write!(f, "    div {}", reg.name(Size::Dword)),
+            Asm::Cmov(cond, dst, src) => write!(f, "    cmov{} {}, {}", cond, dst, src),
+            Asm::Cqo => write!(f, "    cqo"),
+            Asm::Jmp(label) => write!(f, "    jmp {}", label),
+            Asm::Jcc(cond, label) => write!(f, "    j{} {}", cond, label),
+            Asm::Call(label) => write!(f, "    call {}", label),
+            Asm::Ret => write!(f, "    ret"),
+            Asm::Syscall => write!(f, "    syscall"),
+            Asm::RepMovsb => write!(f, "    rep movsb"),
+        }
+    }
+}
+
+impl Program {
+    /// The symbols visible outside of the program, the entry point followed by the exported functions.
+    pub fn globals(&self) -> impl Iterator<Item = &String> {
+        self.entry.iter().chain(self.exports.iter())
+    }
+
+    /// Formats the program as NASM source for `-felf64` on Linux and `-fwin64` on Windows. On Linux external functions
+    /// are called through the procedure linkage table, like GNU as and the encoder do.
+    pub fn to_nasm(&self, os: Os) -> String {
+        let (rodata_section, plt) = match os {
+            Os::Linux => (".rodata", true),
+            Os::Windows => (".rdata", false),
+        };
+        //Symbols are addressed relative to rip, like the encoder does
+        let mut out: Vec<String> = vec!["BITS 64".to_string(), "DEFAULT REL".to_string()];
+        for name in self.globals() {
+            out.push(format!("global {}", name));
+        }
+        for name in &self.externs {
+            out.push(format!("extern {}", name));
+        }
+        out.push("section .bss".to_string());
+        for (name, size) in &self.bss {
+            if *size == 0 {
+                out.push(format!("    {}:", name));
+            } else {
+                out.push(format!("    {}: resb {}", name, size));
+            }
+        }
+        out.push(format!("section {}", rodata_section));
+        for (name, bytes) in &self.rodata {
+            let bytes: Vec<String> = bytes.iter().map(|x| x.to_string()).collect();
+            out.push(format!("    {}: db {}", name, bytes.join(", ")));
+        }
+        out.push("section .text".to_string());
+        for asm in &self.text {
+            match asm {
+                Asm::Call(label) if plt && self.externs.contains(label) => out.push(format!("    call {} wrt ..plt", label)),
+                _ => out.push(asm.to_string()),
+            }
+        }
+        out.push(String::new());
+        out.join("\n")
+    }
+
+    /// Formats the program as GNU as source, using the Intel syntax so the instructions read like the NASM output.
+    pub fn to_gas(&self) -> String {
+        let mut out: Vec<String> = vec![".intel_syntax noprefix".to_string()];
+        for name in self.globals() {
+            out.push(format!(".globl {}", name));
+        }
+        out.push(".section .bss".to_string());
+        for (name, size) in &self.bss {
+            if *size == 0 {
+                out.push(format!("    {}:", name));
+            } else {
+                out.push(format!("    {}: .zero {}", name, size));
+            }
+        }
+        out.push(".section .rodata".to_string());
+        for (name, bytes) in &self.rodata {
+            let bytes: Vec<String> = bytes.iter().map(|x| x.to_string()).collect();
+            out.push(format!("    {}: .byte {}", name, bytes.join(", ")));
+        }
+        out.push(".text".to_string());
+        let mut scope = "";
+        for asm in &self.text {
+            if let Asm::Label(name) = asm
+                && !name.starts_with('.')
+            {
+                scope = name;
+            }
+            out.push(asm.to_gas(scope));
+        }
+        //Without this note the linker of a C program would make the stack executable
+        out.push(".section .note.GNU-stack,\"\",@progbits".to_string());
+        out.push(String::new());
+        out.join("\n")
+    }
+}
+
+impl Asm {
+    /// Formats the instruction for GNU as with `.intel_syntax noprefix`. GNU as has no local labels,
+    /// so labels starting with a dot are prefixed with `scope`, the last regular label.
+    fn to_gas(&self, scope: &str) -> String {
+        let local = |label: &str| if label.starts_with('.') { format!("{}{}", scope, label) } else { label.to_string() };
+        match self {
+            Asm::Label(name) => format!("{}:", local(name)),
+            Asm::Comment(txt) => format!("    #{}", txt),
+            Asm::LeaSymbol(dst, symbol) => format!("    lea {}, [rip + {}]", dst, symbol),
+            Asm::Load(Size::Qword, dst, mem) => format!("    mov {}, qword ptr {}", dst, mem.to_gas()),
+            Asm::Load(Size::Dword, dst, mem) => format!("    mov {}, dword ptr {}", dst.name(Size::Dword), mem.to_gas()),
+            Asm::Load(size, dst, mem) => format!("    movzx {}, {} ptr {}", dst, size, mem.to_gas()),
+            Asm::Store(size, mem, src) => format!("    mov {} ptr {}, {}", size, mem.to_gas(), src.name(*size)),
+            Asm::StoreImm(size, mem, val) => format!("    mov {} ptr {}, {}", size, mem.to_gas(), val),
+            Asm::Lea(dst, mem) => format!("    lea {}, {}", dst, mem.to_gas()),
+            Asm::AluLoad(op, dst, mem) => format!("    {} {}, qword ptr {}", op, dst, mem.to_gas()),
+            Asm::AluStore(op, mem, src) => format!("    {} qword ptr {}, {}", op, mem.to_gas(), src),
+            Asm::PushImm(val) => format!("    push {}", val),
+            Asm::Jmp(label) => format!("    jmp {}", local(label)),
+            Asm::Jcc(cond, label) => format!("    j{} {}", cond, local(label)),
+            Asm::Call(label) => format!("    call {}", local(label)),
+            _ => self.to_string(),
+        }
+    }
+}
+
+/// A reference to a symbol that can only be filled in once all addresses are known: a 32 bit displacement relative to
+/// the end of the instruction. Symbols are never addressed absolutely, so the code is position independent.
+pub struct Fixup {
+    pub offset: usize,
+    pub symbol: String,
+    pub addend: i64,
+}
+
+/// Machine code of the text section, with the offset of every label and the unresolved symbol references.
+pub struct Assembled {
+    pub code: Vec<u8>,
+    pub labels: HashMap<String, usize>,
+    pub fixups: Vec<Fixup>,
+}
+
+/// Encodes the instructions into machine code. Every instruction referring to a symbol has a fixed size,
+/// so the code does not change when the symbols are resolved later on by `Assembled::link`.
+pub fn assemble(text: &[Asm]) -> Assembled {
+    let mut encoder = Encoder {
+        code: vec![],
+        labels: HashMap::new(),
+        fixups: vec![],
+        scope: String::new(),
+    };
+    for asm in text {
+        encoder.encode(asm);
+    }
+    Assembled {
+        code: encoder.code,
+        labels: encoder.labels,
+        fixups: encoder.fixups,
+    }
+}
+
+impl Assembled {
+    /// Resolves all symbol references, with the code placed at `text_addr` and other symbols at the given addresses.
+    pub fn link(&mut self, text_addr: u64, symbols: &HashMap<String, u64>) -> Result<(), String> {
+        for fixup in &self.fixups {
+            let target = match self.labels.get(&fixup.symbol) {
+                Some(offset) => text_addr + *offset as u64,
+                None => *symbols.get(&fixup.symbol).ok_or(format!("Undefined symbol '{}'", fixup.symbol))?,
+            };
+            let value = target as i64 + fixup.addend - (text_addr + fixup.offset as u64 + 4) as i64;
+            let value = i32::try_from(value).map_err(|_| format!("Symbol '{}' is out of range", fixup.symbol))?;
+            self.code[fixup.offset..fixup.offset + 4].copy_from_slice(&value.to_le_bytes());
+        }
+        Ok(())
+    }
+
+    /// Resolves the references to labels, which do not depend on where the code is placed, for an object file.
+    /// All other references are returned, they are left to the linker.
+    pub fn link_labels(&mut self) -> Vec<Fixup> {
+        let mut unresolved: Vec<Fixup> = vec![];
+        for fixup in std::mem::take(&mut self.fixups) {
+            match self.labels.get(&fixup.symbol) {
+                Some(offset) => {
+                    let value = (*offset as i64 + fixup.addend - (fixup.offset + 4) as i64) as i32;
+                    self.code[fixup.offset..fixup.offset + 4].copy_from_slice(&value.to_le_bytes());
+                }
+                _ => unresolved.push(fixup),
+            }
+        }
+        unresolved
+    }
+}
+
+struct Encoder {
+    code: Vec<u8>,
+    labels: HashMap<String, usize>,
+    fixups: Vec<Fixup>,
+    scope: String,
+}
+
+impl Encoder {
+    //Labels starting with a dot are local to the last regular label, like in NASM
+    fn resolve(&self, label: &str) -> String {
+        if label.starts_with('.') {
+            format!("{}{}", self.scope, label)
+        } else {
+            label.to_string()
+        }
+    }
+
+    fn fixup(&mut self, symbol: &str, addend: i64) {
+        self.fixups.push(Fixup {
+            offset: self.code.len(),
+            symbol: self.resolve(symbol),
+            addend,
+        });
+        self.code.extend_from_slice(&[0; 4]);
+    }
+
+    fn rex(&mut self, w: bool, reg: u8, index: u8, base: u8, force: bool) {
+        let rex = 0x40 | (w as u8) << 3 | (reg >> 3) << 2 | (index >> 3) << 1 | base >> 3;
+        if rex != 0x40 || force {
+            self.code.push(rex);
+        }
+    }
+
+    /// Emits the optional prefixes, REX, opcode, ModRM and everything needed to address `mem`.
+    /// `trailing` is the number of immediate bytes following the operand, required for RIP relative addressing.
+    fn mem_op(&mut self, size: Size, opcode: &[u8], reg: u8, mem: &Mem, trailing: i64) {
+        if size == Size::Word {
+            self.code.push(0x66);
+        }
+        let index = mem.index.map(|(x, _)| x.code()).unwrap_or(0);
+        let base = mem.base.map(|x| x.code()).unwrap_or(0);
+        //Byte access to spl, bpl, sil and dil requires a REX prefix, otherwise it refers to ah, ch, dh and bh
+        self.rex(size == Size::Qword, reg, index, base, size == Size::Byte && (4..8).contains(&reg));
+        self.code.extend_from_slice(opcode);
+        if let Some(symbol) = &mem.symbol {
+            if mem.base.is_some() || mem.index.is_some() {
+                panic!("Symbol addressing with registers is not supported: {}", mem);
+            }
+            self.code.push((reg & 7) << 3 | 0b101);
+            self.fixup(&symbol.clone(), mem.disp as i64 - trailing);
+            return;
+        }
+        let Some(base_reg) = mem.base else {
+            panic!("Memory operand without a base is not supported: {}", mem);
+        };
+        let modifier: u8 = if mem.disp == 0 && base & 7 != 5 {
+            0b00
+        } else if i8::try_from(mem.disp).is_ok() {
+            0b01
+        } else {
+            0b10
+        };
+        match mem.index {
+            Some((index_reg, scale)) => {
+                if index_reg == Reg::Rsp {
+                    panic!("rsp can not be used as index: {}", mem);
+                }
+                let scale_bits = match scale {
+                    1 => 0,
+                    2 => 1,
+                    4 => 2,
+                    8 => 3,
+                    _ => panic!("Invalid scale: {}", mem),
+                };
+                self.code.push(modifier << 6 | (reg & 7) << 3 | 0b100);
+                self.code.push(scale_bits << 6 | (index & 7) << 3 | (base & 7));
+            }
+            None => {
+                self.code.push(modifier << 6 | (reg & 7) << 3 | (base & 7));
+                if base_reg.code() & 7 == 4 {
+                    self.code.push(0x24);
+                }
+            }
+        }
+        match modifier {
+            0b01 => self.code.push(mem.disp as u8),
+            0b10 => self.code.extend_from_slice(&mem.disp.to_le_bytes()),
+            _ => {}
+        }
+    }
+
+    /// Emits an instruction with a register as r/m operand.
+    fn reg_op(&mut self, w: bool, opcode: &[u8], reg: u8, rm: Reg) {
+        self.rex(w, reg, 0, rm.code(), false);
+        self.code.extend_from_slice(opcode);
+        self.code.push(0b11 << 6 | (reg & 7) << 3 | (rm.code() & 7));
+    }
+
+    fn encode(&mut self, asm: &Asm) {
+        match asm {
+            Asm::Label(name) => {
+                if !name.starts_with('.') {
+                    self.scope = name.clone();
+                }
+                let label = self.resolve(name);
+                if self.labels.insert(label.clone(), self.code.len()).is_some() {
+                    panic!("Duplicate label: {}", label);
+                }
+            }
+            Asm::Comment(_) => {}
+            Asm::Mov(dst, src) => self.reg_op(true, &[0x89], src.code(), *dst),
+            Asm::MovImm(dst, val) => {
+                if let Ok(val) = u32::try_from(*val) {
+                    self.rex(false, 0, 0, dst.code(), false);
+                    self.code.push(0xb8 + (dst.code() & 7));
+                    self.code.extend_from_slice(&val.to_le_bytes());
+                } else if let Ok(val) = i32::try_from(*val) {
+                    self.reg_op(true, &[0xc7], 0, *dst);
+                    self.code.extend_from_slice(&val.to_le_bytes());
+                } else {
+                    self.rex(true, 0, 0, dst.code(), false);
+                    self.code.push(0xb8 + (dst.code() & 7));
+                    self.code.extend_from_slice(&val.to_le_bytes());
+                }
+            }
+            Asm::LeaSymbol(dst, symbol) => self.mem_op(Size::Qword, &[0x8d], dst.code(), &Mem::symbol(symbol), 0),
+            Asm::Load(size, dst, mem) => match size {
+                Size::Byte => self.mem_op(Size::Qword, &[0x0f, 0xb6], dst.code(), mem, 0),
+                Size::Word => self.mem_op(Size::Qword, &[0x0f, 0xb7], dst.code(), mem, 0),
+                Size::Dword => self.mem_op(Size::Dword, &[0x8b], dst.code(), mem, 0),
+                Size::Qword => self.mem_op(Size::Qword, &[0x8b], dst.code(), mem, 0),
+            },
+            Asm::Store(size, mem, src) => {
+                let opcode = if *size == Size::Byte { 0x88 } else { 0x89 };
+                self.mem_op(*size, &[opcode], src.code(), mem, 0);
+            }
+            Asm::StoreImm(size, mem, val) => match size {
+                Size::Byte => {
+                    self.mem_op(*size, &[0xc6], 0, mem, 1);
+                    self.code.push(*val as u8);
+                }
+                Size::Word => {
+                    self.mem_op(*size, &[0xc7], 0, mem, 2);
+                    self.code.extend_from_slice(&(*val as i16).to_le_bytes());
+                }
+                _ => {
+                    self.mem_op(*size, &[0xc7], 0, mem, 4);
+                    self.code.extend_from_slice(&val.to_le_bytes());
+                }
+            },
+            Asm::Lea(dst, mem) => self.mem_op(Size::Qword, &[0x8d], dst.code(), mem, 0),
+            Asm::Push(reg) => {
+                self.rex(false, 0, 0, reg.code(), false);
+                self.code.push(0x50 + (reg.code() & 7));
+            }
+            Asm::PushImm(val) => {
+                if let Ok(val) = i8::try_from(*val) {
+                    self.code.push(0x6a);
+                    self.code.push(val as u8);
+                } else {
+                    self.code.push(0x68);
+                    self.code.extend_from_slice(&val.to_le_bytes());
+                }
+            }
+            Asm::Pop(reg) => {
+                self.rex(false, 0, 0, reg.code(), false);
+                self.code.push(0x58 + (reg.code() & 7));
+            }
+            Asm::Alu(op, dst, src) => self.reg_op(true, &[alu_opcode(*op) + 1], src.code(), *dst),
+            Asm::AluImm(op, dst, val) => {
+                let digit = alu_opcode(*op) >> 3;
+                if *op == AluOp::Test {
+                    self.reg_op(true, &[0xf7], 0, *dst);
+                    self.code.extend_from_slice(&val.to_le_bytes());
+                } else if let Ok(val) = i8::try_from(*val) {
+                    self.reg_op(true, &[0x83], digit, *dst);
+                    self.code.push(val as u8);
+                } else {
+                    self.reg_op(true, &[0x81], digit, *dst);
+                    self.code.extend_from_slice(&val.to_le_bytes());
+                }
+            }
+            Asm::AluLoad(op, dst, mem) => {
+                let opcode = if *op == AluOp::Test { 0x85 } else { alu_opcode(*op) + 3 };
+                self.mem_op(Size::Qword, &[opcode], dst.code(), mem, 0);
+            }
+            Asm::AluStore(op, mem, src) => self.mem_op(Size::Qword, &[alu_opcode(*op) + 1], src.code(), mem, 0),
+            Asm::Shift(op, dst) => self.reg_op(true, &[0xd3], shift_digit(*op), *dst),
+            Asm::ShiftImm(op, dst, count) => {
+                self.reg_op(true, &[0xc1], shift_digit(*op), *dst);
+                self.code.push(*count);
+            }
+            Asm::Unary(op, reg) => {
+                let digit = match op {
+                    UnaryOp::Neg => 3,
+                    UnaryOp::Mul => 4,
+                    UnaryOp::Div => 6,
+                    UnaryOp::IDiv => 7,
+                };
+                self.reg_op(true, &[0xf7], digit, *reg);
+            }
+            Asm::Div32(reg) => self.reg_op(false, &[0xf7], 6, *reg),
+            Asm::Cmov(cond, dst, src) => self.reg_op(true, &[0x0f, 0x40 + cond_code(*cond)], dst.code(), *src),
+            Asm::Cqo => self.code.extend_from_slice(&[0x48, 0x99]),
+            Asm::Jmp(label) => {
+                self.code.push(0xe9);
+                self.fixup(label, 0);
+            }
+            Asm::Jcc(cond, label) => {
+                self.code.extend_from_slice(&[0x0f, 0x80 + cond_code(*cond)]);
+                self.fixup(label, 0);
+            }
+            Asm::Call(label) => {
+                self.code.push(0xe8);
+                self.fixup(label, 0);
+            }
+            Asm::Ret => self.code.push(0xc3),
+            Asm::Syscall => self.code.extend_from_slice(&[0x0f, 0x05]),
+            Asm::RepMovsb => self.code.extend_from_slice(&[0xf3, 0xa4]),
+        }
+    }
+}
+
+//The opcode of the `r/m8, r8` form, the digit used by the immediate forms is the opcode divided by 8
+fn alu_opcode(op: AluOp) -> u8 {
+    match op {
+        AluOp::Add => 0x00,
+        AluOp::Or => 0x08,
+        AluOp::And => 0x20,
+        AluOp::Sub => 0x28,
+        AluOp::Xor => 0x30,
+        AluOp::Cmp => 0x38,
+        AluOp::Test => 0x84,
+    }
+}
+
+fn shift_digit(op: ShiftOp) -> u8 {
+    match op {
+        ShiftOp::Shl => 4,
+        ShiftOp::Shr => 5,
+        ShiftOp::Sar => 7,
+    }
+}
+
+fn cond_code(cond: Cond) -> u8 {
+    match cond {
+        Cond::B => 0x2,
+        Cond::AE => 0x3,
+        Cond::E => 0x4,
+        Cond::NE => 0x5,
+        Cond::BE => 0x6,
+        Cond::A => 0x7,
+        Cond::L => 0xc,
+        Cond::GE => 0xd,
+        Cond::LE => 0xe,
+        Cond::G => 0xf,
+    }
+}
@@ -0,0 +1,11 @@
+#include <stdio.h>
+#include "counter.h"
+
+int main(void) {
+    printf("%lu\n", (unsigned long)increment(2));
+    printf("%lu\n", (unsigned long)increment(3));
+    fflush(stdout);
+    greet();
+    printf("%s\n", (char *)name());
+    return 0;
+}